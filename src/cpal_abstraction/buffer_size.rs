@@ -0,0 +1,60 @@
+use std::time::Duration;
+
+use crate::errors::{AudioSettings, Error, PlayError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+/// The size of the buffers a stream asks the device for, smaller buffers lower the latency
+/// but the device has to call back more often and it may crackle if the callbacks don't keep up
+pub enum BufferSize {
+    /// Lets the host choose, which is often around 20-40 ms
+    #[default]
+    Default,
+    /// A buffer of that many frames (one sample per channel), the device needs to support it exactly
+    Fixed(u32),
+    /// The buffer closest to that latency that the device supports
+    Latency(Duration),
+}
+
+impl BufferSize {
+    /// Returns the buffer size in frames to ask the device for, None to let the host choose.
+    /// The supported range is the one of the config the stream is created with
+    pub(crate) fn frames(&self, supported: &cpal::SupportedBufferSize, sample_rate: u32) -> Error<Option<u32>> {
+        let range = match supported {
+            cpal::SupportedBufferSize::Range { min, max } => Some((*min, *max)),
+            cpal::SupportedBufferSize::Unknown => None,
+        };
+
+        match *self {
+            BufferSize::Default => Ok(None),
+            BufferSize::Fixed(frames) => match range {
+                Some((min, max)) if frames < min || frames > max => Err(PlayError::DeviceDoesNotSupportAudioSettings(
+                    vec![AudioSettings::BufferSize(frames)], None)),
+                _ => Ok(Some(frames)),
+            },
+            BufferSize::Latency(latency) => {
+                let frames = (latency.as_secs_f64() * sample_rate as f64).round().max(1.0) as u32;
+
+                match range {
+                    Some((min, max)) => Ok(Some(frames.clamp(min, max))),
+                    None => Ok(Some(frames)),
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buffer_size_is_validated_against_the_device() {
+        let supported = cpal::SupportedBufferSize::Range { min: 64, max: 4096 };
+
+        assert_eq!(BufferSize::Fixed(256).frames(&supported, 48000).unwrap(), Some(256));
+        assert!(BufferSize::Fixed(32).frames(&supported, 48000).is_err());
+        assert_eq!(BufferSize::Latency(Duration::from_millis(1)).frames(&supported, 48000).unwrap(), Some(64));
+        assert_eq!(BufferSize::Latency(Duration::from_millis(5)).frames(&supported, 48000).unwrap(), Some(240));
+        assert_eq!(BufferSize::Default.frames(&supported, 48000).unwrap(), None);
+    }
+}
@@ -0,0 +1,204 @@
+use std::ops::RangeInclusive;
+
+use crate::errors::AudioSettings;
+
+use super::{SampleType, SamplesMetadata};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Whether a stream plays or captures audio
+pub enum StreamDirection {
+    /// The stream captures audio from the device
+    Input,
+    /// The stream plays audio on the device
+    Output,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// A range of stream configs supported by a device
+pub struct ConfigRange {
+    /// The type of the samples, None if ez_audi does not know the format of the device
+    pub sample_type: Option<SampleType>,
+    /// The number of channels
+    pub channels: u16,
+    /// The lowest and highest sample rates supported
+    pub sample_rates: RangeInclusive<u32>,
+    /// The smallest and biggest buffers supported in frames, None if the host does not know
+    pub buffer_sizes: Option<RangeInclusive<u32>>,
+}
+
+impl ConfigRange {
+    pub(crate) fn from_cpal(range: &cpal::SupportedStreamConfigRange) -> ConfigRange {
+        ConfigRange {
+            sample_type: range.sample_format().try_into().ok(),
+            channels: range.channels(),
+            sample_rates: range.min_sample_rate().0..=range.max_sample_rate().0,
+            buffer_sizes: buffer_sizes_from_cpal(range.buffer_size()),
+        }
+    }
+
+    /// Returns true if the metadata fits in the range
+    pub fn supports(&self, metadata: &SamplesMetadata) -> bool {
+        self.sample_type.as_ref() == Some(&metadata.sample_type)
+            && self.channels == metadata.channels
+            && self.sample_rates.contains(&metadata.sample_rate)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// The default config of a device in one direction
+pub struct DefaultConfig {
+    /// The metadata of the default config, None if ez_audi does not know the format of the device
+    pub metadata: Option<SamplesMetadata>,
+    /// The smallest and biggest buffers supported in frames, None if the host does not know
+    pub buffer_sizes: Option<RangeInclusive<u32>>,
+}
+
+impl DefaultConfig {
+    pub(crate) fn from_cpal(config: &cpal::SupportedStreamConfig) -> DefaultConfig {
+        let metadata = SampleType::try_from(config.sample_format()).ok()
+            .map(|t| SamplesMetadata::new(config.channels(), config.sample_rate().0, t));
+
+        DefaultConfig {
+            metadata,
+            buffer_sizes: buffer_sizes_from_cpal(config.buffer_size()),
+        }
+    }
+}
+
+fn buffer_sizes_from_cpal(buffer_size: &cpal::SupportedBufferSize) -> Option<RangeInclusive<u32>> {
+    match buffer_size {
+        cpal::SupportedBufferSize::Range { min, max } => Some(*min..=*max),
+        cpal::SupportedBufferSize::Unknown => None,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Everything a device supports, as returned by `Device::capabilities`
+pub struct DeviceCapabilities {
+    /// The name of the device, if it has one
+    pub name: Option<String>,
+    /// The name of the host (audio backend) the device belongs to
+    pub host: String,
+    /// The configs the device can capture audio with, empty if it is not an input device
+    pub input_configs: Vec<ConfigRange>,
+    /// The configs the device can play audio with, empty if it is not an output device
+    pub output_configs: Vec<ConfigRange>,
+    /// The config the device captures audio with by default
+    pub default_input_config: Option<DefaultConfig>,
+    /// The config the device plays audio with by default
+    pub default_output_config: Option<DefaultConfig>,
+}
+
+impl DeviceCapabilities {
+    /// Returns the configs in the direction
+    pub fn configs(&self, direction: StreamDirection) -> &[ConfigRange] {
+        match direction {
+            StreamDirection::Input => &self.input_configs,
+            StreamDirection::Output => &self.output_configs,
+        }
+    }
+
+    /// Returns true if the device can capture or play audio in the direction
+    pub fn supports_direction(&self, direction: StreamDirection) -> bool {
+        !self.configs(direction).is_empty()
+    }
+
+    /// Returns the sample types supported in the direction, without duplicates
+    pub fn sample_types(&self, direction: StreamDirection) -> Vec<SampleType> {
+        let mut sample_types = Vec::new();
+        for sample_type in self.configs(direction).iter().filter_map(|c| c.sample_type.clone()) {
+            if !sample_types.contains(&sample_type) {
+                sample_types.push(sample_type);
+            }
+        }
+
+        sample_types
+    }
+
+    /// Returns the channel counts supported in the direction, sorted and without duplicates
+    pub fn channel_counts(&self, direction: StreamDirection) -> Vec<u16> {
+        let mut channels = self.configs(direction).iter()
+            .map(|c| c.channels)
+            .collect::<Vec<_>>();
+        channels.sort();
+        channels.dedup();
+
+        channels
+    }
+
+    /// Returns the lowest and highest sample rates supported in the direction, None if there are no configs
+    pub fn sample_rates(&self, direction: StreamDirection) -> Option<RangeInclusive<u32>> {
+        let configs = self.configs(direction);
+        let min = configs.iter().map(|c| *c.sample_rates.start()).min()?;
+        let max = configs.iter().map(|c| *c.sample_rates.end()).max()?;
+
+        Some(min..=max)
+    }
+
+    /// Returns true if a single config supports the metadata exactly
+    pub fn supports(&self, metadata: &SamplesMetadata, direction: StreamDirection) -> bool {
+        self.configs(direction).iter().any(|c| c.supports(metadata))
+    }
+
+    /// Returns the settings of the metadata that the device does not support in the direction,
+    /// empty if it is supported. The samples can still be played or recorded, they are then converted
+    pub fn unsupported_settings(&self, metadata: &SamplesMetadata, direction: StreamDirection) -> Vec<AudioSettings> {
+        if self.supports(metadata, direction) {
+            return Vec::new()
+        }
+
+        let configs = self.configs(direction);
+        let mut settings = Vec::new();
+
+        if !configs.iter().any(|c| c.sample_type.as_ref() == Some(&metadata.sample_type)) {
+            settings.push(AudioSettings::SampleType(Some(metadata.sample_type.clone())));
+        }
+        if !configs.iter().any(|c| c.sample_rates.contains(&metadata.sample_rate)) {
+            settings.push(AudioSettings::SampleRate(metadata.sample_rate));
+        }
+        if !configs.iter().any(|c| c.channels == metadata.channels) {
+            settings.push(AudioSettings::Channels(metadata.channels as u32));
+        }
+
+        // Was it none individually?
+        if settings.is_empty() {
+            settings.push(AudioSettings::Combinaison);
+        }
+
+        settings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn capabilities() -> DeviceCapabilities {
+        let config = ConfigRange {
+            sample_type: Some(SampleType::I16),
+            channels: 2,
+            sample_rates: 44100..=48000,
+            buffer_sizes: None,
+        };
+
+        DeviceCapabilities {
+            name: None,
+            host: "test".to_string(),
+            input_configs: Vec::new(),
+            output_configs: vec![config],
+            default_input_config: None,
+            default_output_config: None,
+        }
+    }
+
+    #[test]
+    fn reports_unsupported_settings() {
+        let capabilities = capabilities();
+
+        assert!(capabilities.supports(&SamplesMetadata::new(2, 48000, SampleType::I16), StreamDirection::Output));
+        assert!(!capabilities.supports_direction(StreamDirection::Input));
+
+        let settings = capabilities.unsupported_settings(&SamplesMetadata::new(1, 8000, SampleType::I16), StreamDirection::Output);
+        assert_eq!(settings, vec![AudioSettings::SampleRate(8000), AudioSettings::Channels(1)]);
+    }
+}
@@ -0,0 +1,105 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Debug)]
+struct ClockState {
+    sample_rate: u32,
+    /// Frames handed to the device before the last callback
+    frames_before_callback: u64,
+    /// Frames handed to the device including the last callback
+    frames: u64,
+    /// When the last callback happened
+    last_callback: Option<Instant>,
+    /// Time between a callback and the moment its samples are heard
+    latency: Duration,
+}
+
+#[derive(Debug, Clone)]
+/// The frame clock of an output stream, counts the frames (one sample per channel) sent to the device.
+/// Is updated at every callback of the stream with the timestamps given by the host, which allows
+/// to know which frame will be heard at a given `Instant`
+pub struct StreamClock {
+    state: Arc<Mutex<ClockState>>,
+}
+
+impl StreamClock {
+    pub(crate) fn new(sample_rate: u32) -> StreamClock {
+        let state = ClockState {
+            sample_rate,
+            frames_before_callback: 0,
+            frames: 0,
+            last_callback: None,
+            latency: Duration::ZERO,
+        };
+
+        StreamClock {
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    /// Called by the stream at the start of each callback
+    pub(crate) fn advance(&self, frames: u64, latency: Option<Duration>) {
+        // The clock is not worth crashing the audio thread over
+        if let Ok(mut state) = self.state.lock() {
+            state.frames_before_callback = state.frames;
+            state.frames += frames;
+            state.last_callback = Some(Instant::now());
+            if let Some(latency) = latency {
+                state.latency = latency;
+            }
+        }
+    }
+
+    /// Returns the sample rate the clock is counting in
+    pub fn sample_rate(&self) -> u32 {
+        self.state.lock().map(|s| s.sample_rate).unwrap_or(0)
+    }
+
+    /// Returns the number of frames that were sent to the device so far
+    pub fn frames(&self) -> u64 {
+        self.state.lock().map(|s| s.frames).unwrap_or(0)
+    }
+
+    /// Returns the time elapsed on the stream based on the frames sent to the device
+    pub fn time(&self) -> Duration {
+        let (frames, sample_rate) = match self.state.lock() {
+            Ok(s) => (s.frames, s.sample_rate),
+            Err(_) => return Duration::ZERO,
+        };
+
+        if sample_rate == 0 {
+            return Duration::ZERO
+        }
+
+        Duration::from_secs_f64(frames as f64 / sample_rate as f64)
+    }
+
+    /// Returns the time between the moment samples are sent to the device and the moment they are heard,
+    /// as reported by the host at the last callback
+    pub fn latency(&self) -> Duration {
+        self.state.lock().map(|s| s.latency).unwrap_or(Duration::ZERO)
+    }
+
+    /// Returns the frame that will be heard at the instant, compensating for the latency.
+    /// Returns None if the stream has not called back yet and so there is no point of reference
+    pub fn frame_at(&self, instant: Instant) -> Option<u64> {
+        let state = self.state.lock().ok()?;
+        let last_callback = state.last_callback?;
+
+        // The first frame of the last callback is heard at that instant
+        let heard_at = last_callback + state.latency;
+        let offset_seconds = match instant.checked_duration_since(heard_at) {
+            Some(d) => d.as_secs_f64(),
+            None => -heard_at.duration_since(instant).as_secs_f64(),
+        };
+
+        let frame = state.frames_before_callback as f64 + offset_seconds * state.sample_rate as f64;
+
+        Some(frame.round().max(0.0) as u64)
+    }
+
+    /// Returns the frame that is being heard right now
+    pub fn current_frame(&self) -> Option<u64> {
+        self.frame_at(Instant::now())
+    }
+}
@@ -1,4 +1,4 @@
-use cpal::{SupportedOutputConfigs, SupportedStreamConfigRange, SampleRate};
+use cpal::{SupportedOutputConfigs, SupportedStreamConfigRange, SupportedStreamConfig, SampleRate, SampleFormat};
 
 use crate::{traits::AudioMetadataTrait, errors::{PlayError, AudioSettings}, Error};
 
@@ -77,3 +77,44 @@ pub fn find_fitting_stream_config(metadata: &impl AudioMetadataTrait, range: Sup
 
     Err(PlayError::DeviceDoesNotSupportAudioSettings(error_list, None))
 }
+
+/// Finds the stream config that is the closest to the provided config, the samples then need to be converted to it.
+/// Works with both the output and input configs of a device.
+/// A config that supports the sample rate is preferred, than one with the same channel count (or more channels)
+/// and finally one with the same sample format (or f32 or i16)
+pub fn find_closest_stream_config(metadata: &impl AudioMetadataTrait, range: impl Iterator<Item = SupportedStreamConfigRange>) -> Error<SupportedStreamConfig> {
+    let channels = metadata.channels() as u16;
+    let sample_rate = metadata.sample_rate();
+    let sample_format: Option<SampleFormat> = metadata.sample_type().map(|t| t.into());
+
+    let closest_config = range
+        .map(|s_config| {
+            let min_rate = s_config.min_sample_rate().0;
+            let max_rate = s_config.max_sample_rate().0;
+            let config_rate = sample_rate.clamp(min_rate, max_rate);
+            let rate_score = config_rate.abs_diff(sample_rate);
+
+            // Losing channels is worse than having extra silent ones
+            let channels_score = match s_config.channels() {
+                c if c == channels => 0,
+                c if c > channels => (c - channels) as u32,
+                c => 100 + (channels - c) as u32,
+            };
+
+            let format_score = match s_config.sample_format() {
+                f if Some(f) == sample_format => 0,
+                SampleFormat::F32 => 1,
+                SampleFormat::I16 => 2,
+                _ => 3,
+            };
+
+            ((rate_score, channels_score, format_score), s_config.with_sample_rate(SampleRate(config_rate)))
+        })
+        .min_by_key(|(score, _)| *score)
+        .map(|(_, config)| config);
+
+    match closest_config {
+        Some(c) => Ok(c),
+        None => Err(PlayError::DeviceDoesNotSupportAudioSettings(vec![AudioSettings::Combinaison], None)),
+    }
+}
@@ -0,0 +1,199 @@
+use super::{IntermediateSampleType, SamplesMetadata, StreamSource};
+use crate::resampler::{Resampler, ResampleQuality};
+
+/// Maps a frame to another channel count.
+/// Mono is copied to every channel, extra channels are folded into the output channels and averaged,
+/// missing channels are silent
+pub(crate) fn remix_frame(input: &[IntermediateSampleType], output: &mut [IntermediateSampleType]) {
+    output.iter_mut().for_each(|s| *s = 0.0);
+
+    if input.len() == 1 {
+        output.iter_mut().for_each(|s| *s = input[0]);
+        return
+    }
+
+    let output_channels = output.len();
+    for (i, sample) in input.iter().enumerate() {
+        output[i % output_channels] += *sample;
+    }
+
+    for (c, sample) in output.iter_mut().enumerate() {
+        // Number of input channels that were folded into this one
+        let contributions = input.len().saturating_sub(c).div_ceil(output_channels);
+        if contributions > 1 {
+            *sample /= contributions as IntermediateSampleType;
+        }
+    }
+}
+
+/// Converts what a source outputs into the channel count and sample rate of the device, in the audio thread
+pub(crate) struct StreamConverter {
+    input_channels: usize,
+    output_channels: usize,
+    /// How many input frames are consumed per output frame
+    ratio: f64,
+    /// None if only the channels need to be converted
+    resampler: Option<Resampler>,
+    /// Converted samples that did not fit in the last output buffer
+    pending: Vec<IntermediateSampleType>,
+    /// Buffer the source writes into
+    source_buffer: Vec<IntermediateSampleType>,
+    /// Buffer for the pulled frames remixed to the output channel count
+    remixed: Vec<IntermediateSampleType>,
+}
+
+impl StreamConverter {
+    /// Returns None if there is nothing to convert
+    pub(crate) fn new(source_metadata: &SamplesMetadata, output_channels: u16, output_sample_rate: u32) -> Option<StreamConverter> {
+        if source_metadata.channels == output_channels && source_metadata.sample_rate == output_sample_rate {
+            return None
+        }
+
+        let resampler = if source_metadata.sample_rate != output_sample_rate {
+            Some(Resampler::new(output_channels, source_metadata.sample_rate, output_sample_rate, ResampleQuality::Sinc))
+        } else {
+            None
+        };
+
+        Some(StreamConverter {
+            input_channels: source_metadata.channels.max(1) as usize,
+            output_channels: output_channels.max(1) as usize,
+            ratio: source_metadata.sample_rate as f64 / output_sample_rate.max(1) as f64,
+            resampler,
+            pending: Vec::new(),
+            source_buffer: Vec::new(),
+            remixed: Vec::new(),
+        })
+    }
+
+    /// Pulls frames from the source and converts them at the end of the pending samples
+    fn pull<S: StreamSource + ?Sized>(&mut self, source: &mut S, frames: usize) {
+        self.source_buffer.clear();
+        self.source_buffer.resize(frames * self.input_channels, 0.0);
+        source.fill_buffer(&mut self.source_buffer);
+
+        self.remixed.clear();
+        self.remixed.resize(frames * self.output_channels, 0.0);
+        for (input, output) in self.source_buffer.chunks(self.input_channels).zip(self.remixed.chunks_mut(self.output_channels)) {
+            remix_frame(input, output);
+        }
+
+        match &mut self.resampler {
+            Some(r) => r.process(&self.remixed, &mut self.pending),
+            None => self.pending.extend_from_slice(&self.remixed),
+        }
+    }
+
+    /// Fills the output with the converted samples of the source
+    pub(crate) fn fill_buffer<S: StreamSource + ?Sized>(&mut self, source: &mut S, output: &mut [IntermediateSampleType]) {
+        while self.pending.len() < output.len() {
+            let missing_frames = (output.len() - self.pending.len()).div_ceil(self.output_channels);
+            let frames = (missing_frames as f64 * self.ratio).ceil() as usize + 1;
+
+            self.pull(source, frames);
+        }
+
+        output.copy_from_slice(&self.pending[..output.len()]);
+        self.pending.drain(..output.len());
+    }
+}
+
+/// Converts what an input device captures into the channel count and sample rate of the sink, in the audio thread
+pub(crate) struct InputConverter {
+    input_channels: usize,
+    output_channels: usize,
+    /// None if only the channels need to be converted
+    resampler: Option<Resampler>,
+    /// Buffer for the captured frames remixed to the sink's channel count
+    remixed: Vec<IntermediateSampleType>,
+    /// Buffer for the resampled frames
+    resampled: Vec<IntermediateSampleType>,
+}
+
+impl InputConverter {
+    /// Returns None if there is nothing to convert
+    pub(crate) fn new(input_channels: u16, input_sample_rate: u32, sink_metadata: &SamplesMetadata) -> Option<InputConverter> {
+        if sink_metadata.channels == input_channels && sink_metadata.sample_rate == input_sample_rate {
+            return None
+        }
+
+        let resampler = if sink_metadata.sample_rate != input_sample_rate {
+            Some(Resampler::new(sink_metadata.channels, input_sample_rate, sink_metadata.sample_rate, ResampleQuality::Sinc))
+        } else {
+            None
+        };
+
+        Some(InputConverter {
+            input_channels: input_channels.max(1) as usize,
+            output_channels: sink_metadata.channels.max(1) as usize,
+            resampler,
+            remixed: Vec::new(),
+            resampled: Vec::new(),
+        })
+    }
+
+    /// Converts the captured samples, the result is only valid until the next call
+    pub(crate) fn convert(&mut self, input: &[IntermediateSampleType]) -> &[IntermediateSampleType] {
+        let frames = input.len() / self.input_channels;
+
+        self.remixed.clear();
+        self.remixed.resize(frames * self.output_channels, 0.0);
+        for (input, output) in input.chunks(self.input_channels).zip(self.remixed.chunks_mut(self.output_channels)) {
+            remix_frame(input, output);
+        }
+
+        match &mut self.resampler {
+            Some(r) => {
+                self.resampled.clear();
+                r.process(&self.remixed, &mut self.resampled);
+                &self.resampled
+            },
+            None => &self.remixed,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpal_abstraction::SampleType;
+
+    struct Ramp(f32);
+
+    impl StreamSource for Ramp {
+        fn fill_buffer(&mut self, buffer: &mut [IntermediateSampleType]) {
+            for sample in buffer {
+                *sample = self.0;
+                self.0 += 1.0;
+            }
+        }
+    }
+
+    #[test]
+    fn remix_folds_and_copies_channels() {
+        let mut stereo = [0.0; 2];
+        remix_frame(&[0.5], &mut stereo);
+        assert_eq!(stereo, [0.5, 0.5]);
+
+        let mut mono = [0.0];
+        remix_frame(&[1.0, 0.0], &mut mono);
+        assert_eq!(mono, [0.5]);
+    }
+
+    #[test]
+    fn converter_resamples_continuously() {
+        let metadata = SamplesMetadata::new(1, 100, SampleType::F32);
+        let mut converter = StreamConverter::new(&metadata, 1, 200).unwrap();
+        let mut source = Ramp(0.0);
+
+        let mut output = [0.0; 400];
+        for chunk in output.chunks_mut(7) {
+            converter.fill_buffer(&mut source, chunk);
+        }
+
+        // Away from the start, a ramp stays a ramp at half the slope
+        for pair in output[100..].windows(2) {
+            assert!((pair[1] - pair[0] - 0.5).abs() < 0.01);
+        }
+    }
+}
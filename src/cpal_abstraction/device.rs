@@ -1,24 +1,101 @@
-use std::{fmt::Debug, sync::{Mutex, Arc}};
+use std::{fmt::Debug, sync::{Mutex, Arc}, time::Duration};
 
-use cpal::{self, traits::{HostTrait, DeviceTrait}, Host};
+use cpal::{self, traits::{HostTrait, DeviceTrait}, Sample as CpalSampleTrait};
 
 use crate::{traits::AudioMetadataTrait, Error, errors::PlayError};
 use crate::samples_player::SamplesPlayerTrait;
 
-use super::{config, Samples, Sample, Stream};
+use super::{config, stream, Samples, Sample, Stream, StreamClock, StreamSource, StreamSink, SamplesMetadata, SampleType, IntermediateSampleType};
+use super::{DeviceCapabilities, ConfigRange, DefaultConfig, Host, DeviceId, BufferSize, OfflineDevice};
+use super::conversion::{StreamConverter, InputConverter};
+use super::offline::IndexedSamples;
 
-/// An abstraction over cpal::Device, represents a physical output device
+/// Returns the time between the callback and the moment its samples are played, if the host knows it
+fn callback_latency(info: &cpal::OutputCallbackInfo) -> Option<Duration> {
+    let timestamp = info.timestamp();
+    timestamp.playback.duration_since(&timestamp.callback)
+}
+
+/// Returns the time between the moment the samples were captured and the callback, if the host knows it
+fn input_callback_latency(info: &cpal::InputCallbackInfo) -> Option<Duration> {
+    let timestamp = info.timestamp();
+    timestamp.callback.duration_since(&timestamp.capture)
+}
+
+enum DeviceKind {
+    Cpal {
+        device: cpal::Device,
+        /// The host the device belongs to
+        host_id: cpal::HostId,
+        /// Tells apart devices with the same name on the same host
+        index: usize,
+    },
+    Offline(OfflineDevice),
+}
+
+/// An abstraction over cpal::Device, represents a physical output and/or input device.
+/// Can also be an `OfflineDevice`, which has no hardware behind it
 pub struct Device {
-    device: cpal::Device,
+    kind: DeviceKind,
+    /// The buffer size asked for when creating streams
+    buffer_size: BufferSize,
 }
 
 impl Device {
-    fn new(device: cpal::Device) -> Device {
+    pub(super) fn new(device: cpal::Device, host_id: cpal::HostId, index: usize) -> Device {
+        Device {
+            kind: DeviceKind::Cpal { device, host_id, index },
+            buffer_size: BufferSize::Default,
+        }
+    }
+
+    pub(super) fn from_offline(device: OfflineDevice) -> Device {
         Device {
-            device
+            kind: DeviceKind::Offline(device),
+            buffer_size: BufferSize::Default,
         }
     }
 
+    /// Returns the offline device behind this device, None if it is a real one
+    pub fn offline(&self) -> Option<&OfflineDevice> {
+        match &self.kind {
+            DeviceKind::Offline(o) => Some(o),
+            DeviceKind::Cpal { .. } => None,
+        }
+    }
+
+    /// Returns the cpal device, offline devices have none so nothing can be done with cpal on them
+    fn cpal_device(&self) -> Error<&cpal::Device> {
+        match &self.kind {
+            DeviceKind::Cpal { device, .. } => Ok(device),
+            DeviceKind::Offline(_) => Err(PlayError::Unsupported("using cpal on an offline device".to_string())),
+        }
+    }
+
+    /// Sets the buffer size (and so the latency) of the streams created on this device, the default is `BufferSize::Default`.
+    /// The players played on this device will use it. Offline devices use the block size of the `OfflineDevice` instead
+    pub fn with_buffer_size(mut self, buffer_size: BufferSize) -> Device {
+        self.buffer_size = buffer_size;
+        self
+    }
+
+    /// Returns the buffer size asked for when creating streams
+    pub fn buffer_size(&self) -> BufferSize {
+        self.buffer_size
+    }
+
+    /// Returns the config to create the stream with and its buffer size in frames, if a size is asked for
+    fn stream_config(&self, config: &cpal::SupportedStreamConfig) -> Error<(cpal::StreamConfig, Option<u32>)> {
+        let buffer_frames = self.buffer_size.frames(config.buffer_size(), config.sample_rate().0)?;
+
+        let mut stream_config = config.config();
+        if let Some(frames) = buffer_frames {
+            stream_config.buffer_size = cpal::BufferSize::Fixed(frames);
+        }
+
+        Ok((stream_config, buffer_frames))
+    }
+
     /// Plays the samples on the default device of the default host
     pub fn play_default_output(player: &mut impl SamplesPlayerTrait) -> Error<()> {
         let device =  match Device::default_output() {
@@ -30,8 +107,22 @@ impl Device {
     }
 
     /// Creates a stream that will play the metadata based on the metadata given
-    pub fn create_stream<T: Sample>(&self, metadata: &impl AudioMetadataTrait, samples: Arc<Mutex<Samples<T>>>) -> Error<Stream> {
-        let config_range = match self.inner_device().supported_output_configs() {
+    pub fn create_stream<T: Sample>(&self, metadata: &impl AudioMetadataTrait, samples: Arc<Mutex<Samples<T>>>) -> Error<Stream>
+    where IntermediateSampleType: cpal::FromSample<T> {
+        self.create_stream_from_index(metadata, samples, 0)
+    }
+
+    /// Same as `create_stream`, but starts playing at the index of the samples
+    pub(crate) fn create_stream_from_index<T: Sample>(&self, metadata: &impl AudioMetadataTrait, samples: Arc<Mutex<Samples<T>>>, start_index: usize) -> Error<Stream>
+    where IntermediateSampleType: cpal::FromSample<T> {
+        if let DeviceKind::Offline(offline) = &self.kind {
+            let source_metadata = SamplesMetadata::new(metadata.channels() as u16, metadata.sample_rate(), SampleType::F32);
+            let source = IndexedSamples { samples, index: start_index };
+
+            return offline.create_source_stream(&source_metadata, Arc::new(Mutex::new(source)))
+        }
+
+        let config_range = match self.cpal_device()?.supported_output_configs() {
             Ok(c) => c,
             Err(e) => return Err(PlayError::DeviceIoError(
                 format!("the device had an issue fetching configs"), Some(Box::new(e))))
@@ -42,10 +133,22 @@ impl Device {
         let sample_rate = cpal::SampleRate(metadata.sample_rate());
         let config = config.with_sample_rate(sample_rate);
 
-        let mut index = 0;
-        let data_callback = move |samples_out: &mut [T], _info: &_| {
-            // TODO: This should maybe not crash // Removed expect so that it does not print text
-            let samples = samples.lock().unwrap(); //.expect("samples are inaccessible to audio stream");
+        let clock = StreamClock::new(metadata.sample_rate());
+        let callback_clock = clock.clone();
+        let channels = metadata.channels().max(1) as u64;
+
+        let mut index = start_index;
+        let data_callback = move |samples_out: &mut [T], info: &cpal::OutputCallbackInfo| {
+            callback_clock.advance(samples_out.len() as u64 / channels, callback_latency(info));
+
+            // Plays silence rather than crashing the audio thread
+            let samples = match samples.lock() {
+                Ok(s) => s,
+                Err(_) => {
+                    samples_out.iter_mut().for_each(|s| *s = T::EQUILIBRIUM);
+                    return
+                },
+            };
             for sample in samples_out {
                 *sample = match samples.samples.get(index) {
                     Some(s) => *s,
@@ -55,14 +158,88 @@ impl Device {
             }
         };
 
-        let error_callback = |err| {
-            // FIXME: I will puke uncontrolably if this is not removed within a reasonable amount of time :)
-            panic!("{:?}", err);
+        let (error_callback, errors) = stream::error_channel();
+
+        let (stream_config, buffer_frames) = self.stream_config(&config)?;
+        let stream_err = self
+            .cpal_device()?
+            .build_output_stream(&stream_config, data_callback, error_callback, None);
+
+        let stream = match stream_err {
+            Ok(s) => s,
+            Err(e) => return Err(PlayError::DeviceIoError(
+                "device had an error while trying to build an audio stream".to_string(),
+                Some(Box::new(e)))),
+        };
+
+        Ok(Stream::new(stream, clock, errors, buffer_frames))
+    }
+
+    /// Creates a stream that will pull its samples from the source.
+    /// If the device does not support the metadata, the closest config the device supports is used
+    /// and the samples given by the source are converted to its sample type, channel count and sample rate
+    pub fn create_source_stream<S: StreamSource>(&self, metadata: &SamplesMetadata, source: Arc<Mutex<S>>) -> Error<Stream> {
+        if let DeviceKind::Offline(offline) = &self.kind {
+            return offline.create_source_stream(metadata, source)
+        }
+
+        let config_range = match self.cpal_device()?.supported_output_configs() {
+            Ok(c) => c,
+            Err(e) => return Err(PlayError::DeviceIoError(
+                "the device had an issue fetching configs".to_string(), Some(Box::new(e))))
         };
-        
+
+        let config = config::find_closest_stream_config(metadata, config_range)?;
+
+        match config.sample_format() {
+            cpal::SampleFormat::U8 => self.create_typed_source_stream::<u8, S>(metadata, config, source),
+            cpal::SampleFormat::U16 => self.create_typed_source_stream::<u16, S>(metadata, config, source),
+            cpal::SampleFormat::U32 => self.create_typed_source_stream::<u32, S>(metadata, config, source),
+            cpal::SampleFormat::U64 => self.create_typed_source_stream::<u64, S>(metadata, config, source),
+            cpal::SampleFormat::I8 => self.create_typed_source_stream::<i8, S>(metadata, config, source),
+            cpal::SampleFormat::I16 => self.create_typed_source_stream::<i16, S>(metadata, config, source),
+            cpal::SampleFormat::I32 => self.create_typed_source_stream::<i32, S>(metadata, config, source),
+            cpal::SampleFormat::I64 => self.create_typed_source_stream::<i64, S>(metadata, config, source),
+            cpal::SampleFormat::F32 => self.create_typed_source_stream::<f32, S>(metadata, config, source),
+            cpal::SampleFormat::F64 => self.create_typed_source_stream::<f64, S>(metadata, config, source),
+            f => Err(PlayError::Unsupported(format!("the sample format {f:?}"))),
+        }
+    }
+
+    fn create_typed_source_stream<T: Sample, S: StreamSource>(&self, metadata: &SamplesMetadata, config: cpal::SupportedStreamConfig, source: Arc<Mutex<S>>) -> Error<Stream> {
+        let clock = StreamClock::new(config.sample_rate().0);
+        let callback_clock = clock.clone();
+        let channels = config.channels().max(1) as u64;
+
+        let mut converter = StreamConverter::new(metadata, config.channels(), config.sample_rate().0);
+
+        // Kept between callbacks so that we don't allocate in the audio thread every time
+        let mut buffer: Vec<IntermediateSampleType> = Vec::new();
+        let data_callback = move |samples_out: &mut [T], info: &cpal::OutputCallbackInfo| {
+            callback_clock.advance(samples_out.len() as u64 / channels, callback_latency(info));
+
+            buffer.clear();
+            buffer.resize(samples_out.len(), 0.0);
+
+            // The buffer stays silent if the source is not accessible anymore
+            if let Ok(mut source) = source.lock() {
+                match &mut converter {
+                    Some(c) => c.fill_buffer(&mut *source, &mut buffer),
+                    None => source.fill_buffer(&mut buffer),
+                }
+            }
+
+            for (sample_out, sample) in samples_out.iter_mut().zip(&buffer) {
+                *sample_out = sample.to_sample::<T>();
+            }
+        };
+
+        let (error_callback, errors) = stream::error_channel();
+
+        let (stream_config, buffer_frames) = self.stream_config(&config)?;
         let stream_err = self
-            .inner_device()
-            .build_output_stream(&config.config(), data_callback, error_callback, None);
+            .cpal_device()?
+            .build_output_stream(&stream_config, data_callback, error_callback, None);
 
         let stream = match stream_err {
             Ok(s) => s,
@@ -71,7 +248,80 @@ impl Device {
                 Some(Box::new(e)))),
         };
 
-        Ok(stream.into())
+        Ok(Stream::new(stream, clock, errors, buffer_frames))
+    }
+
+    /// Creates a stream that will capture samples from the device and hand them to the sink.
+    /// If the device does not support the metadata, the closest config the device supports is used
+    /// and the captured samples are converted to the channel count and sample rate of the metadata
+    pub fn create_input_stream<S: StreamSink>(&self, metadata: &SamplesMetadata, sink: Arc<Mutex<S>>) -> Error<Stream> {
+        if let DeviceKind::Offline(_) = &self.kind {
+            return Err(PlayError::Unsupported("capturing audio from an offline device".to_string()))
+        }
+
+        let config_range = match self.cpal_device()?.supported_input_configs() {
+            Ok(c) => c,
+            Err(e) => return Err(PlayError::DeviceIoError(
+                "the device had an issue fetching input configs".to_string(), Some(Box::new(e))))
+        };
+
+        let config = config::find_closest_stream_config(metadata, config_range)?;
+
+        match config.sample_format() {
+            cpal::SampleFormat::U8 => self.create_typed_input_stream::<u8, S>(metadata, config, sink),
+            cpal::SampleFormat::U16 => self.create_typed_input_stream::<u16, S>(metadata, config, sink),
+            cpal::SampleFormat::U32 => self.create_typed_input_stream::<u32, S>(metadata, config, sink),
+            cpal::SampleFormat::U64 => self.create_typed_input_stream::<u64, S>(metadata, config, sink),
+            cpal::SampleFormat::I8 => self.create_typed_input_stream::<i8, S>(metadata, config, sink),
+            cpal::SampleFormat::I16 => self.create_typed_input_stream::<i16, S>(metadata, config, sink),
+            cpal::SampleFormat::I32 => self.create_typed_input_stream::<i32, S>(metadata, config, sink),
+            cpal::SampleFormat::I64 => self.create_typed_input_stream::<i64, S>(metadata, config, sink),
+            cpal::SampleFormat::F32 => self.create_typed_input_stream::<f32, S>(metadata, config, sink),
+            cpal::SampleFormat::F64 => self.create_typed_input_stream::<f64, S>(metadata, config, sink),
+            f => Err(PlayError::Unsupported(format!("the sample format {f:?}"))),
+        }
+    }
+
+    fn create_typed_input_stream<T: Sample, S: StreamSink>(&self, metadata: &SamplesMetadata, config: cpal::SupportedStreamConfig, sink: Arc<Mutex<S>>) -> Error<Stream>
+    where IntermediateSampleType: cpal::FromSample<T> {
+        let clock = StreamClock::new(config.sample_rate().0);
+        let callback_clock = clock.clone();
+        let channels = config.channels().max(1) as u64;
+
+        let mut converter = InputConverter::new(config.channels(), config.sample_rate().0, metadata);
+
+        // Kept between callbacks so that we don't allocate in the audio thread every time
+        let mut buffer: Vec<IntermediateSampleType> = Vec::new();
+        let data_callback = move |samples_in: &[T], info: &cpal::InputCallbackInfo| {
+            callback_clock.advance(samples_in.len() as u64 / channels, input_callback_latency(info));
+
+            buffer.clear();
+            buffer.extend(samples_in.iter().map(|s| s.to_sample::<IntermediateSampleType>()));
+
+            // The captured samples are lost if the sink is not accessible anymore
+            if let Ok(mut sink) = sink.lock() {
+                match &mut converter {
+                    Some(c) => sink.consume_buffer(c.convert(&buffer)),
+                    None => sink.consume_buffer(&buffer),
+                }
+            }
+        };
+
+        let (error_callback, errors) = stream::error_channel();
+
+        let (stream_config, buffer_frames) = self.stream_config(&config)?;
+        let stream_err = self
+            .cpal_device()?
+            .build_input_stream(&stream_config, data_callback, error_callback, None);
+
+        let stream = match stream_err {
+            Ok(s) => s,
+            Err(e) => return Err(PlayError::DeviceIoError(
+                "device had an error while trying to build an input stream".to_string(),
+                Some(Box::new(e)))),
+        };
+
+        Ok(Stream::new(stream, clock, errors, buffer_frames))
     }
 
     /// Plays the samples in the SamplesPlayer on this device
@@ -79,8 +329,8 @@ impl Device {
         player.play_on_device(self)
     }
 
-    /// Returns all devices from all hosts
-    fn list_cpal_devices() -> Vec<cpal::Device> {
+    /// Returns all devices from all hosts, with the host they belong to
+    fn list_cpal_devices() -> Vec<(cpal::HostId, cpal::Device)> {
         // We like Iterators, You like Iterators, Everybody likes Iterators!
         let hosts = cpal::available_hosts();
 
@@ -89,23 +339,44 @@ impl Device {
             .map(|id| cpal::host_from_id(id))
             .filter(|r| r.is_ok())
             .map(|r| r.unwrap())
-            .collect::<Vec<Host>>();
+            .collect::<Vec<cpal::Host>>();
 
         // Gets all devices form all hosts, discards ones that cause an error
         hosts.into_iter()
-            .map(|h| h.devices())
-            .filter(|r| r.is_ok())
-            .map(|r| r.unwrap().collect::<Vec<cpal::Device>>())
-            .flatten()
+            .map(|h| (h.id(), h.devices()))
+            .filter(|(_, r)| r.is_ok())
+            .flat_map(|(id, r)| r.unwrap().map(move |d| (id, d)))
             .collect()
     } 
 
+    /// Returns the input devices from all hosts
+    fn list_cpal_input_devices() -> Vec<cpal::Device> {
+        cpal::available_hosts().into_iter()
+            .filter_map(|id| cpal::host_from_id(id).ok())
+            .filter_map(|h| h.input_devices().ok())
+            .flatten()
+            .collect()
+    }
+
+    /// Returns the output devices from all hosts
+    fn list_cpal_output_devices() -> Vec<cpal::Device> {
+        cpal::available_hosts().into_iter()
+            .filter_map(|id| cpal::host_from_id(id).ok())
+            .filter_map(|h| h.output_devices().ok())
+            .flatten()
+            .collect()
+    }
+
     /// Returns the default output device of the default host.
     /// Be aware that there may be none
     pub fn default_output() -> Option<Device> {
-        let inner_device = cpal::default_host().default_output_device()?;
+        Host::default_host().default_output()
+    }
 
-        Some(Device::new(inner_device))
+    /// Returns the default input device (microphone, line in, etc.) of the default host.
+    /// Be aware that there may be none
+    pub fn default_input() -> Option<Device> {
+        Host::default_host().default_input()
     }
 
     /// Gives the name of all devices on all hosts
@@ -114,38 +385,161 @@ impl Device {
 
         // Gets all device names, discards ones that cause an error 
         devices.into_iter()
-            .map(|d| d.name())
+            .map(|(_, d)| d.name())
             .filter(|r| r.is_ok())
             .map(|r| r.unwrap())
             .collect()
     }
 
-    /// Creates a new device from its device name
+    /// Gives the name of all devices that can capture audio on all hosts
+    pub fn list_input_device_names() -> Vec<String> {
+        Device::list_cpal_input_devices().into_iter()
+            .filter_map(|d| d.name().ok())
+            .collect()
+    }
+
+    /// Gives the name of all devices that can play audio on all hosts
+    pub fn list_output_device_names() -> Vec<String> {
+        Device::list_cpal_output_devices().into_iter()
+            .filter_map(|d| d.name().ok())
+            .collect()
+    }
+
+    /// Creates a new device from its device name, the first device with that name on all hosts.
+    /// Use `Host` to choose among devices with the same name
     pub fn new_from_name(device_name: &str) -> Option<Device> {
         let devices = Device::list_cpal_devices();
 
-        let the_device = devices.into_iter()
-            .filter(|d| d.name().unwrap_or("".to_string()) == device_name)
-            .next()?;
+        let (host_id, the_device) = devices.into_iter()
+            .find(|(_, d)| d.name().unwrap_or("".to_string()) == device_name)?;
+
+        // It is the first device with that name on its host
+        Some(Device::new(the_device, host_id, 0))
+    }
 
-        Some(Device::new(the_device))
+    /// Finds the device with the id on its host, None if it is not there anymore
+    pub fn new_from_id(id: &DeviceId) -> Option<Device> {
+        Host::from_name(&id.host)?.device_from_id(id)
     }
 
     #[doc(hidden)]
-    /// Gives a reference to the inner cpal device struct
+    /// Gives a reference to the inner cpal device struct.
+    /// # Panics
+    /// Offline devices have no cpal device, use `try_inner_device` if the device can be offline
     pub fn inner_device(&self) -> &cpal::Device {
-        &self.device
+        self.try_inner_device().expect("offline devices have no inner cpal device")
+    }
+
+    #[doc(hidden)]
+    /// Gives a reference to the inner cpal device struct, None for offline devices
+    pub fn try_inner_device(&self) -> Option<&cpal::Device> {
+        self.cpal_device().ok()
     }
 }
 
 impl Device {
     /// Returns the name of the device, if it can find one
     pub fn name(&self) -> Option<String> {
-        match self.device.name() {
-            Ok(n) => Some(n),
-            Err(_) => None,   
+        let device = match &self.kind {
+            DeviceKind::Cpal { device, .. } => device,
+            DeviceKind::Offline(o) => return Some(o.name()),
+        };
+
+        device.name().ok()
+    }
+
+    /// Returns the name of the host (audio backend) the device belongs to, "Offline" for offline devices
+    pub fn host_name(&self) -> String {
+        match &self.kind {
+            DeviceKind::Cpal { host_id, .. } => host_id.name().to_string(),
+            DeviceKind::Offline(o) => o.name(),
+        }
+    }
+
+    /// Returns the id of the device, which can be saved to find the device again with `Device::new_from_id`
+    pub fn id(&self) -> DeviceId {
+        DeviceId {
+            host: self.host_name(),
+            name: self.name().unwrap_or_default(),
+            index: match &self.kind {
+                DeviceKind::Cpal { index, .. } => *index,
+                DeviceKind::Offline(_) => 0,
+            },
         }
     }
+
+    /// Returns everything the device supports: sample types, channel counts, sample rates and buffer sizes
+    /// in both directions, with its default configs
+    pub fn capabilities(&self) -> DeviceCapabilities {
+        let device = match &self.kind {
+            DeviceKind::Cpal { device, .. } => device,
+            DeviceKind::Offline(o) => return offline_capabilities(o),
+        };
+
+        let input_configs = match device.supported_input_configs() {
+            Ok(c) => c.map(|c| ConfigRange::from_cpal(&c)).collect(),
+            Err(_) => Vec::new(),
+        };
+        let output_configs = match device.supported_output_configs() {
+            Ok(c) => c.map(|c| ConfigRange::from_cpal(&c)).collect(),
+            Err(_) => Vec::new(),
+        };
+
+        DeviceCapabilities {
+            name: self.name(),
+            host: self.host_name(),
+            input_configs,
+            output_configs,
+            default_input_config: device.default_input_config().ok().map(|c| DefaultConfig::from_cpal(&c)),
+            default_output_config: device.default_output_config().ok().map(|c| DefaultConfig::from_cpal(&c)),
+        }
+    }
+
+    /// Returns true if the device can capture audio
+    pub fn supports_input(&self) -> bool {
+        let device = match &self.kind {
+            DeviceKind::Cpal { device, .. } => device,
+            DeviceKind::Offline(_) => return false,
+        };
+
+        device.supported_input_configs()
+            .map(|mut c| c.next().is_some())
+            .unwrap_or(false)
+    }
+
+    /// Returns true if the device can play audio
+    pub fn supports_output(&self) -> bool {
+        let device = match &self.kind {
+            DeviceKind::Cpal { device, .. } => device,
+            DeviceKind::Offline(_) => return true,
+        };
+
+        device.supported_output_configs()
+            .map(|mut c| c.next().is_some())
+            .unwrap_or(false)
+    }
+}
+
+/// An offline device plays at exactly one config
+fn offline_capabilities(device: &OfflineDevice) -> DeviceCapabilities {
+    let metadata = device.metadata();
+    let buffer_sizes = Some(device.block_size()..=device.block_size());
+
+    let config = ConfigRange {
+        sample_type: Some(metadata.sample_type.clone()),
+        channels: metadata.channels,
+        sample_rates: metadata.sample_rate..=metadata.sample_rate,
+        buffer_sizes: buffer_sizes.clone(),
+    };
+
+    DeviceCapabilities {
+        name: Some(device.name()),
+        host: device.name(),
+        input_configs: Vec::new(),
+        output_configs: vec![config],
+        default_input_config: None,
+        default_output_config: Some(DefaultConfig { metadata: Some(metadata), buffer_sizes }),
+    }
 }
 
 impl Debug for Device {
@@ -158,15 +552,26 @@ impl Debug for Device {
 mod tests {
     use super::*;
 
+    /// Machines without a sound card (CI, containers) have nothing to test
+    fn has_devices() -> bool {
+        !Host::default_host().devices().is_empty()
+    }
+
     #[test]
-    /// This test assumes that the computer that runs the tests at least have 1
-    /// device with a name
     fn list_device_names_works() {
+        if !has_devices() {
+            return
+        }
+
         assert_ne!(Device::list_device_names().len(), 0)
     }
 
     #[test]
     fn new_from_name_works() {
+        if !has_devices() {
+            return
+        }
+
         let device_name = &Device::list_device_names()[0];
         assert!(Device::new_from_name(device_name).is_some())
     }
@@ -0,0 +1,206 @@
+use std::collections::HashMap;
+use std::fmt::{Debug, Display};
+use std::str::FromStr;
+
+use cpal::traits::{HostTrait, DeviceTrait};
+
+use crate::errors::PlayError;
+
+use super::Device;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+/// Identifies a device across runs of the program, as long as the devices of the host do not change.
+/// Can be turned into a string and parsed back to be saved in settings
+pub struct DeviceId {
+    /// The name of the host the device belongs to
+    pub host: String,
+    /// The name of the device
+    pub name: String,
+    /// Tells apart devices with the same name on the same host, 0 for the first one
+    pub index: usize,
+}
+
+impl Display for DeviceId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // The name goes last because it can contain anything
+        f.write_str(&format!("{}/{}/{}", self.host, self.index, self.name))
+    }
+}
+
+impl FromStr for DeviceId {
+    type Err = PlayError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(3, '/');
+        let invalid = || PlayError::ParseError(format!("the device id '{s}'"));
+
+        let host = parts.next().ok_or_else(invalid)?;
+        let index = parts.next().and_then(|i| i.parse().ok()).ok_or_else(invalid)?;
+        let name = parts.next().ok_or_else(invalid)?;
+
+        Ok(DeviceId {
+            host: host.to_string(),
+            name: name.to_string(),
+            index,
+        })
+    }
+}
+
+/// An abstraction over cpal::Host, an audio backend of the system (ALSA, JACK, WASAPI, etc.).
+/// Which hosts are available depends on the platform and on the features cpal was built with
+pub struct Host {
+    host: cpal::Host,
+}
+
+impl Host {
+    /// Returns the default host of the platform
+    pub fn default_host() -> Host {
+        Host {
+            host: cpal::default_host(),
+        }
+    }
+
+    /// Returns all the hosts that are available, discards the ones that fail to initialize
+    pub fn available() -> Vec<Host> {
+        cpal::available_hosts().into_iter()
+            .filter_map(|id| cpal::host_from_id(id).ok())
+            .map(|host| Host { host })
+            .collect()
+    }
+
+    /// Gives the name of all the available hosts
+    pub fn available_names() -> Vec<String> {
+        cpal::available_hosts().into_iter()
+            .map(|id| id.name().to_string())
+            .collect()
+    }
+
+    /// Returns the host with that name (case insensitive), None if it is not available
+    pub fn from_name(name: &str) -> Option<Host> {
+        let id = cpal::available_hosts().into_iter()
+            .find(|id| id.name().eq_ignore_ascii_case(name))?;
+
+        let host = cpal::host_from_id(id).ok()?;
+
+        Some(Host { host })
+    }
+
+    /// Returns the name of the host
+    pub fn name(&self) -> String {
+        self.host.id().name().to_string()
+    }
+
+    /// Returns all the devices of the host, each with a stable id
+    pub fn devices(&self) -> Vec<Device> {
+        let devices = match self.host.devices() {
+            Ok(d) => d,
+            Err(_) => return Vec::new(),
+        };
+
+        // Counts the devices with the same name to tell them apart
+        let mut name_counts: HashMap<String, usize> = HashMap::new();
+        devices.map(|device| {
+            let count = name_counts.entry(device.name().unwrap_or_default()).or_insert(0);
+            let index = *count;
+            *count += 1;
+
+            Device::new(device, self.host.id(), index)
+        })
+        .collect()
+    }
+
+    /// Returns the devices of the host that can capture audio
+    pub fn input_devices(&self) -> Vec<Device> {
+        self.devices().into_iter()
+            .filter(|d| d.supports_input())
+            .collect()
+    }
+
+    /// Returns the devices of the host that can play audio
+    pub fn output_devices(&self) -> Vec<Device> {
+        self.devices().into_iter()
+            .filter(|d| d.supports_output())
+            .collect()
+    }
+
+    /// Returns the default output device of the host.
+    /// Be aware that there may be none
+    pub fn default_output(&self) -> Option<Device> {
+        let device = self.host.default_output_device()?;
+        let index = self.index_of(&device);
+
+        Some(Device::new(device, self.host.id(), index))
+    }
+
+    /// Returns the default input device of the host.
+    /// Be aware that there may be none
+    pub fn default_input(&self) -> Option<Device> {
+        let device = self.host.default_input_device()?;
+        let index = self.index_of(&device);
+
+        Some(Device::new(device, self.host.id(), index))
+    }
+
+    /// Finds the index `devices` gives to the device.
+    /// cpal devices can not be compared, so devices with the same name are told apart by the configurations they support
+    fn index_of(&self, device: &cpal::Device) -> usize {
+        let name = device.name().unwrap_or_default();
+        let configs = |d: &cpal::Device| (
+            d.supported_output_configs().ok().map(|c| c.collect::<Vec<_>>()),
+            d.supported_input_configs().ok().map(|c| c.collect::<Vec<_>>()),
+        );
+
+        let same_name = match self.host.devices() {
+            Ok(d) => d.filter(|d| d.name().unwrap_or_default() == name).collect::<Vec<_>>(),
+            Err(_) => return 0,
+        };
+        if same_name.len() <= 1 {
+            return 0
+        }
+
+        let device_configs = configs(device);
+        same_name.iter()
+            .position(|d| configs(d) == device_configs)
+            .unwrap_or(0)
+    }
+
+    /// Returns the device of the host with that name, the first one if there are many
+    pub fn device_from_name(&self, name: &str) -> Option<Device> {
+        self.devices().into_iter()
+            .find(|d| d.name().as_deref() == Some(name))
+    }
+
+    /// Returns the device with that id, None if it is not on this host or not there anymore
+    pub fn device_from_id(&self, id: &DeviceId) -> Option<Device> {
+        if !self.name().eq_ignore_ascii_case(&id.host) {
+            return None
+        }
+
+        self.devices().into_iter()
+            .find(|d| d.id() == *id)
+    }
+}
+
+impl Debug for Host {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&format!("Host {{ name: {:?} }}", self.name()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn device_id_round_trips_through_strings() {
+        let id = DeviceId {
+            host: "ALSA".to_string(),
+            name: "hw:CARD=PCH/DEV=0".to_string(),
+            index: 1,
+        };
+
+        let parsed: DeviceId = id.to_string().parse().unwrap();
+        assert_eq!(parsed, id);
+        assert!(matches!("ALSA/not a number/default".parse::<DeviceId>(), Err(PlayError::ParseError(_))));
+    }
+}
@@ -4,4 +4,20 @@ pub use device::Device;
 pub mod samples;
 pub use samples::*;
 mod stream;
-pub use stream::Stream;
\ No newline at end of file
+pub use stream::Stream;
+mod source;
+pub use source::StreamSource;
+mod sink;
+pub use sink::StreamSink;
+mod clock;
+pub use clock::StreamClock;
+mod conversion;
+mod capabilities;
+pub use capabilities::{DeviceCapabilities, ConfigRange, DefaultConfig, StreamDirection};
+mod host;
+pub use host::{Host, DeviceId};
+mod buffer_size;
+pub use buffer_size::BufferSize;
+
+mod offline;
+pub use offline::OfflineDevice;
\ No newline at end of file
@@ -0,0 +1,424 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::errors::{Error, PlayError};
+
+use super::{Sample, Samples, SamplesMetadata, SampleType, IntermediateSampleType, Stream, StreamClock, StreamSink, StreamSource};
+use super::conversion::StreamConverter;
+
+/// Frames rendered at once when no block size is given
+const DEFAULT_BLOCK_FRAMES: usize = 512;
+
+/// Called by the device on every block, fills the buffer with what the stream plays
+type RenderCallback = Box<dyn FnMut(&mut [IntermediateSampleType]) + Send>;
+
+/// Receives everything the device renders
+type RenderSink = Box<dyn FnMut(&[IntermediateSampleType]) + Send>;
+
+/// The part of an offline stream the device pulls from
+struct OfflineStreamState {
+    callback: Mutex<RenderCallback>,
+    playing: AtomicBool,
+}
+
+/// An output stream on an offline device, the device only keeps a weak reference so dropping the stream removes it
+pub(crate) struct OfflineStream {
+    state: Arc<OfflineStreamState>,
+    /// Keeps the device rendering in real time while the stream is around
+    _device: Arc<OfflineDeviceState>,
+}
+
+impl OfflineStream {
+    pub(crate) fn start(&self) {
+        self.state.playing.store(true, Ordering::SeqCst);
+    }
+
+    pub(crate) fn stop(&self) {
+        self.state.playing.store(false, Ordering::SeqCst);
+    }
+}
+
+struct OfflineDeviceState {
+    channels: u16,
+    sample_rate: u32,
+    block_frames: AtomicUsize,
+    real_time: AtomicBool,
+    /// Set once the real time thread is running
+    thread_started: AtomicBool,
+    sink: Mutex<Option<RenderSink>>,
+    streams: Mutex<Vec<Weak<OfflineStreamState>>>,
+    frames: AtomicU64,
+}
+
+impl OfflineDeviceState {
+    fn lock_streams(&self) -> Error<MutexGuard<'_, Vec<Weak<OfflineStreamState>>>> {
+        self.streams.lock()
+            .map_err(|e| PlayError::PoisonedMutex("offline device streams".to_string(), e.to_string().into()))
+    }
+
+    /// Pulls the frames from all the playing streams, mixes them and hands them to the sink
+    fn render(&self, frames: usize) -> Error<()> {
+        let channels = self.channels.max(1) as usize;
+        let block_size = self.block_frames.load(Ordering::SeqCst);
+
+        let streams = {
+            let mut streams = self.lock_streams()?;
+            streams.retain(|s| s.strong_count() > 0);
+            streams.iter().filter_map(|s| s.upgrade()).collect::<Vec<_>>()
+        };
+
+        let mut mix = Vec::new();
+        let mut buffer = Vec::new();
+        let mut rendered = 0;
+        while rendered < frames {
+            let block_frames = block_size.min(frames - rendered);
+
+            mix.clear();
+            mix.resize(block_frames * channels, 0.0);
+
+            for stream in streams.iter().filter(|s| s.playing.load(Ordering::SeqCst)) {
+                buffer.clear();
+                buffer.resize(mix.len(), 0.0);
+
+                // A stream that panicked in its callback stays silent, like on a real device
+                if let Ok(mut callback) = stream.callback.lock() {
+                    callback(&mut buffer);
+                }
+
+                for (mixed, sample) in mix.iter_mut().zip(&buffer) {
+                    *mixed += *sample;
+                }
+            }
+
+            if let Ok(mut sink) = self.sink.lock() {
+                if let Some(sink) = sink.as_mut() {
+                    sink(&mix);
+                }
+            }
+
+            rendered += block_frames;
+            self.frames.fetch_add(block_frames as u64, Ordering::SeqCst);
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+/// A device without hardware behind it, it pulls from the streams played on it when told to
+/// (or in real time, see `with_real_time`) and discards what it renders or hands it to a sink.
+/// Useful to render players into `Samples` or a `WavWriter`, and to test them on machines without a sound card.
+/// Clones control the same device
+pub struct OfflineDevice {
+    state: Arc<OfflineDeviceState>,
+}
+
+impl OfflineDevice {
+    /// Creates an offline device playing at the channel count and sample rate, what it renders is discarded
+    pub fn new(channels: u16, sample_rate: u32) -> OfflineDevice {
+        let state = OfflineDeviceState {
+            channels: channels.max(1),
+            sample_rate,
+            block_frames: AtomicUsize::new(DEFAULT_BLOCK_FRAMES),
+            real_time: AtomicBool::new(false),
+            thread_started: AtomicBool::new(false),
+            sink: Mutex::new(None),
+            streams: Mutex::new(Vec::new()),
+            frames: AtomicU64::new(0),
+        };
+
+        OfflineDevice {
+            state: Arc::new(state),
+        }
+    }
+
+    /// Hands everything the device renders to the sink, interleaved at the channel count and sample rate of the device.
+    /// `Samples<f32>` and `WavWriter` are sinks. Like the other settings it applies to every clone of the device
+    pub fn with_sink<S: StreamSink>(self, sink: Arc<Mutex<S>>) -> OfflineDevice {
+        let render_sink: RenderSink = Box::new(move |buffer| {
+            if let Ok(mut sink) = sink.lock() {
+                sink.consume_buffer(buffer);
+            }
+        });
+
+        if let Ok(mut s) = self.state.sink.lock() {
+            *s = Some(render_sink);
+        }
+        self
+    }
+
+    /// Sets how many frames the streams are asked for at once, 512 by default
+    pub fn with_block_size(self, frames: u32) -> OfflineDevice {
+        self.state.block_frames.store((frames as usize).max(1), Ordering::SeqCst);
+        self
+    }
+
+    /// Renders on a thread at the pace of a real device instead of waiting for `render`,
+    /// for programs that expect the audio to play on its own
+    pub fn with_real_time(self, real_time: bool) -> OfflineDevice {
+        self.state.real_time.store(real_time, Ordering::SeqCst);
+
+        let has_streams = self.state.lock_streams().map(|s| !s.is_empty()).unwrap_or(false);
+        if has_streams {
+            self.start_real_time_thread();
+        }
+        self
+    }
+
+    /// Starts rendering on a thread if the device is in real time and no thread is running yet
+    fn start_real_time_thread(&self) {
+        if self.state.real_time.load(Ordering::SeqCst) && !self.state.thread_started.swap(true, Ordering::SeqCst) {
+            spawn_real_time_thread(Arc::downgrade(&self.state));
+        }
+    }
+
+    /// Creates a `Device` that players can play on
+    pub fn device(&self) -> super::Device {
+        super::Device::from_offline(self.clone())
+    }
+
+    /// Returns the metadata the device renders at
+    pub fn metadata(&self) -> SamplesMetadata {
+        SamplesMetadata::new(self.state.channels, self.state.sample_rate, SampleType::F32)
+    }
+
+    /// Returns how many frames the streams are asked for at once
+    pub fn block_size(&self) -> u32 {
+        self.state.block_frames.load(Ordering::SeqCst) as u32
+    }
+
+    /// Returns true if the device renders on its own, see `with_real_time`
+    pub fn is_real_time(&self) -> bool {
+        self.state.real_time.load(Ordering::SeqCst)
+    }
+
+    /// Returns the number of frames rendered so far
+    pub fn frames(&self) -> u64 {
+        self.state.frames.load(Ordering::SeqCst)
+    }
+
+    /// Renders that many frames from the streams that are playing, in blocks of the block size
+    pub fn render(&self, frames: usize) -> Error<()> {
+        self.state.render(frames)
+    }
+
+    /// Renders that much audio from the streams that are playing
+    pub fn render_duration(&self, duration: Duration) -> Error<()> {
+        let frames = (duration.as_secs_f64() * self.state.sample_rate as f64).round() as usize;
+
+        self.render(frames)
+    }
+
+    /// Creates a stream pulling from the source, converted to the channel count and sample rate of the device
+    pub(crate) fn create_source_stream<S: StreamSource>(&self, metadata: &SamplesMetadata, source: Arc<Mutex<S>>) -> Error<Stream> {
+        let clock = StreamClock::new(self.state.sample_rate);
+        let callback_clock = clock.clone();
+        let channels = self.state.channels as u64;
+
+        let mut converter = StreamConverter::new(metadata, self.state.channels, self.state.sample_rate);
+        let callback: RenderCallback = Box::new(move |buffer| {
+            callback_clock.advance(buffer.len() as u64 / channels, None);
+
+            // The buffer stays silent if the source is not accessible anymore
+            if let Ok(mut source) = source.lock() {
+                match &mut converter {
+                    Some(c) => c.fill_buffer(&mut *source, buffer),
+                    None => source.fill_buffer(buffer),
+                }
+            }
+        });
+
+        let stream_state = Arc::new(OfflineStreamState {
+            callback: Mutex::new(callback),
+            playing: AtomicBool::new(false),
+        });
+        self.state.lock_streams()?.push(Arc::downgrade(&stream_state));
+
+        self.start_real_time_thread();
+
+        let stream = OfflineStream {
+            state: stream_state,
+            _device: Arc::clone(&self.state),
+        };
+
+        Ok(Stream::new_offline(stream, clock, self.block_size()))
+    }
+
+    /// Returns a name for the device, so that it can be told apart from real ones
+    pub(crate) fn name(&self) -> String {
+        "Offline".to_string()
+    }
+}
+
+impl std::fmt::Debug for OfflineDevice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&format!("OfflineDevice {{ metadata: {:?}, frames: {} }}", self.metadata(), self.frames()))
+    }
+}
+
+/// Renders a block every block duration until the device and all its streams are dropped,
+/// it waits without rendering while the device is not in real time
+fn spawn_real_time_thread(device: Weak<OfflineDeviceState>) {
+    thread::spawn(move || {
+        let mut next_block = Instant::now();
+
+        loop {
+            let device = match device.upgrade() {
+                Some(d) => d,
+                None => return,
+            };
+            let block_frames = device.block_frames.load(Ordering::SeqCst);
+
+            if device.real_time.load(Ordering::SeqCst) {
+                if device.render(block_frames).is_err() {
+                    return
+                }
+            } else {
+                // Picks the pace back up from now once in real time again
+                next_block = Instant::now();
+            }
+
+            // Keeps the pace from the start so that the time spent rendering does not add up
+            next_block += Duration::from_secs_f64(block_frames as f64 / device.sample_rate.max(1) as f64);
+            drop(device);
+
+            thread::sleep(next_block.saturating_duration_since(Instant::now()));
+        }
+    });
+}
+
+/// Plays `Samples<T>` from an index, the source behind the streams of `Device::create_stream` on offline devices
+pub(crate) struct IndexedSamples<T: Sample> {
+    pub(crate) samples: Arc<Mutex<Samples<T>>>,
+    pub(crate) index: usize,
+}
+
+impl<T: Sample> StreamSource for IndexedSamples<T>
+where IntermediateSampleType: cpal::FromSample<T> {
+    fn fill_buffer(&mut self, buffer: &mut [IntermediateSampleType]) {
+        let samples = match self.samples.lock() {
+            Ok(s) => s,
+            Err(_) => return,
+        };
+
+        for sample in buffer {
+            if let Some(s) = samples.samples.get(self.index) {
+                *sample = cpal::Sample::to_sample::<IntermediateSampleType>(*s);
+            }
+            self.index += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modifiers::Volume;
+    use crate::samples_player::{SamplesPlayer, SamplesPlayerTrait, ExactSamplesPlayer, Mixer};
+
+    fn rendered_samples(channels: u16, sample_rate: u32) -> (OfflineDevice, Arc<Mutex<Samples<f32>>>) {
+        let samples = Arc::new(Mutex::new(Samples::new(Vec::new(), SamplesMetadata::new(channels, sample_rate, SampleType::F32))));
+        let device = OfflineDevice::new(channels, sample_rate)
+            .with_block_size(3)
+            .with_sink(Arc::clone(&samples));
+
+        (device, samples)
+    }
+
+    #[test]
+    fn renders_players_with_their_modifiers() {
+        let (offline, rendered) = rendered_samples(1, 1000);
+
+        let mut player = SamplesPlayer::new(Samples::new(vec![1.0f32; 4], SamplesMetadata::new(1, 1000, SampleType::F32)));
+        player.add_modifier(Box::new(Volume(0.5)));
+        player.play_on_device(offline.device()).unwrap();
+
+        offline.render(6).unwrap();
+        assert_eq!(rendered.lock().unwrap().samples, vec![0.5, 0.5, 0.5, 0.5, 0.0, 0.0]);
+        assert_eq!(offline.frames(), 6);
+
+        // Nothing is pulled from a stopped player
+        player.stop().unwrap();
+        offline.render(2).unwrap();
+        assert_eq!(rendered.lock().unwrap().samples.len(), 8);
+        assert_eq!(&rendered.lock().unwrap().samples[6..], &[0.0, 0.0]);
+    }
+
+    #[test]
+    fn exact_player_resumes_on_a_new_device() {
+        let (first, _) = rendered_samples(1, 1000);
+        let (second, rendered) = rendered_samples(1, 1000);
+
+        let mut player = ExactSamplesPlayer::new(Samples::new(vec![0i16, 8192, 16384, -16384], SamplesMetadata::new(1, 1000, SampleType::I16)));
+        player.play_on_device(first.device()).unwrap();
+        first.render(2).unwrap();
+
+        player.resume_on_device(second.device()).unwrap();
+        second.render(2).unwrap();
+        assert_eq!(rendered.lock().unwrap().samples, vec![0.5, -0.5]);
+
+        // Playing explicitly starts over, even after the end
+        let (third, rendered) = rendered_samples(1, 1000);
+        player.play_on_device(third.device()).unwrap();
+        third.render(2).unwrap();
+        assert_eq!(rendered.lock().unwrap().samples, vec![0.0, 0.25]);
+    }
+
+    #[test]
+    fn replaced_samples_keep_the_layout_of_the_player() {
+        let (offline, rendered) = rendered_samples(1, 1000);
+
+        let mut player = SamplesPlayer::new(Samples::new(vec![1.0f32; 4], SamplesMetadata::new(1, 1000, SampleType::F32)));
+        player.play_on_device(offline.device()).unwrap();
+        offline.render(2).unwrap();
+
+        player.replace_samples(Samples::new(vec![0.5f32; 8], SamplesMetadata::new(2, 1000, SampleType::F32)), None);
+        assert_eq!(player.metadata().channels(), 1);
+        offline.render(4).unwrap();
+        assert_eq!(&rendered.lock().unwrap().samples[2..], &[0.5; 4]);
+    }
+
+    #[test]
+    fn settings_apply_to_every_clone() {
+        let (offline, rendered) = rendered_samples(1, 1000);
+        let clone = offline.clone().with_block_size(2);
+        assert_eq!(offline.block_size(), 2);
+
+        let mut player = SamplesPlayer::new(Samples::new(vec![1.0f32; 4], SamplesMetadata::new(1, 1000, SampleType::F32)));
+        player.play_on_device(clone.device()).unwrap();
+        clone.render(2).unwrap();
+        assert_eq!(rendered.lock().unwrap().samples, vec![1.0, 1.0]);
+    }
+
+    #[test]
+    fn exact_player_resumes_at_the_source_position_from_another_sample_rate() {
+        // 40 frames at 4000Hz are 10 frames of the 1000Hz samples
+        let (first, _) = rendered_samples(1, 4000);
+        let (second, rendered) = rendered_samples(1, 1000);
+
+        let samples = (0..100).map(|i| i as f32 / 100.0).collect::<Vec<_>>();
+        let mut player = ExactSamplesPlayer::new(Samples::new(samples, SamplesMetadata::new(1, 1000, SampleType::F32)));
+        player.play_on_device(first.device()).unwrap();
+        first.render(40).unwrap();
+
+        player.resume_on_device(second.device()).unwrap();
+        second.render(1).unwrap();
+        assert_eq!((rendered.lock().unwrap().samples[0] * 100.0).round(), 10.0);
+    }
+
+    #[test]
+    fn mixer_clock_follows_the_rendered_frames() {
+        let (offline, rendered) = rendered_samples(2, 1000);
+
+        let mut mixer = Mixer::new(1, 1000);
+        mixer.add_source(Samples::new(vec![0.25f32; 8], SamplesMetadata::new(1, 1000, SampleType::F32))).unwrap();
+        mixer.play_on_device(offline.device()).unwrap();
+
+        offline.render(4).unwrap();
+        assert_eq!(mixer.frame().unwrap(), 4);
+        // The mono mixer is played on both channels of the device
+        assert_eq!(rendered.lock().unwrap().samples, vec![0.25; 8]);
+    }
+}
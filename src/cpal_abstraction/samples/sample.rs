@@ -2,6 +2,8 @@ use std::fmt::Debug;
 
 use cpal;
 
+use crate::errors::PlayError;
+
 use super::Samples;
 
 /// Type that the samples with be converted to in order to do stuff such as apply modifiers.
@@ -57,6 +59,29 @@ impl From<SampleType> for cpal::SampleFormat {
     }
 }
 
+impl TryFrom<cpal::SampleFormat> for SampleType {
+    type Error = PlayError;
+
+    fn try_from(value: cpal::SampleFormat) -> Result<Self, Self::Error> {
+        match value {
+            cpal::SampleFormat::U8 => Ok(SampleType::U8),
+            cpal::SampleFormat::U16 => Ok(SampleType::U16),
+            cpal::SampleFormat::U32 => Ok(SampleType::U32),
+            cpal::SampleFormat::U64 => Ok(SampleType::U64),
+
+            cpal::SampleFormat::I8 => Ok(SampleType::I8),
+            cpal::SampleFormat::I16 => Ok(SampleType::I16),
+            cpal::SampleFormat::I32 => Ok(SampleType::I32),
+            cpal::SampleFormat::I64 => Ok(SampleType::I64),
+
+            cpal::SampleFormat::F32 => Ok(SampleType::F32),
+            cpal::SampleFormat::F64 => Ok(SampleType::F64),
+
+            f => Err(PlayError::Unsupported(format!("the sample format {f:?}"))),
+        }
+    }
+}
+
 // TODO: This is stupid bruteforcing of the problem
 // There is probably a way to make a macro for this
 impl From<u8> for SampleType {
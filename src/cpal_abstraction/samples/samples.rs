@@ -90,7 +90,7 @@ where IntermediateSampleType: cpal::FromSample<T> {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 /// Metadata about audio samples, normally used with the `Samples` struct
 pub struct SamplesMetadata {
     /// Numbers of channels: mono = 1, Stereo = 2, etc...
@@ -102,6 +102,7 @@ pub struct SamplesMetadata {
 }
 
 impl SamplesMetadata {
+    /// Creates a new SamplesMetadata struct
     pub fn new(channels: u16, sample_rate: u32, sample_type: SampleType) -> SamplesMetadata {
         SamplesMetadata { 
             channels,
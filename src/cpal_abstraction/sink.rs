@@ -0,0 +1,21 @@
+use super::{IntermediateSampleType, Samples};
+
+/// Trait implemented on everything that can receive the samples captured by an input stream in the audio thread.
+/// It is the counterpart of `StreamSource` for recording
+pub trait StreamSink: Send + 'static {
+    /// Receives a buffer of captured samples, the buffer is interleaved with the channel count of the stream
+    fn consume_buffer(&mut self, buffer: &[IntermediateSampleType]);
+}
+
+impl<F: FnMut(&[IntermediateSampleType]) + Send + 'static> StreamSink for F {
+    fn consume_buffer(&mut self, buffer: &[IntermediateSampleType]) {
+        self(buffer)
+    }
+}
+
+impl StreamSink for Samples<IntermediateSampleType> {
+    /// Appends the captured samples at the end
+    fn consume_buffer(&mut self, buffer: &[IntermediateSampleType]) {
+        self.samples.extend_from_slice(buffer);
+    }
+}
@@ -0,0 +1,10 @@
+use super::IntermediateSampleType;
+
+/// Trait implemented on everything that can be pulled from by an audio stream in the audio thread.
+/// Unlike `Samples`, a source decides what to output at each callback, which allows things such as
+/// mixing multiple sounds together while the stream is running
+pub trait StreamSource: Send + 'static {
+    /// Fills the whole buffer with samples, the buffer is interleaved with the channel count of the stream.
+    /// The buffer is filled with silence before being handed to the source
+    fn fill_buffer(&mut self, buffer: &mut [IntermediateSampleType]);
+}
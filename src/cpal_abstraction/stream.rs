@@ -1,23 +1,106 @@
+use std::sync::mpsc::{self, Receiver};
+use std::time::Duration;
+
 use cpal;
 use cpal::traits::StreamTrait;
 
-use crate::errors::Error;
+use crate::errors::{Error, PlayError};
+
+use super::StreamClock;
+use super::offline::OfflineStream;
+
+/// Creates the channel through which the error callback of a stream reports its errors
+pub(crate) fn error_channel() -> (impl FnMut(cpal::StreamError) + Send + 'static, Receiver<cpal::StreamError>) {
+    let (sender, receiver) = mpsc::channel::<cpal::StreamError>();
+
+    let error_callback = move |err| {
+        // The receiver is only gone if the stream is being dropped, nobody is left to tell
+        let _ = sender.send(err);
+    };
+
+    (error_callback, receiver)
+}
+
+enum StreamKind {
+    Cpal(cpal::Stream),
+    Offline(OfflineStream),
+}
 
 /// An audio stream, stops the stream when dropped
 pub struct Stream {
-    stream: cpal::Stream
+    stream: StreamKind,
+    clock: StreamClock,
+    errors: Receiver<cpal::StreamError>,
+    /// The size of the buffers asked to the device, None if the host chose it
+    buffer_size: Option<u32>,
 }
 
 impl Stream {
-    fn new(stream: cpal::Stream) -> Stream {
+    pub(crate) fn new(stream: cpal::Stream, clock: StreamClock, errors: Receiver<cpal::StreamError>, buffer_size: Option<u32>) -> Stream {
         Stream {
-            stream,
+            stream: StreamKind::Cpal(stream),
+            clock,
+            errors,
+            buffer_size,
+        }
+    }
+
+    pub(crate) fn new_offline(stream: OfflineStream, clock: StreamClock, block_size: u32) -> Stream {
+        // Nothing can go wrong without a device, the sender is dropped right away
+        let (_, errors) = mpsc::channel();
+
+        Stream {
+            stream: StreamKind::Offline(stream),
+            clock,
+            errors,
+            buffer_size: Some(block_size),
+        }
+    }
+
+    /// Returns the size of the buffers in frames, None if it was left to the host (see `BufferSize`)
+    pub fn buffer_size(&self) -> Option<u32> {
+        self.buffer_size
+    }
+
+    /// Returns the latency the stream achieved, as reported by the host once the stream called back.
+    /// Before that, or if the host does not report it, the duration of a buffer is returned if it is known
+    pub fn latency(&self) -> Option<Duration> {
+        let reported = self.clock.latency();
+        if reported > Duration::ZERO {
+            return Some(reported)
         }
+
+        let sample_rate = self.clock.sample_rate();
+        match self.buffer_size {
+            Some(frames) if sample_rate > 0 => Some(Duration::from_secs_f64(frames as f64 / sample_rate as f64)),
+            _ => None,
+        }
+    }
+
+    /// Returns the errors the stream ran into since the last call, as `PlayError::StreamIoError`.
+    /// The stream keeps running after an error, but it may be silent (if the device was unplugged for example)
+    pub fn take_errors(&self) -> Vec<PlayError> {
+        self.errors.try_iter()
+            .map(|e| e.into())
+            .collect()
+    }
+
+    /// Returns the frame clock of the stream, it is shared so it keeps being updated while the stream plays
+    pub fn clock(&self) -> StreamClock {
+        self.clock.clone()
     }
 
     /// Continues/Starts the audio from where it ended
     pub fn start(&self) -> Error<()> {
-        match self.stream.play() {
+        let stream = match &self.stream {
+            StreamKind::Cpal(s) => s,
+            StreamKind::Offline(s) => {
+                s.start();
+                return Ok(())
+            },
+        };
+
+        match stream.play() {
             Ok(_) => Ok(()),
             Err(e) => Err(e.into())
         }
@@ -25,15 +108,17 @@ impl Stream {
 
     /// Stops the audio until it is explicitly continued
     pub fn stop(&self) -> Error<()> {
-        match self.stream.pause() {
+        let stream = match &self.stream {
+            StreamKind::Cpal(s) => s,
+            StreamKind::Offline(s) => {
+                s.stop();
+                return Ok(())
+            },
+        };
+
+        match stream.pause() {
             Ok(_) => Ok(()),
             Err(e) => Err(e.into())
         }
     }
 }
-
-impl From<cpal::Stream> for Stream {
-    fn from(value: cpal::Stream) -> Self {
-        Stream::new(value)
-    }
-}
\ No newline at end of file
@@ -3,6 +3,7 @@
 use std::{io, fmt::Display, error, sync::PoisonError};
 
 use crate::cpal_abstraction::SampleType;
+use crate::samples_player::{SourceId, VoiceId};
 
 /// A Result<T, ez_audi::PlayError>
 pub type Error<T> = Result<T, PlayError>;
@@ -31,6 +32,30 @@ pub enum PlayError {
     PoisonedMutex(String, Box<dyn error::Error>),
     /// Feature is not support as of yet
     Unsupported(String),
+    /// The source is not, or no longer, in the mixer
+    SourceDoesNotExist(SourceId),
+    /// No sound was loaded under that name
+    SoundDoesNotExist(String),
+    /// The voice is not, or no longer, playing
+    VoiceDoesNotExist(VoiceId),
+    /// The pitch is not a finite number above 0
+    InvalidPitch(f64),
+    /// There is no track at that index in the queue
+    TrackDoesNotExist(usize),
+    /// There is no band at that index in the equalizer
+    BandDoesNotExist(usize),
+    /// The text is not in the expected format
+    ParseError(String),
+}
+
+impl PlayError {
+    /// Returns true if the error comes from a stream whose device is not available anymore (unplugged, disabled, etc.)
+    pub fn is_device_not_available(&self) -> bool {
+        match self {
+            Self::StreamIoError(_, Some(e)) => matches!(e.downcast_ref::<cpal::StreamError>(), Some(cpal::StreamError::DeviceNotAvailable)),
+            _ => false,
+        }
+    }
 }
 
 impl Display for PlayError {
@@ -45,6 +70,13 @@ impl Display for PlayError {
             Self::StreamIoError(s, _) => f.write_str(&format!("error while communicating with stream: {s}")),
             Self::PoisonedMutex(s, _) => f.write_str(&format!("error while trying to access mutex {s}")),
             Self::Unsupported(e) => f.write_str(&format!("ez_audi does not support '{}'", e)),
+            Self::SourceDoesNotExist(id) => f.write_str(&format!("the source {id:?} does not exist")),
+            Self::SoundDoesNotExist(n) => f.write_str(&format!("the sound '{n}' was not loaded")),
+            Self::VoiceDoesNotExist(id) => f.write_str(&format!("the voice {id:?} does not exist")),
+            Self::InvalidPitch(p) => f.write_str(&format!("the pitch {p} is not above 0")),
+            Self::TrackDoesNotExist(i) => f.write_str(&format!("there is no track at index {i} in the queue")),
+            Self::BandDoesNotExist(i) => f.write_str(&format!("there is no band at index {i} in the equalizer")),
+            Self::ParseError(s) => f.write_str(&format!("could not parse {s}")),
         }
     }
 }
@@ -76,6 +108,13 @@ impl error::Error for PlayError {
             },
             Self::PoisonedMutex(_, s) => Some(&**s),
             Self::Unsupported(_) => None,
+            Self::SourceDoesNotExist(_) => None,
+            Self::SoundDoesNotExist(_) => None,
+            Self::VoiceDoesNotExist(_) => None,
+            Self::InvalidPitch(_) => None,
+            Self::TrackDoesNotExist(_) => None,
+            Self::BandDoesNotExist(_) => None,
+            Self::ParseError(_) => None,
         }
     }
 }
@@ -98,6 +137,12 @@ impl From<cpal::PauseStreamError> for PlayError {
     }
 }
 
+impl From<cpal::StreamError> for PlayError {
+    fn from(value: cpal::StreamError) -> Self {
+        Self::StreamIoError("the stream ran into an error".to_string(), Some(Box::new(value)))
+    }
+}
+
 impl<T: 'static> From<PoisonError<T>> for PlayError {
     fn from(value: PoisonError<T>) -> Self {
         Self::PoisonedMutex("".to_string(), Box::new(value))
@@ -113,6 +158,8 @@ pub enum AudioSettings {
     SampleRate(u32),
     /// The number of channels
     Channels(u32),
+    /// The size of the buffers in frames
+    BufferSize(u32),
     /// If it is caused by the combinaison of more than one setting above,
     /// if two independently are unsupported than it should be reported as multiple
     /// enums
@@ -125,6 +172,7 @@ impl Display for AudioSettings {
             Self::SampleType(st) => f.write_str(&format!("the sample type {:?}", st)),
             Self::SampleRate(r) => f.write_str(&format!("the sample rate of {:?}", r)),
             Self::Channels(c) => f.write_str(&format!("the channel count of {:?}", c)),
+            Self::BufferSize(b) => f.write_str(&format!("the buffer size of {:?} frames", b)),
             Self::Combinaison => f.write_str("the combinaison of settings")
         }
     }
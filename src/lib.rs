@@ -6,6 +6,13 @@
 //! 
 //! * Read and play LPcm WAVE (.wav) files
 //! * Apply modifiers to the samples for Volume, Looping, etc..
+//! * Mix many sounds together on a single output stream
+//! * Record audio from input devices into samples or WAVE files
+//! * Monitor an input device through modifiers in real time
+//! * Render players offline without a sound card
+//! * Filter, equalize, compress and add echoes or reverb to the audio
+//! * Change the pitch or tempo of the audio without affecting the other
+//! * Fade the audio in and out and automate its volume
 //! * Control over the raw audio samples
 //! * Get audio file metadata
 //! 
@@ -49,21 +56,26 @@ mod cpal_abstraction;
 mod wav;
 mod errors;
 mod traits;
+mod recorder;
+mod monitor;
 
 use errors::Error;
 
 pub use errors::PlayError;
-pub use cpal_abstraction::{Device, Stream};
+pub use cpal_abstraction::{Device, OfflineDevice, Host, DeviceId, BufferSize, Stream, StreamClock, DeviceCapabilities, ConfigRange, DefaultConfig, StreamDirection};
+pub use recorder::Recorder;
+pub use monitor::Monitor;
 
 pub mod samples_player;
-pub use samples_player::SamplesPlayer;
+pub use samples_player::{SamplesPlayer, Mixer, VoicePool, QueuePlayer};
 pub use samples_player::modifiers;
+pub use samples_player::resampler;
 
 pub mod audio_files {
     //! Functions and structs for dealing with audio files and their audio_codecs
 
     use crate::wav;
-    pub use wav::WavAudio;
+    pub use wav::{WavAudio, WavWriter};
     pub use wav::file_is_wav;
     use crate::audio_codecs;
     pub use audio_codecs::{AudioCodec, AudioCodecTrait};
@@ -73,7 +85,7 @@ pub mod samples {
     //! Functions and structs for closely working with samples 
 
     use crate::cpal_abstraction;
-    pub use cpal_abstraction::{Sample, IntermediateSampleType, Samples, SampleType};
+    pub use cpal_abstraction::{Sample, IntermediateSampleType, Samples, SamplesMetadata, SampleType, StreamSource, StreamSink};
 }
 
 pub mod public_traits {
@@ -84,7 +96,7 @@ pub mod public_traits {
     pub use traits::{AudioFileTrait, AudioMetadataTrait};
     pub use crate::audio_codecs::AudioCodecTrait;
     use crate::cpal_abstraction;
-    pub use cpal_abstraction::SamplesTrait;
+    pub use cpal_abstraction::{SamplesTrait, StreamSource, StreamSink};
     use crate::samples_player;
     pub use samples_player::SamplesPlayerTrait;
     use crate::modifiers;
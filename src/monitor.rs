@@ -0,0 +1,319 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use crate::cpal_abstraction::{Device, Stream, SamplesMetadata, SampleType, IntermediateSampleType, StreamSink, StreamSource};
+use crate::errors::{Error, PlayError};
+use crate::modifiers::ModifierTrait;
+
+/// Default amount of audio kept in the ring buffer before playing
+const DEFAULT_TARGET_LATENCY: Duration = Duration::from_millis(20);
+/// Default maximum amount of audio in the ring buffer before dropping some
+const DEFAULT_MAX_LATENCY: Duration = Duration::from_millis(100);
+
+/// Holds the captured audio until the output plays it.
+/// The input and output devices never run at exactly the same rate, so the buffer either fills up
+/// (then it drops audio to get back to the target) or empties (then it waits to be filled to the target)
+struct RingBuffer {
+    channels: usize,
+    samples: VecDeque<IntermediateSampleType>,
+    /// Frames to buffer before playing
+    target_frames: usize,
+    /// Frames at which audio starts being dropped
+    max_frames: usize,
+    /// False while filling up to the target, the output is silent in the meantime
+    primed: bool,
+    underruns: u64,
+    overruns: u64,
+}
+
+impl RingBuffer {
+    fn buffered_frames(&self) -> usize {
+        self.samples.len() / self.channels
+    }
+
+    fn push(&mut self, block: &[IntermediateSampleType]) {
+        self.samples.extend(block);
+
+        if self.buffered_frames() > self.max_frames {
+            // Drops the oldest frames to get back to the target latency
+            let extra_frames = self.buffered_frames() - self.target_frames;
+            self.samples.drain(..(extra_frames * self.channels));
+            self.overruns += 1;
+        }
+
+        if self.buffered_frames() >= self.target_frames {
+            self.primed = true;
+        }
+    }
+}
+
+impl StreamSource for RingBuffer {
+    fn fill_buffer(&mut self, buffer: &mut [IntermediateSampleType]) {
+        if !self.primed {
+            return
+        }
+
+        if self.samples.len() < buffer.len() {
+            // Plays what is left and waits to be filled up again
+            self.underruns += 1;
+            self.primed = false;
+        }
+
+        let available = buffer.len().min(self.samples.len());
+        for (sample, buffered) in buffer.iter_mut().zip(self.samples.drain(..available)) {
+            *sample = buffered;
+        }
+    }
+}
+
+/// Receives the captured audio, passes it through the modifiers and hands it to the ring buffer
+struct MonitorInput {
+    metadata: SamplesMetadata,
+    modifiers: Vec<Box<dyn ModifierTrait + Send>>,
+    ring_buffer: Arc<Mutex<RingBuffer>>,
+    /// Buffer for the block being modified
+    block: Vec<IntermediateSampleType>,
+}
+
+impl StreamSink for MonitorInput {
+    fn consume_buffer(&mut self, buffer: &[IntermediateSampleType]) {
+        self.block.clear();
+        self.block.extend_from_slice(buffer);
+
+        for modifier in self.modifiers.iter_mut() {
+            modifier.process_block(&mut self.block, &self.metadata);
+        }
+
+        // The output will play silence until the lock is usable again, nothing else to do here
+        if let Ok(mut ring_buffer) = self.ring_buffer.lock() {
+            ring_buffer.push(&self.block);
+        }
+    }
+}
+
+/// Plays what an input device captures on an output device in real time, through modifiers.
+/// A ring buffer between both devices absorbs the difference of their clocks, its size sets the latency.
+/// Keep the monitor in scope to keep it running
+pub struct Monitor {
+    /// The channel count and sample rate the audio is processed at
+    metadata: SamplesMetadata,
+    input: Arc<Mutex<MonitorInput>>,
+    ring_buffer: Arc<Mutex<RingBuffer>>,
+    input_stream: Option<Stream>,
+    output_stream: Option<Stream>,
+}
+
+impl Monitor {
+    /// Creates a new Monitor processing the audio at the channel count and sample rate.
+    /// The devices are converted to it if they do not match
+    pub fn new(channels: u16, sample_rate: u32) -> Monitor {
+        let metadata = SamplesMetadata::new(channels.max(1), sample_rate, SampleType::F32);
+
+        let ring_buffer = RingBuffer {
+            channels: channels.max(1) as usize,
+            samples: VecDeque::new(),
+            target_frames: duration_to_frames(DEFAULT_TARGET_LATENCY, sample_rate),
+            max_frames: duration_to_frames(DEFAULT_MAX_LATENCY, sample_rate),
+            primed: false,
+            underruns: 0,
+            overruns: 0,
+        };
+        let ring_buffer = Arc::new(Mutex::new(ring_buffer));
+
+        let input = MonitorInput {
+            metadata: metadata.clone(),
+            modifiers: Vec::new(),
+            ring_buffer: Arc::clone(&ring_buffer),
+            block: Vec::new(),
+        };
+
+        Monitor {
+            metadata,
+            input: Arc::new(Mutex::new(input)),
+            ring_buffer,
+            input_stream: None,
+            output_stream: None,
+        }
+    }
+
+    fn lock_input(&self) -> Error<MutexGuard<'_, MonitorInput>> {
+        self.input.lock()
+            .map_err(|e| PlayError::PoisonedMutex("monitor input".to_string(), e.to_string().into()))
+    }
+
+    fn lock_ring_buffer(&self) -> Error<MutexGuard<'_, RingBuffer>> {
+        self.ring_buffer.lock()
+            .map_err(|e| PlayError::PoisonedMutex("monitor ring buffer".to_string(), e.to_string().into()))
+    }
+
+    /// Returns the metadata the audio is processed at
+    pub fn metadata(&self) -> &SamplesMetadata {
+        &self.metadata
+    }
+
+    /// Adds a modifier at the end of the chain, the modifiers are applied with `ModifierTrait::process_block`
+    pub fn add_modifier(&mut self, modifier: Box<dyn ModifierTrait + Send>) -> Error<()> {
+        self.lock_input()?.modifiers.push(modifier);
+
+        Ok(())
+    }
+
+    /// Removes all the modifiers
+    pub fn clear_modifiers(&mut self) -> Error<()> {
+        self.lock_input()?.modifiers.clear();
+
+        Ok(())
+    }
+
+    /// Sets how much audio is buffered before playing (the added latency) and how much can be buffered
+    /// before dropping audio. A bigger gap between both handles more clock drift and jitter between the devices
+    pub fn set_ring_buffer(&mut self, target_latency: Duration, max_latency: Duration) -> Error<()> {
+        let target_frames = duration_to_frames(target_latency, self.metadata.sample_rate);
+        let max_frames = duration_to_frames(max_latency, self.metadata.sample_rate).max(target_frames);
+
+        let mut ring_buffer = self.lock_ring_buffer()?;
+        ring_buffer.target_frames = target_frames;
+        ring_buffer.max_frames = max_frames;
+
+        Ok(())
+    }
+
+    /// Returns the amount of audio waiting in the ring buffer
+    pub fn buffered_latency(&self) -> Error<Duration> {
+        let frames = self.lock_ring_buffer()?.buffered_frames();
+
+        Ok(frames_to_duration(frames, self.metadata.sample_rate))
+    }
+
+    /// Returns the total time between a sound reaching the input device and it being heard on the output device,
+    /// as far as the hosts report their latency
+    pub fn latency(&self) -> Error<Duration> {
+        let stream_latency = |stream: &Option<Stream>| stream.as_ref()
+            .map(|s| s.clock().latency())
+            .unwrap_or(Duration::ZERO);
+
+        Ok(stream_latency(&self.input_stream) + self.buffered_latency()? + stream_latency(&self.output_stream))
+    }
+
+    /// Returns how many times the output ran out of audio and how many times audio was dropped
+    /// because the input was ahead, in that order
+    pub fn dropouts(&self) -> Error<(u64, u64)> {
+        let ring_buffer = self.lock_ring_buffer()?;
+
+        Ok((ring_buffer.underruns, ring_buffer.overruns))
+    }
+
+    /// Continues the monitoring
+    pub fn start(&self) -> Error<()> {
+        if let Some(s) = &self.input_stream {
+            s.start()?;
+        }
+        if let Some(s) = &self.output_stream {
+            s.start()?;
+        }
+
+        Ok(())
+    }
+
+    /// Pauses the monitoring, what was buffered is forgotten so that it does not play late when starting again
+    pub fn stop(&self) -> Error<()> {
+        if let Some(s) = &self.input_stream {
+            s.stop()?;
+        }
+        if let Some(s) = &self.output_stream {
+            s.stop()?;
+        }
+
+        let mut ring_buffer = self.lock_ring_buffer()?;
+        ring_buffer.samples.clear();
+        ring_buffer.primed = false;
+
+        Ok(())
+    }
+
+    /// Returns the errors the input and output streams ran into since the last call, see `Stream::take_errors`
+    pub fn take_stream_errors(&self) -> Vec<PlayError> {
+        [&self.input_stream, &self.output_stream].into_iter()
+            .flatten()
+            .flat_map(|s| s.take_errors())
+            .collect()
+    }
+
+    /// Starts capturing on the input device and playing on the output device
+    pub fn monitor_on_devices(&mut self, input: Device, output: Device) -> Error<()> {
+        // Drops the old streams first so they don't keep writing into the buffer
+        self.input_stream = None;
+        self.output_stream = None;
+        self.lock_input()?.modifiers.iter_mut().for_each(|m| m.reset());
+
+        let output_stream = output.create_source_stream(&self.metadata, Arc::clone(&self.ring_buffer))?;
+        let input_stream = input.create_input_stream(&self.metadata, Arc::clone(&self.input))?;
+
+        self.output_stream = Some(output_stream);
+        self.input_stream = Some(input_stream);
+
+        self.stop()?;
+        self.start()
+    }
+
+    /// Starts capturing on the default input device and playing on the default output device of the default host
+    pub fn monitor_on_default(&mut self) -> Error<()> {
+        let input = match Device::default_input() {
+            Some(i) => i,
+            None => return Err(PlayError::DeviceDoesNotExist { name : "default".to_string() }),
+        };
+        let output = match Device::default_output() {
+            Some(o) => o,
+            None => return Err(PlayError::DeviceDoesNotExist { name : "default".to_string() }),
+        };
+
+        self.monitor_on_devices(input, output)
+    }
+}
+
+fn duration_to_frames(duration: Duration, sample_rate: u32) -> usize {
+    (duration.as_secs_f64() * sample_rate as f64).round() as usize
+}
+
+fn frames_to_duration(frames: usize, sample_rate: u32) -> Duration {
+    if sample_rate == 0 {
+        return Duration::ZERO
+    }
+
+    Duration::from_secs_f64(frames as f64 / sample_rate as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modifiers::Volume;
+
+    #[test]
+    fn input_goes_through_modifiers_to_output() {
+        let mut monitor = Monitor::new(1, 1000);
+        monitor.set_ring_buffer(Duration::from_millis(4), Duration::from_millis(10)).unwrap();
+        monitor.add_modifier(Box::new(Volume(0.5))).unwrap();
+
+        let mut output = [0.0; 4];
+        monitor.ring_buffer.lock().unwrap().fill_buffer(&mut output);
+        assert_eq!(output, [0.0; 4]);
+
+        monitor.input.lock().unwrap().consume_buffer(&[1.0; 4]);
+        monitor.ring_buffer.lock().unwrap().fill_buffer(&mut output);
+        assert_eq!(output, [0.5; 4]);
+    }
+
+    #[test]
+    fn ring_buffer_drops_audio_when_input_is_ahead() {
+        let mut monitor = Monitor::new(1, 1000);
+        monitor.set_ring_buffer(Duration::from_millis(4), Duration::from_millis(10)).unwrap();
+
+        for _ in 0..3 {
+            monitor.input.lock().unwrap().consume_buffer(&[1.0; 4]);
+        }
+
+        assert_eq!(monitor.buffered_latency().unwrap(), Duration::from_millis(4));
+        assert_eq!(monitor.dropouts().unwrap(), (0, 1));
+    }
+}
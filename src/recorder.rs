@@ -0,0 +1,136 @@
+use std::io::{Write, Seek};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::cpal_abstraction::{Device, Stream, Samples, SamplesMetadata, Sample, IntermediateSampleType, StreamSink, StreamClock};
+use crate::errors::{Error, PlayError};
+use crate::wav::WavWriter;
+
+/// Captures audio from an input device and hands it to a sink.
+/// The sink can be a `Samples` (see `Recorder::in_memory`), a `WavWriter`, a closure receiving each block
+/// or anything implementing `StreamSink`. Keep the recorder in scope to keep recording
+pub struct Recorder<S: StreamSink> {
+    /// The metadata the captured samples are converted to
+    metadata: SamplesMetadata,
+    sink: Arc<Mutex<S>>,
+    stream: Option<Stream>,
+}
+
+impl<S: StreamSink> Recorder<S> {
+    /// Creates a new Recorder, the captured samples will be converted to the channel count and sample rate of the metadata
+    pub fn new(metadata: SamplesMetadata, sink: S) -> Recorder<S> {
+        Recorder {
+            metadata,
+            sink: Arc::new(Mutex::new(sink)),
+            stream: None,
+        }
+    }
+
+    fn lock_sink(&self) -> Error<MutexGuard<'_, S>> {
+        self.sink.lock()
+            .map_err(|e| PlayError::PoisonedMutex("recorder sink".to_string(), e.to_string().into()))
+    }
+
+    /// Returns the metadata of the captured samples
+    pub fn metadata(&self) -> &SamplesMetadata {
+        &self.metadata
+    }
+
+    /// Gives access to the sink, the recording waits while the closure runs so keep it short
+    pub fn with_sink<R>(&self, f: impl FnOnce(&mut S) -> R) -> Error<R> {
+        let mut sink = self.lock_sink()?;
+
+        Ok(f(&mut sink))
+    }
+
+    /// Returns the frame clock of the input stream, None if the recorder is not recording
+    pub fn clock(&self) -> Option<StreamClock> {
+        self.stream.as_ref().map(|s| s.clock())
+    }
+
+    /// Continues the recording
+    pub fn start(&self) -> Error<()> {
+        let stream = match &self.stream {
+            Some(s) => s,
+            None => return Ok(()), // No stream to start
+        };
+
+        stream.start()
+    }
+
+    /// Pauses the recording
+    pub fn stop(&self) -> Error<()> {
+        let stream = match &self.stream {
+            Some(s) => s,
+            None => return Ok(()), // No stream to stop
+        };
+
+        stream.stop()
+    }
+
+    /// Returns the errors the stream ran into since the last call, see `Stream::take_errors`
+    pub fn take_stream_errors(&self) -> Vec<PlayError> {
+        match &self.stream {
+            Some(s) => s.take_errors(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Starts recording on a device
+    pub fn record_on_device(&mut self, device: Device) -> Error<()> {
+        let stream = device.create_input_stream(&self.metadata, Arc::clone(&self.sink))?;
+
+        // Makes sure that the stream is started
+        stream.start()?;
+
+        self.stream = Some(stream);
+
+        Ok(())
+    }
+
+    /// Starts recording on the default input device of the default host
+    pub fn record_on_default(&mut self) -> Error<()> {
+        let default_input = match Device::default_input() {
+            Some(i) => i,
+            None => return Err(PlayError::DeviceDoesNotExist { name : "default".to_string() }),
+        };
+
+        self.record_on_device(default_input)
+    }
+
+    /// Stops recording and gives back the sink
+    pub fn into_sink(mut self) -> Error<S> {
+        // Dropping the stream drops the callback and so its handle to the sink
+        self.stream = None;
+
+        let sink = match Arc::try_unwrap(self.sink) {
+            Ok(s) => s,
+            Err(_) => return Err(PlayError::StreamIoError("the input stream still holds the sink".to_string(), None)),
+        };
+
+        sink.into_inner()
+            .map_err(|e| PlayError::PoisonedMutex("recorder sink".to_string(), e.to_string().into()))
+    }
+}
+
+impl Recorder<Samples<IntermediateSampleType>> {
+    /// Creates a new Recorder that keeps the captured samples in memory
+    pub fn in_memory(metadata: SamplesMetadata) -> Recorder<Samples<IntermediateSampleType>> {
+        let samples = Samples::new(Vec::new(), metadata.clone());
+
+        Recorder::new(metadata, samples)
+    }
+
+    /// Takes the samples captured so far in the sample type T, the recording keeps going
+    pub fn take_samples<T: Sample>(&self) -> Error<Samples<T>> {
+        let samples = std::mem::take(&mut self.lock_sink()?.samples);
+
+        Ok(Samples::new(samples, self.metadata.clone()).into_t_samples::<T>())
+    }
+}
+
+impl<W: Write + Seek + Send + 'static> Recorder<WavWriter<W>> {
+    /// Stops recording and finishes the WAVE file
+    pub fn finish(self) -> Error<W> {
+        self.into_sink()?.finish()
+    }
+}
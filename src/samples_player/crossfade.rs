@@ -0,0 +1,152 @@
+use std::f32::consts::FRAC_PI_2;
+use std::time::Duration;
+
+use crate::cpal_abstraction::{Samples, IntermediateSampleType};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// The shape of the volume change of a fade
+pub enum FadeCurve {
+    /// The volume changes at a constant rate, dips in loudness in the middle of a crossfade
+    Linear,
+    /// Keeps the perceived loudness constant during a crossfade (sine/cosine)
+    EqualPower,
+    /// Starts and ends slowly, changes quickly in the middle (smoothstep)
+    SCurve,
+    /// Changes slowly then quickly, even in decibels. Fades out quickly then trails off like a natural decay
+    Exponential,
+    /// Changes quickly then slowly, the opposite of `Exponential`
+    Logarithmic,
+}
+
+impl FadeCurve {
+    /// Returns the gain of a sound fading in, the progress goes from 0.0 (start of the fade) to 1.0 (end of the fade)
+    pub fn fade_in_gain(&self, progress: IntermediateSampleType) -> IntermediateSampleType {
+        let progress = progress.clamp(0.0, 1.0);
+
+        match self {
+            FadeCurve::Linear => progress,
+            FadeCurve::EqualPower => (progress * FRAC_PI_2).sin(),
+            FadeCurve::SCurve => progress * progress * (3.0 - 2.0 * progress),
+            // Goes over 60 dB, moved down so that it starts at silence
+            FadeCurve::Exponential => (1000f32.powf(progress) - 1.0) / 999.0,
+            FadeCurve::Logarithmic => (1.0 + 999.0 * progress).log10() / 3.0,
+        }
+    }
+
+    /// Returns the gain of a sound fading out, the progress goes from 0.0 (start of the fade) to 1.0 (end of the fade)
+    pub fn fade_out_gain(&self, progress: IntermediateSampleType) -> IntermediateSampleType {
+        self.fade_in_gain(1.0 - progress)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// A transition where a sound fades out while the next one fades in
+pub struct Crossfade {
+    /// How long both sounds overlap
+    pub duration: Duration,
+    /// The shape of the fade of both sounds
+    pub curve: FadeCurve,
+}
+
+impl Crossfade {
+    /// Creates a new Crossfade
+    pub fn new(duration: Duration, curve: FadeCurve) -> Crossfade {
+        Crossfade {
+            duration,
+            curve,
+        }
+    }
+
+    /// Returns the duration of the crossfade in frames (one sample per channel)
+    pub fn frames(&self, sample_rate: u32) -> usize {
+        (self.duration.as_secs_f64() * sample_rate as f64).round() as usize
+    }
+}
+
+/// A crossfade in progress inside of a player's stream, holds the sound that is fading out
+pub(super) struct CrossfadeState {
+    fading_out: Samples<IntermediateSampleType>,
+    /// Index of the next sample to be played in `fading_out`
+    index: usize,
+    /// Number of frames of the crossfade that were already played
+    elapsed: usize,
+    /// Total number of frames of the crossfade
+    length: usize,
+    curve: FadeCurve,
+}
+
+impl CrossfadeState {
+    /// Starts fading out the samples from the index, the crossfade is shortened to not go past the end of the samples
+    pub(super) fn new(fading_out: Samples<IntermediateSampleType>, index: usize, crossfade: &Crossfade) -> CrossfadeState {
+        let channels = fading_out.metadata.channels.max(1) as usize;
+        let remaining_frames = fading_out.samples.len().saturating_sub(index) / channels;
+        let length = crossfade.frames(fading_out.metadata.sample_rate).min(remaining_frames);
+
+        CrossfadeState {
+            fading_out,
+            index,
+            elapsed: 0,
+            length,
+            curve: crossfade.curve,
+        }
+    }
+
+    /// Mixes the sound fading out into the frame of the sound fading in.
+    /// Returns false once the crossfade is over
+    pub(super) fn mix_frame(&mut self, frame: &mut [IntermediateSampleType]) -> bool {
+        if self.is_over() {
+            return false
+        }
+
+        let progress = self.elapsed as IntermediateSampleType / self.length as IntermediateSampleType;
+        let gain_in = self.curve.fade_in_gain(progress);
+        let gain_out = self.curve.fade_out_gain(progress);
+
+        for sample in frame.iter_mut() {
+            let fading_sample = self.fading_out.samples.get(self.index).copied().unwrap_or(0.0);
+            *sample = *sample * gain_in + fading_sample * gain_out;
+            self.index += 1;
+        }
+        self.elapsed += 1;
+
+        !self.is_over()
+    }
+
+    fn is_over(&self) -> bool {
+        self.elapsed >= self.length
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpal_abstraction::{SamplesMetadata, SampleType};
+
+    #[test]
+    fn equal_power_keeps_power_constant() {
+        for i in 0..=10 {
+            let progress = i as f32 / 10.0;
+            let gain_in = FadeCurve::EqualPower.fade_in_gain(progress);
+            let gain_out = FadeCurve::EqualPower.fade_out_gain(progress);
+
+            assert!((gain_in * gain_in + gain_out * gain_out - 1.0).abs() < 0.0001);
+        }
+    }
+
+    #[test]
+    fn crossfade_blends_both_sounds() {
+        let fading_out = Samples::new(vec![1.0; 100], SamplesMetadata::new(1, 100, SampleType::F32));
+        let crossfade = Crossfade::new(Duration::from_millis(500), FadeCurve::Linear);
+        let mut state = CrossfadeState::new(fading_out, 0, &crossfade);
+
+        let mut first_frame = [0.0];
+        assert!(state.mix_frame(&mut first_frame));
+        assert_eq!(first_frame[0], 1.0);
+
+        let mut frames_left = 1;
+        while state.mix_frame(&mut [0.0]) {
+            frames_left += 1;
+        }
+        assert_eq!(frames_left, 49);
+    }
+}
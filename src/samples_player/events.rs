@@ -0,0 +1,78 @@
+use crate::{Device, Error, PlayError};
+
+#[derive(Debug)]
+/// Something that happened to the stream of a player, returned by `poll_events`
+pub enum PlayerEvent {
+    /// The stream ran into an error that did not stop it
+    StreamError(PlayError),
+    /// The device the player was playing on is not available anymore (unplugged, disabled, etc.)
+    DeviceLost,
+    /// The player now plays on the default output, from where it was on the lost device
+    SwitchedDevice {
+        /// Name of the new device, if it has one
+        name: Option<String>,
+    },
+    /// The player could not be moved to the default output, it stays silent until played on another device
+    FailoverFailed(PlayError),
+}
+
+/// Turns the errors of a stream into events and, if the device was lost and failover is on,
+/// plays on the default output with `play_on_device`
+pub(crate) fn handle_stream_errors(errors: Vec<PlayError>, failover: bool, play_on_device: impl FnOnce(Device) -> Error<()>) -> Vec<PlayerEvent> {
+    let mut events = Vec::new();
+    let mut device_lost = false;
+
+    for error in errors {
+        if error.is_device_not_available() {
+            device_lost = true;
+        } else {
+            events.push(PlayerEvent::StreamError(error));
+        }
+    }
+
+    if !device_lost {
+        return events
+    }
+    events.push(PlayerEvent::DeviceLost);
+
+    if !failover {
+        return events
+    }
+
+    let device = match Device::default_output() {
+        Some(d) => d,
+        None => {
+            events.push(PlayerEvent::FailoverFailed(PlayError::DeviceDoesNotExist { name: "default".to_string() }));
+            return events
+        },
+    };
+
+    let name = device.name();
+    match play_on_device(device) {
+        Ok(_) => events.push(PlayerEvent::SwitchedDevice { name }),
+        Err(e) => events.push(PlayerEvent::FailoverFailed(e)),
+    }
+
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn other_errors_do_not_trigger_failover() {
+        let errors = vec![cpal::StreamError::BackendSpecific { err: cpal::BackendSpecificError { description: "xrun".to_string() } }.into()];
+
+        let events = handle_stream_errors(errors, true, |_| panic!("should not fail over"));
+        assert!(matches!(events[..], [PlayerEvent::StreamError(_)]));
+    }
+
+    #[test]
+    fn lost_device_is_reported_without_failover() {
+        let errors = vec![cpal::StreamError::DeviceNotAvailable.into()];
+
+        let events = handle_stream_errors(errors, false, |_| panic!("should not fail over"));
+        assert!(matches!(events[..], [PlayerEvent::DeviceLost]));
+    }
+}
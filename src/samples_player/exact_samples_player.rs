@@ -1,6 +1,7 @@
 use std::sync::{Mutex, MutexGuard, Arc};
+use std::time::Duration;
 
-use crate::{Device, traits::AudioMetadataTrait, cpal_abstraction, Error, modifiers::ModifierTrait};
+use crate::{Device, traits::AudioMetadataTrait, cpal_abstraction, Error, PlayError, modifiers::ModifierTrait};
 
 use cpal_abstraction::{Sample, Samples, SamplesTrait, IntermediateSampleType};
 
@@ -13,6 +14,8 @@ pub struct ExactSamplesPlayer<T: Sample> {
     modifiers: Vec<Box<dyn ModifierTrait>>,
     samples_with_modifiers: Option<Arc<Mutex<Samples<T>>>>,
     stream: Option<cpal_abstraction::Stream>,
+    /// Index the stream started playing from, so that failing over to a new stream resumes where the old one was
+    start_index: usize,
 }
 
 impl<T: Sample> ExactSamplesPlayer<T>
@@ -24,6 +27,7 @@ where IntermediateSampleType: cpal::FromSample<T> {
             modifiers: Vec::new(),
             samples_with_modifiers: None,
             stream: None,
+            start_index: 0,
         }
     }
 
@@ -71,6 +75,28 @@ where IntermediateSampleType: cpal::FromSample<T> {
 
         self.stream = Some(stream);
     }
+
+    /// Plays on the device from the index in the samples
+    fn play_from_index(&mut self, device: Device, start_index: usize) -> Error<()> {
+        // Makes sure that there is a Sample in self.samples_with_modifiers
+        self.apply_modifiers();
+
+        let samples_arc = self.samples_with_modifiers
+            .as_ref()
+            .expect("no samples with modifiers");
+        let samples_arc = Arc::clone(samples_arc);
+
+        let stream = device.create_stream_from_index(&self.original_samples.metadata,
+            samples_arc, start_index)?;
+
+        // Makes sure that the stream is started
+        stream.start()?;
+
+        self.start_index = start_index;
+        self.set_stream(stream);
+
+        Ok(())
+    }
 }
 
 impl<T: Sample> SamplesPlayerTrait for ExactSamplesPlayer<T>
@@ -109,23 +135,32 @@ where IntermediateSampleType: cpal::FromSample<T> {
         stream.stop()
     }
 
-    fn play_on_device(&mut self, device: Device) -> Error<()> {
-        // Makes sure that there is a Sample in self.samples_with_modifiers
-        self.apply_modifiers();
+    fn take_stream_errors(&self) -> Vec<PlayError> {
+        match &self.stream {
+            Some(s) => s.take_errors(),
+            None => Vec::new(),
+        }
+    }
 
-        let samples_arc = self.samples_with_modifiers
-            .as_ref()
-            .expect("no samples with modifiers");
-        let samples_arc = Arc::clone(samples_arc);
+    fn latency(&self) -> Option<Duration> {
+        self.stream.as_ref().and_then(|s| s.latency())
+    }
 
-        let stream = device.create_stream(&self.original_samples.metadata,
-            samples_arc)?;
+    fn play_on_device(&mut self, device: Device) -> Error<()> {
+        self.play_from_index(device, 0)
+    }
 
-        // Makes sure that the stream is started
-        stream.start()?;
+    fn resume_on_device(&mut self, device: Device) -> Error<()> {
+        let mut start_index = self.start_index;
+        if let Some(old_stream) = &self.stream {
+            let metadata = &self.original_samples.metadata;
+            let clock = old_stream.clock();
 
-        self.set_stream(stream);
+            // The clock counts the frames of the device, which can play at another sample rate than the samples
+            let frames = clock.frames() as f64 * metadata.sample_rate as f64 / clock.sample_rate().max(1) as f64;
+            start_index += frames.round() as usize * metadata.channels as usize;
+        }
 
-        Ok(())
+        self.play_from_index(device, start_index)
     }
 }
\ No newline at end of file
@@ -0,0 +1,163 @@
+use std::f64::consts::PI;
+use std::ops::{Add, AddAssign, Mul, Sub};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+/// A complex number, only what the FFT and the modifiers using it need
+pub(crate) struct Complex {
+    pub(crate) re: f32,
+    pub(crate) im: f32,
+}
+
+impl Complex {
+    pub(crate) fn new(re: f32, im: f32) -> Complex {
+        Complex { re, im }
+    }
+
+    pub(crate) fn from_polar(magnitude: f32, phase: f32) -> Complex {
+        Complex::new(magnitude * phase.cos(), magnitude * phase.sin())
+    }
+
+    pub(crate) fn magnitude(&self) -> f32 {
+        self.re.hypot(self.im)
+    }
+
+    pub(crate) fn phase(&self) -> f32 {
+        self.im.atan2(self.re)
+    }
+
+    pub(crate) fn conj(&self) -> Complex {
+        Complex::new(self.re, -self.im)
+    }
+}
+
+impl Add for Complex {
+    type Output = Complex;
+
+    fn add(self, rhs: Complex) -> Complex {
+        Complex::new(self.re + rhs.re, self.im + rhs.im)
+    }
+}
+
+impl AddAssign for Complex {
+    fn add_assign(&mut self, rhs: Complex) {
+        self.re += rhs.re;
+        self.im += rhs.im;
+    }
+}
+
+impl Sub for Complex {
+    type Output = Complex;
+
+    fn sub(self, rhs: Complex) -> Complex {
+        Complex::new(self.re - rhs.re, self.im - rhs.im)
+    }
+}
+
+impl Mul for Complex {
+    type Output = Complex;
+
+    fn mul(self, rhs: Complex) -> Complex {
+        Complex::new(self.re * rhs.re - self.im * rhs.im, self.re * rhs.im + self.im * rhs.re)
+    }
+}
+
+#[derive(Debug, Clone)]
+/// An iterative radix-2 FFT of a fixed size, the twiddles and the bit reversal are computed once
+pub(crate) struct Fft {
+    size: usize,
+    twiddles: Vec<Complex>,
+    bit_reversed: Vec<usize>,
+}
+
+impl Fft {
+    /// Creates an FFT of the size, rounded up to a power of two
+    pub(crate) fn new(size: usize) -> Fft {
+        let size = size.max(2).next_power_of_two();
+        let bits = size.trailing_zeros();
+
+        let twiddles = (0..size / 2)
+            .map(|i| {
+                let angle = -2.0 * PI * i as f64 / size as f64;
+                Complex::new(angle.cos() as f32, angle.sin() as f32)
+            })
+            .collect();
+
+        let bit_reversed = (0..size)
+            .map(|i| i.reverse_bits() >> (usize::BITS - bits))
+            .collect();
+
+        Fft { size, twiddles, bit_reversed }
+    }
+
+    fn transform(&self, buffer: &mut [Complex], inverse: bool) {
+        assert_eq!(buffer.len(), self.size, "the buffer is not the size of the FFT");
+
+        for (i, j) in self.bit_reversed.iter().enumerate() {
+            if i < *j {
+                buffer.swap(i, *j);
+            }
+        }
+
+        let mut len = 2;
+        while len <= self.size {
+            let step = self.size / len;
+            for start in (0..self.size).step_by(len) {
+                for k in 0..len / 2 {
+                    let mut twiddle = self.twiddles[k * step];
+                    if inverse {
+                        twiddle.im = -twiddle.im;
+                    }
+
+                    let even = buffer[start + k];
+                    let odd = buffer[start + k + len / 2] * twiddle;
+                    buffer[start + k] = even + odd;
+                    buffer[start + k + len / 2] = even - odd;
+                }
+            }
+            len *= 2;
+        }
+    }
+
+    /// Transforms the signal into its spectrum in place
+    pub(crate) fn forward(&self, buffer: &mut [Complex]) {
+        self.transform(buffer, false);
+    }
+
+    /// Transforms the spectrum back into the signal in place, scaled so that it round trips
+    pub(crate) fn inverse(&self, buffer: &mut [Complex]) {
+        self.transform(buffer, true);
+
+        let scale = 1.0 / self.size as f32;
+        for c in buffer.iter_mut() {
+            c.re *= scale;
+            c.im *= scale;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_the_dft_and_round_trips() {
+        let fft = Fft::new(8);
+        let signal = [1.0, 2.0, 0.0, -1.0, 0.5, 0.0, 3.0, -2.0].map(|s| Complex::new(s, 0.0));
+
+        let mut spectrum = signal;
+        fft.forward(&mut spectrum);
+
+        for (k, bin) in spectrum.iter().enumerate() {
+            let expected = signal.iter().enumerate().fold(Complex::default(), |sum, (n, s)| {
+                let angle = -2.0 * std::f32::consts::PI * (k * n) as f32 / 8.0;
+                sum + *s * Complex::new(angle.cos(), angle.sin())
+            });
+            assert!((bin.re - expected.re).abs() < 1e-4 && (bin.im - expected.im).abs() < 1e-4);
+        }
+
+        fft.inverse(&mut spectrum);
+        for (s, original) in spectrum.iter().zip(&signal) {
+            assert!((s.re - original.re).abs() < 1e-5 && s.im.abs() < 1e-5);
+        }
+    }
+}
@@ -0,0 +1,535 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::{Device, Error, PlayError, traits::AudioMetadataTrait, cpal_abstraction, modifiers::{ModifierTrait, utils}};
+
+use cpal_abstraction::{Sample, Samples, SamplesTrait, SamplesMetadata, SampleType, IntermediateSampleType, StreamSource, StreamClock};
+
+use super::{PlayerEvent, handle_stream_errors};
+
+/// Default level above which the limiter of the mixer starts to softly compress the mix
+pub(super) const DEFAULT_LIMITER_THRESHOLD: IntermediateSampleType = 0.8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// Identifies a source inside of a `Mixer`
+pub struct SourceId(u64);
+
+/// The part of a source that is used by the audio thread
+struct MixingSource {
+    id: SourceId,
+    samples: Samples<IntermediateSampleType>,
+    /// Index of the next sample to be played in `samples`
+    index: usize,
+    gain: IntermediateSampleType,
+    pan: IntermediateSampleType,
+    /// Frame of the mixer at which the source starts playing
+    start_frame: u64,
+    /// Frame of the mixer at which the source stops playing
+    stop_frame: Option<u64>,
+    /// The source reached its stop frame
+    stopped: bool,
+}
+
+impl MixingSource {
+    fn is_finished(&self) -> bool {
+        self.stopped || self.index >= self.samples.samples.len()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// When a scheduled action happens in a `Mixer`
+pub enum ScheduleTime {
+    /// As soon as possible
+    Now,
+    /// At a frame of the mixer, see `Mixer::frame`
+    Frame(u64),
+    /// When the output is heard at that instant, compensating for the latency of the stream.
+    /// The mixer needs to be playing on a stream to use this
+    Instant(Instant),
+}
+
+/// The part of a source that stays with the mixer, used to reapply the modifiers
+struct SourceControl {
+    original_samples: Samples<IntermediateSampleType>,
+    modifiers: Vec<Box<dyn ModifierTrait>>,
+}
+
+impl SourceControl {
+    fn samples_with_modifiers(&self) -> Samples<IntermediateSampleType> {
+        let mut modified_samples = self.original_samples.clone();
+        for modifier in &self.modifiers {
+            modified_samples = modifier.modify(modified_samples);
+        }
+
+        modified_samples
+    }
+}
+
+/// The state shared between the `Mixer` and its stream
+struct MixerState {
+    channels: usize,
+    /// Number of frames mixed so far
+    frame: u64,
+    sources: Vec<MixingSource>,
+    master_volume: IntermediateSampleType,
+    limiter_threshold: IntermediateSampleType,
+}
+
+impl MixerState {
+    fn source_mut(&mut self, id: SourceId) -> Option<&mut MixingSource> {
+        self.sources.iter_mut().find(|s| s.id == id)
+    }
+}
+
+/// Softly compresses everything above the threshold so that the output never goes past 1.0
+pub(super) fn soft_limit(sample: IntermediateSampleType, threshold: IntermediateSampleType) -> IntermediateSampleType {
+    let amplitude = sample.abs();
+    if amplitude <= threshold {
+        return sample
+    }
+
+    let knee = 1.0 - threshold;
+    let limited = threshold + knee * ((amplitude - threshold) / knee).tanh();
+
+    limited.copysign(sample)
+}
+
+/// Converts the samples to the channel count and sample rate of the output
+pub(super) fn into_output_layout(mut samples: Samples<IntermediateSampleType>, output_metadata: &SamplesMetadata) -> Samples<IntermediateSampleType> {
+    if samples.metadata.channels != output_metadata.channels {
+        samples = utils::into_n_channels(samples, output_metadata.channels);
+    }
+    if samples.metadata.sample_rate != output_metadata.sample_rate {
+        samples = utils::into_sample_rate(samples, output_metadata.sample_rate);
+    }
+
+    samples
+}
+
+/// Gain of a channel for the pan, only the first two channels are affected by the pan.
+/// The center (0.0) leaves both channels at full volume
+fn pan_gain(pan: IntermediateSampleType, channel: usize, channels: usize) -> IntermediateSampleType {
+    if channels < 2 {
+        return 1.0
+    }
+
+    match channel {
+        0 => (1.0 - pan).min(1.0),
+        1 => (1.0 + pan).min(1.0),
+        _ => 1.0,
+    }
+}
+
+impl StreamSource for MixerState {
+    fn fill_buffer(&mut self, buffer: &mut [IntermediateSampleType]) {
+        let channels = self.channels;
+        let buffer_start = self.frame;
+        let buffer_frames = (buffer.len() / channels) as u64;
+        let buffer_end = buffer_start + buffer_frames;
+
+        for source in self.sources.iter_mut() {
+            if source.start_frame >= buffer_end {
+                continue;
+            }
+
+            // Only the part of the buffer between the start and the stop is mixed so that it is sample accurate
+            let first_frame = source.start_frame.saturating_sub(buffer_start);
+            let last_frame = match source.stop_frame {
+                Some(stop) if stop < buffer_end => {
+                    source.stopped = true;
+                    stop.saturating_sub(buffer_start)
+                },
+                _ => buffer_frames,
+            };
+
+            let range = (first_frame as usize * channels)..(last_frame as usize * channels);
+            for (i, sample_out) in buffer.iter_mut().enumerate().take(range.end).skip(range.start) {
+                let sample = match source.samples.samples.get(source.index) {
+                    Some(s) => *s,
+                    None => break,
+                };
+                source.index += 1;
+
+                *sample_out += sample * source.gain * pan_gain(source.pan, i % channels, channels);
+            }
+        }
+        self.frame = buffer_end;
+
+        // The sources are dropped once they are done playing
+        self.sources.retain(|s| !s.is_finished());
+
+        for sample in buffer.iter_mut() {
+            *sample = soft_limit(*sample * self.master_volume, self.limiter_threshold);
+        }
+    }
+}
+
+/// Plays many sources on a single output stream by summing them together.
+/// Sources can be added and removed while the mixer is playing, each with its own gain, pan and modifiers.
+/// Sources are converted to the channel count and sample rate of the mixer when they are added.
+///
+/// Sources can also be scheduled to start and stop on an exact frame of the mixer, or at an `Instant`
+/// using the clock of the stream
+pub struct Mixer {
+    metadata: SamplesMetadata,
+    state: Arc<Mutex<MixerState>>,
+    controls: HashMap<SourceId, SourceControl>,
+    next_id: u64,
+    stream: Option<cpal_abstraction::Stream>,
+    /// Frame of the mixer when the current stream was created, the clock of the stream starts at 0 from there
+    stream_start_frame: u64,
+}
+
+impl Mixer {
+    /// Creates a new `Mixer` outputing with the channel count and sample rate specified
+    pub fn new(channels: u16, sample_rate: u32) -> Mixer {
+        // Without channels there would be no frames to play
+        let channels = channels.max(1);
+        let state = MixerState {
+            channels: channels as usize,
+            frame: 0,
+            sources: Vec::new(),
+            master_volume: 1.0,
+            limiter_threshold: DEFAULT_LIMITER_THRESHOLD,
+        };
+
+        Mixer {
+            metadata: SamplesMetadata::new(channels, sample_rate, SampleType::F32),
+            state: Arc::new(Mutex::new(state)),
+            controls: HashMap::new(),
+            next_id: 0,
+            stream: None,
+            stream_start_frame: 0,
+        }
+    }
+
+    /// Returns the metadata of the output of the mixer
+    pub fn metadata(&self) -> Box<dyn AudioMetadataTrait> {
+        Box::new(self.metadata.clone())
+    }
+
+    fn lock_state(&self) -> Error<MutexGuard<'_, MixerState>> {
+        self.state.lock()
+            .map_err(|e| PlayError::PoisonedMutex("mixer state".to_string(), e.to_string().into()))
+    }
+
+    /// Removes the controls of the sources which finished playing
+    fn prune_finished_sources(&mut self) -> Error<()> {
+        let playing_ids = self.lock_state()?.sources.iter()
+            .map(|s| s.id)
+            .collect::<Vec<SourceId>>();
+        self.controls.retain(|id, _| playing_ids.contains(id));
+
+        Ok(())
+    }
+
+    /// Adds a source to the mix, it starts playing right away if the mixer is playing.
+    /// The source is removed from the mixer once it is done playing
+    pub fn add_source<T: Sample>(&mut self, samples: Samples<T>) -> Error<SourceId>
+    where IntermediateSampleType: cpal::FromSample<T> {
+        self.add_scheduled_source(samples, ScheduleTime::Now)
+    }
+
+    /// Adds a source to the mix that starts playing at the time specified, if the time already passed it starts right away.
+    /// The source is removed from the mixer once it is done playing
+    pub fn add_scheduled_source<T: Sample>(&mut self, samples: Samples<T>, start: ScheduleTime) -> Error<SourceId>
+    where IntermediateSampleType: cpal::FromSample<T> {
+        self.prune_finished_sources()?;
+
+        let start_frame = self.resolve_schedule_time(start)?;
+        let samples = into_output_layout(samples.into_generic_representation_samples(), &self.metadata);
+
+        let id = SourceId(self.next_id);
+        self.next_id += 1;
+
+        let mixing_source = MixingSource {
+            id,
+            samples: samples.clone(),
+            index: 0,
+            gain: 1.0,
+            pan: 0.0,
+            start_frame,
+            stop_frame: None,
+            stopped: false,
+        };
+        self.lock_state()?.sources.push(mixing_source);
+
+        let control = SourceControl {
+            original_samples: samples,
+            modifiers: Vec::new(),
+        };
+        self.controls.insert(id, control);
+
+        Ok(id)
+    }
+
+    /// Stops the source at the time specified, the source is then removed from the mixer
+    pub fn schedule_stop(&mut self, id: SourceId, stop: ScheduleTime) -> Error<()> {
+        let stop_frame = self.resolve_schedule_time(stop)?;
+
+        let mut state = self.lock_state()?;
+        let source = state.source_mut(id).ok_or(PlayError::SourceDoesNotExist(id))?;
+        source.stop_frame = Some(stop_frame);
+
+        Ok(())
+    }
+
+    /// Returns the number of frames (one sample per channel) mixed so far, this is the timeline used to schedule sources
+    pub fn frame(&self) -> Error<u64> {
+        Ok(self.lock_state()?.frame)
+    }
+
+    /// Returns the clock of the stream the mixer is playing on, if it is playing
+    pub fn clock(&self) -> Option<StreamClock> {
+        self.stream.as_ref().map(|s| s.clock())
+    }
+
+    /// Returns the frame of the mixer that will be heard at the instant, compensating for the latency of the stream
+    pub fn frame_at(&self, instant: Instant) -> Error<u64> {
+        let clock = self.clock().ok_or(PlayError::StreamIoError(
+            "the mixer is not playing on a stream".to_string(), None))?;
+        let clock_frame = clock.frame_at(instant).ok_or(PlayError::StreamIoError(
+            "the stream did not start playing yet".to_string(), None))?;
+
+        // The device may be running at another sample rate than the mixer
+        let mixer_frames = clock_frame as f64 * self.metadata.sample_rate as f64 / clock.sample_rate().max(1) as f64;
+
+        Ok(self.stream_start_frame + mixer_frames.round() as u64)
+    }
+
+    fn resolve_schedule_time(&self, time: ScheduleTime) -> Error<u64> {
+        match time {
+            ScheduleTime::Now => Ok(0),
+            ScheduleTime::Frame(f) => Ok(f),
+            ScheduleTime::Instant(i) => self.frame_at(i),
+        }
+    }
+
+    /// Removes the source from the mix, stopping it
+    pub fn remove_source(&mut self, id: SourceId) -> Error<()> {
+        self.controls.remove(&id);
+
+        let mut state = self.lock_state()?;
+        let source_count = state.sources.len();
+        state.sources.retain(|s| s.id != id);
+
+        if state.sources.len() == source_count {
+            return Err(PlayError::SourceDoesNotExist(id))
+        }
+
+        Ok(())
+    }
+
+    /// Removes all the sources from the mix
+    pub fn clear_sources(&mut self) -> Error<()> {
+        self.controls.clear();
+        self.lock_state()?.sources.clear();
+
+        Ok(())
+    }
+
+    /// Returns true if the source is still in the mix
+    pub fn contains_source(&self, id: SourceId) -> Error<bool> {
+        Ok(self.lock_state()?.sources.iter().any(|s| s.id == id))
+    }
+
+    /// Returns the number of sources currently in the mix
+    pub fn source_count(&self) -> Error<usize> {
+        Ok(self.lock_state()?.sources.len())
+    }
+
+    /// Sets the gain of the source, 1.0 leaves the source unchanged
+    pub fn set_gain(&mut self, id: SourceId, gain: IntermediateSampleType) -> Error<()> {
+        let mut state = self.lock_state()?;
+        let source = state.source_mut(id).ok_or(PlayError::SourceDoesNotExist(id))?;
+        source.gain = gain;
+
+        Ok(())
+    }
+
+    /// Sets the pan of the source, from -1.0 (left) to 1.0 (right).
+    /// Only has an effect if the mixer has at least two channels
+    pub fn set_pan(&mut self, id: SourceId, pan: IntermediateSampleType) -> Error<()> {
+        let mut state = self.lock_state()?;
+        let source = state.source_mut(id).ok_or(PlayError::SourceDoesNotExist(id))?;
+        source.pan = pan.clamp(-1.0, 1.0);
+
+        Ok(())
+    }
+
+    /// Sets the volume applied to the whole mix before it goes through the limiter
+    pub fn set_master_volume(&mut self, volume: IntermediateSampleType) -> Error<()> {
+        self.lock_state()?.master_volume = volume;
+
+        Ok(())
+    }
+
+    /// Sets the level above which the limiter starts to compress the mix, between 0.0 and 1.0.
+    /// Lowering it gives more headroom to the sources but squashes loud mixes more
+    pub fn set_limiter_threshold(&mut self, threshold: IntermediateSampleType) -> Error<()> {
+        self.lock_state()?.limiter_threshold = threshold.clamp(0.0, 0.99);
+
+        Ok(())
+    }
+
+    /// Reapplies the modifiers of the source and swaps the samples it is playing
+    fn apply_modifiers(&mut self, id: SourceId) -> Error<()> {
+        let control = self.controls.get(&id).ok_or(PlayError::SourceDoesNotExist(id))?;
+        let samples = control.samples_with_modifiers();
+
+        let mut state = self.lock_state()?;
+        let source = state.source_mut(id).ok_or(PlayError::SourceDoesNotExist(id))?;
+        source.samples = samples;
+
+        Ok(())
+    }
+
+    /// Adds a modifier to the source, see `SamplesPlayerTrait::add_modifier`
+    pub fn add_modifier(&mut self, id: SourceId, modifier: Box<dyn ModifierTrait>) -> Error<()> {
+        let control = self.controls.get_mut(&id).ok_or(PlayError::SourceDoesNotExist(id))?;
+        control.modifiers.push(modifier);
+
+        self.apply_modifiers(id)
+    }
+
+    /// Clears all modifiers of the source and their effects
+    pub fn clear_modifiers(&mut self, id: SourceId) -> Error<()> {
+        let control = self.controls.get_mut(&id).ok_or(PlayError::SourceDoesNotExist(id))?;
+        control.modifiers = Vec::new();
+
+        self.apply_modifiers(id)
+    }
+
+    /// Starts/Continues the playing
+    pub fn start(&self) -> Error<()> {
+        let stream = match &self.stream {
+            Some(s) => s,
+            None => return Ok(()), // No stream to start
+        };
+
+        stream.start()
+    }
+
+    /// Stops the playing
+    pub fn stop(&self) -> Error<()> {
+        let stream = match &self.stream {
+            Some(s) => s,
+            None => return Ok(()), // No stream to stop
+        };
+
+        stream.stop()
+    }
+
+    /// Returns the errors the stream ran into since the last call, see `Stream::take_errors`
+    pub fn take_stream_errors(&self) -> Vec<PlayError> {
+        match &self.stream {
+            Some(s) => s.take_errors(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Returns the latency of the stream, see `Stream::latency`. None if not playing or if it is not known yet
+    pub fn latency(&self) -> Option<Duration> {
+        self.stream.as_ref().and_then(|s| s.latency())
+    }
+
+    /// Checks what happened to the stream since the last call, call it regularly.
+    /// If the device was lost and failover is on, the mix is moved to the new default output
+    pub fn poll_events(&mut self, failover: bool) -> Vec<PlayerEvent> {
+        let errors = self.take_stream_errors();
+
+        handle_stream_errors(errors, failover, |device| self.play_on_device(device))
+    }
+
+    /// Starts playing the mix on a device
+    pub fn play_on_device(&mut self, device: Device) -> Error<()> {
+        let stream = device.create_source_stream(&self.metadata, Arc::clone(&self.state))?;
+        self.stream_start_frame = self.frame()?;
+
+        // Makes sure that the stream is started
+        stream.start()?;
+
+        self.stream = Some(stream);
+
+        Ok(())
+    }
+
+    /// Starts playing the mix on the default device of the default host
+    pub fn play_on_default(&mut self) -> Error<()> {
+        let default_output = match Device::default_output() {
+            Some(o) => o,
+            None => return Err(PlayError::DeviceDoesNotExist { name : "default".to_string() }),
+        };
+
+        self.play_on_device(default_output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mono_samples(samples: Vec<IntermediateSampleType>) -> Samples<IntermediateSampleType> {
+        Samples::new(samples, SamplesMetadata::new(1, 48000, SampleType::F32))
+    }
+
+    #[test]
+    fn sources_are_summed() {
+        let mut mixer = Mixer::new(1, 48000);
+        mixer.add_source(mono_samples(vec![0.1, 0.2, 0.3])).unwrap();
+        mixer.add_source(mono_samples(vec![0.1, 0.1])).unwrap();
+
+        let mut buffer = vec![0.0; 4];
+        mixer.state.lock().unwrap().fill_buffer(&mut buffer);
+
+        assert!((buffer[0] - 0.2).abs() < 0.0001);
+        assert!((buffer[1] - 0.3).abs() < 0.0001);
+        assert!((buffer[2] - 0.3).abs() < 0.0001);
+        assert_eq!(buffer[3], 0.0);
+        assert_eq!(mixer.source_count().unwrap(), 0);
+    }
+
+    #[test]
+    fn zero_channels_play_as_mono() {
+        let mut mixer = Mixer::new(0, 48000);
+        mixer.add_source(mono_samples(vec![0.1, 0.2])).unwrap();
+
+        let mut buffer = vec![0.0; 2];
+        mixer.state.lock().unwrap().fill_buffer(&mut buffer);
+
+        assert!((buffer[1] - 0.2).abs() < 0.0001);
+    }
+
+    #[test]
+    fn limiter_keeps_mix_in_range() {
+        let mut mixer = Mixer::new(2, 48000);
+        for _ in 0..10 {
+            let id = mixer.add_source(mono_samples(vec![0.9; 100])).unwrap();
+            mixer.set_pan(id, -1.0).unwrap();
+        }
+
+        let mut buffer = vec![0.0; 200];
+        mixer.state.lock().unwrap().fill_buffer(&mut buffer);
+
+        assert!(buffer.iter().all(|s| s.abs() <= 1.0));
+        // Hard left pan silences the right channel
+        assert_eq!(buffer[1], 0.0);
+    }
+
+    #[test]
+    fn scheduled_sources_are_sample_accurate() {
+        let mut mixer = Mixer::new(1, 48000);
+        let id = mixer.add_scheduled_source(mono_samples(vec![0.5; 100]), ScheduleTime::Frame(6)).unwrap();
+        mixer.schedule_stop(id, ScheduleTime::Frame(12)).unwrap();
+
+        let mut buffer = vec![0.0; 8];
+        mixer.state.lock().unwrap().fill_buffer(&mut buffer);
+        assert_eq!(buffer, vec![0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.5, 0.5]);
+
+        let mut buffer = vec![0.0; 8];
+        mixer.state.lock().unwrap().fill_buffer(&mut buffer);
+        assert_eq!(buffer, vec![0.5, 0.5, 0.5, 0.5, 0.0, 0.0, 0.0, 0.0]);
+        assert!(!mixer.contains_source(id).unwrap());
+    }
+}
@@ -1,7 +1,12 @@
 //! Contains all the types of sample players,
-//! use SamplesPlayer for speed and ExactSamplesPlayer for control
+//! use SamplesPlayer for speed and ExactSamplesPlayer for control.
+//! Use Mixer to play many samples at once on the same output and VoicePool for sound effects.
+//! QueuePlayer plays audio files one after the other without gaps.
+//! The resampler module changes the sample rate of samples
 
 pub mod modifiers;
+pub mod resampler;
+mod fft;
 
 mod samples_player_trait;
 pub use samples_player_trait::SamplesPlayerTrait;
@@ -9,4 +14,17 @@ pub use samples_player_trait::SamplesPlayerTrait;
 mod samples_player;
 pub use samples_player::SamplesPlayer;
 mod exact_samples_player;
-pub use exact_samples_player::ExactSamplesPlayer;
\ No newline at end of file
+pub use exact_samples_player::ExactSamplesPlayer;
+mod mixer;
+pub use mixer::{Mixer, SourceId, ScheduleTime};
+mod voice_pool;
+pub use voice_pool::{VoicePool, VoiceId, VoiceStealing};
+mod queue_player;
+pub use queue_player::{QueuePlayer, RepeatMode};
+mod crossfade;
+pub use crossfade::{Crossfade, FadeCurve};
+mod speed;
+pub use speed::SpeedMode;
+mod events;
+pub use events::PlayerEvent;
+pub(crate) use events::handle_stream_errors;
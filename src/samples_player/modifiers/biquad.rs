@@ -0,0 +1,324 @@
+use std::f64::consts::PI;
+
+use crate::samples::{IntermediateSampleType, Samples, SamplesMetadata};
+
+use super::ModifierTrait;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The shapes a `Biquad` can filter with, from the Audio EQ Cookbook by Robert Bristow-Johnson
+pub enum FilterType {
+    /// Keeps what is below the frequency
+    LowPass,
+    /// Keeps what is above the frequency
+    HighPass,
+    /// Keeps what is around the frequency, the Q sets how wide the band is
+    BandPass,
+    /// Removes what is around the frequency, the Q sets how wide the notch is
+    Notch,
+    /// Shifts the phase around the frequency without changing the volume
+    AllPass,
+    /// Boosts or cuts what is below the frequency by the gain
+    LowShelf,
+    /// Boosts or cuts what is above the frequency by the gain
+    HighShelf,
+    /// Boosts or cuts what is around the frequency by the gain, the Q sets how wide the bell is
+    Peaking,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// The coefficients of a biquad, normalized so that a0 is 1
+pub(crate) struct BiquadCoefficients {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+}
+
+impl BiquadCoefficients {
+    pub(crate) fn new(filter_type: FilterType, frequency: f32, q: f32, gain_db: f32, sample_rate: u32) -> BiquadCoefficients {
+        let sample_rate = sample_rate.max(1) as f64;
+        // Above the nyquist frequency the filter would fold back
+        let frequency = (frequency as f64).max(1.0).min((sample_rate * 0.499).max(1.0));
+        let q = (q as f64).max(0.01);
+
+        let w0 = 2.0 * PI * frequency / sample_rate;
+        let (sin, cos) = w0.sin_cos();
+        let alpha = sin / (2.0 * q);
+        let a = 10f64.powf(gain_db as f64 / 40.0);
+        let sqrt_a_alpha = 2.0 * a.sqrt() * alpha;
+
+        let (b0, b1, b2, a0, a1, a2) = match filter_type {
+            FilterType::LowPass => ((1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0, 1.0 + alpha, -2.0 * cos, 1.0 - alpha),
+            FilterType::HighPass => ((1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0, 1.0 + alpha, -2.0 * cos, 1.0 - alpha),
+            FilterType::BandPass => (alpha, 0.0, -alpha, 1.0 + alpha, -2.0 * cos, 1.0 - alpha),
+            FilterType::Notch => (1.0, -2.0 * cos, 1.0, 1.0 + alpha, -2.0 * cos, 1.0 - alpha),
+            FilterType::AllPass => (1.0 - alpha, -2.0 * cos, 1.0 + alpha, 1.0 + alpha, -2.0 * cos, 1.0 - alpha),
+            FilterType::Peaking => (1.0 + alpha * a, -2.0 * cos, 1.0 - alpha * a, 1.0 + alpha / a, -2.0 * cos, 1.0 - alpha / a),
+            FilterType::LowShelf => (
+                a * ((a + 1.0) - (a - 1.0) * cos + sqrt_a_alpha),
+                2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                a * ((a + 1.0) - (a - 1.0) * cos - sqrt_a_alpha),
+                (a + 1.0) + (a - 1.0) * cos + sqrt_a_alpha,
+                -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                (a + 1.0) + (a - 1.0) * cos - sqrt_a_alpha,
+            ),
+            FilterType::HighShelf => (
+                a * ((a + 1.0) + (a - 1.0) * cos + sqrt_a_alpha),
+                -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                a * ((a + 1.0) + (a - 1.0) * cos - sqrt_a_alpha),
+                (a + 1.0) - (a - 1.0) * cos + sqrt_a_alpha,
+                2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                (a + 1.0) - (a - 1.0) * cos - sqrt_a_alpha,
+            ),
+        };
+
+        BiquadCoefficients {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+        }
+    }
+
+    /// Returns how much the filter multiplies the amplitude at the frequency
+    pub(crate) fn magnitude(&self, frequency: f32, sample_rate: u32) -> f64 {
+        let w = 2.0 * PI * frequency as f64 / sample_rate.max(1) as f64;
+        let (sin1, cos1) = w.sin_cos();
+        let (sin2, cos2) = (2.0 * w).sin_cos();
+
+        // H(z) evaluated at z = e^jw
+        let num_re = self.b0 + self.b1 * cos1 + self.b2 * cos2;
+        let num_im = -self.b1 * sin1 - self.b2 * sin2;
+        let den_re = 1.0 + self.a1 * cos1 + self.a2 * cos2;
+        let den_im = -self.a1 * sin1 - self.a2 * sin2;
+
+        ((num_re * num_re + num_im * num_im) / (den_re * den_re + den_im * den_im)).sqrt()
+    }
+
+    /// Filters one sample, the state is the memory of the filter for the channel
+    pub(crate) fn process(&self, state: &mut [f64; 2], sample: f64) -> f64 {
+        // Transposed direct form II, it behaves well with floats
+        let output = self.b0 * sample + state[0];
+        state[0] = self.b1 * sample - self.a1 * output + state[1];
+        state[1] = self.b2 * sample - self.a2 * output;
+
+        output
+    }
+}
+
+#[derive(Debug, Clone)]
+/// A second order filter applied to every channel separately.
+/// When used in real time (see `ModifierTrait::process_block`) the filter remembers the end of the last block
+pub struct Biquad {
+    filter_type: FilterType,
+    frequency: f32,
+    q: f32,
+    gain_db: f32,
+    /// The memory of the filter for each channel
+    state: Vec<[f64; 2]>,
+    /// The coefficients for the sample rate they were computed at
+    coefficients: Option<(u32, BiquadCoefficients)>,
+}
+
+impl Biquad {
+    /// Creates a filter, the frequency is in Hz and the gain in dB.
+    /// The gain is only used by the shelves and peaking filters, a Q of 0.707 gives no resonance
+    pub fn new(filter_type: FilterType, frequency: f32, q: f32, gain_db: f32) -> Biquad {
+        Biquad {
+            filter_type,
+            frequency,
+            q,
+            gain_db,
+            state: Vec::new(),
+            coefficients: None,
+        }
+    }
+
+    /// Creates a low-pass filter
+    pub fn low_pass(frequency: f32, q: f32) -> Biquad {
+        Biquad::new(FilterType::LowPass, frequency, q, 0.0)
+    }
+
+    /// Creates a high-pass filter
+    pub fn high_pass(frequency: f32, q: f32) -> Biquad {
+        Biquad::new(FilterType::HighPass, frequency, q, 0.0)
+    }
+
+    /// Creates a band-pass filter
+    pub fn band_pass(frequency: f32, q: f32) -> Biquad {
+        Biquad::new(FilterType::BandPass, frequency, q, 0.0)
+    }
+
+    /// Creates a notch filter
+    pub fn notch(frequency: f32, q: f32) -> Biquad {
+        Biquad::new(FilterType::Notch, frequency, q, 0.0)
+    }
+
+    /// Creates a low shelf
+    pub fn low_shelf(frequency: f32, q: f32, gain_db: f32) -> Biquad {
+        Biquad::new(FilterType::LowShelf, frequency, q, gain_db)
+    }
+
+    /// Creates a high shelf
+    pub fn high_shelf(frequency: f32, q: f32, gain_db: f32) -> Biquad {
+        Biquad::new(FilterType::HighShelf, frequency, q, gain_db)
+    }
+
+    /// Creates a peaking filter
+    pub fn peaking(frequency: f32, q: f32, gain_db: f32) -> Biquad {
+        Biquad::new(FilterType::Peaking, frequency, q, gain_db)
+    }
+
+    /// Returns the type of the filter
+    pub fn filter_type(&self) -> FilterType {
+        self.filter_type
+    }
+
+    /// Returns the frequency in Hz
+    pub fn frequency(&self) -> f32 {
+        self.frequency
+    }
+
+    /// Returns the Q
+    pub fn q(&self) -> f32 {
+        self.q
+    }
+
+    /// Returns the gain in dB
+    pub fn gain_db(&self) -> f32 {
+        self.gain_db
+    }
+
+    /// Changes the type of the filter, the memory of the filter is kept so that there is no click
+    pub fn set_filter_type(&mut self, filter_type: FilterType) {
+        self.filter_type = filter_type;
+        self.coefficients = None;
+    }
+
+    /// Changes the frequency in Hz
+    pub fn set_frequency(&mut self, frequency: f32) {
+        self.frequency = frequency;
+        self.coefficients = None;
+    }
+
+    /// Changes the Q
+    pub fn set_q(&mut self, q: f32) {
+        self.q = q;
+        self.coefficients = None;
+    }
+
+    /// Changes the gain in dB
+    pub fn set_gain_db(&mut self, gain_db: f32) {
+        self.gain_db = gain_db;
+        self.coefficients = None;
+    }
+
+    fn compute_coefficients(&self, sample_rate: u32) -> BiquadCoefficients {
+        BiquadCoefficients::new(self.filter_type, self.frequency, self.q, self.gain_db, sample_rate)
+    }
+
+    /// Returns how much the filter changes the volume at the frequency, in dB
+    pub fn response_db(&self, frequency: f32, sample_rate: u32) -> f32 {
+        let magnitude = self.compute_coefficients(sample_rate).magnitude(frequency, sample_rate);
+
+        (20.0 * magnitude.max(1e-12).log10()) as f32
+    }
+
+    /// Filters the interleaved samples with the memory of the filter
+    fn filter(coefficients: &BiquadCoefficients, state: &mut [[f64; 2]], samples: &mut [IntermediateSampleType]) {
+        let channels = state.len();
+
+        for frame in samples.chunks_mut(channels) {
+            for (sample, state) in frame.iter_mut().zip(state.iter_mut()) {
+                *sample = coefficients.process(state, *sample as f64) as IntermediateSampleType;
+            }
+        }
+    }
+}
+
+impl ModifierTrait for Biquad {
+    fn modify(&self, mut samples: Samples<IntermediateSampleType>) -> Samples<IntermediateSampleType> {
+        let coefficients = self.compute_coefficients(samples.metadata.sample_rate);
+        let mut state = vec![[0.0; 2]; samples.metadata.channels.max(1) as usize];
+
+        Biquad::filter(&coefficients, &mut state, &mut samples.samples);
+
+        samples
+    }
+
+    fn process_block(&mut self, block: &mut [IntermediateSampleType], metadata: &SamplesMetadata) {
+        let coefficients = match self.coefficients {
+            Some((sample_rate, c)) if sample_rate == metadata.sample_rate => c,
+            _ => {
+                let c = self.compute_coefficients(metadata.sample_rate);
+                self.coefficients = Some((metadata.sample_rate, c));
+                c
+            },
+        };
+
+        self.state.resize(metadata.channels.max(1) as usize, [0.0; 2]);
+
+        Biquad::filter(&coefficients, &mut self.state, block);
+    }
+
+    fn reset(&mut self) {
+        self.state.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::samples::SampleType;
+
+    fn sine(frequency: f32, sample_rate: u32, len: usize) -> Samples<IntermediateSampleType> {
+        let samples = (0..len)
+            .map(|i| (2.0 * std::f32::consts::PI * frequency * i as f32 / sample_rate as f32).sin())
+            .collect();
+
+        Samples::new(samples, SamplesMetadata::new(1, sample_rate, SampleType::F32))
+    }
+
+    fn peak(samples: &[IntermediateSampleType]) -> IntermediateSampleType {
+        samples.iter().fold(0.0, |m, s| m.max(s.abs()))
+    }
+
+    #[test]
+    fn low_pass_cuts_high_frequencies() {
+        let filter = Biquad::low_pass(500.0, 0.707);
+
+        let low = filter.modify(sine(100.0, 48000, 4800));
+        let high = filter.modify(sine(8000.0, 48000, 4800));
+
+        assert!(peak(&low.samples[2400..]) > 0.95);
+        assert!(peak(&high.samples[2400..]) < 0.01);
+        assert!((filter.response_db(500.0, 48000) + 3.0).abs() < 0.1);
+        assert!((Biquad::peaking(1000.0, 1.0, 6.0).response_db(1000.0, 48000) - 6.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn tiny_sample_rates_do_not_panic() {
+        for sample_rate in [1, 2, 4] {
+            let input = Samples::new(vec![1.0, -1.0, 0.5, 0.0], SamplesMetadata::new(1, sample_rate, SampleType::F32));
+            let output = Biquad::peaking(1000.0, 1.0, 6.0).modify(input);
+
+            assert!(output.samples.iter().all(|s| s.is_finite()));
+        }
+    }
+
+    #[test]
+    fn blocks_give_the_same_result_as_whole_samples() {
+        let input = sine(440.0, 48000, 1000);
+        let whole = Biquad::high_shelf(2000.0, 0.707, -6.0).modify(input.clone());
+
+        let mut filter = Biquad::high_shelf(2000.0, 0.707, -6.0);
+        let mut blocks = input.samples.clone();
+        for block in blocks.chunks_mut(64) {
+            filter.process_block(block, &input.metadata);
+        }
+
+        assert_eq!(blocks, whole.samples);
+    }
+}
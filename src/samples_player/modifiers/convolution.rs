@@ -0,0 +1,346 @@
+use std::collections::VecDeque;
+
+use crate::errors::{Error, PlayError};
+use crate::resampler::{self, ResampleQuality};
+use crate::samples::{IntermediateSampleType, SampleType, Samples, SamplesMetadata};
+use crate::samples_player::fft::{Complex, Fft};
+use crate::traits::AudioFileTrait;
+use crate::wav::WavAudio;
+
+use super::ModifierTrait;
+
+/// Frames per partition of the impulse response by default, it is also the latency in real time
+const DEFAULT_PARTITION_SIZE: usize = 512;
+
+/// Which impulse response goes from an input channel to an output channel
+#[derive(Debug, Clone, Copy)]
+struct Path {
+    input: usize,
+    output: usize,
+    impulse_response: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+struct ConvolutionState {
+    channels: usize,
+    sample_rate: u32,
+    fft: Option<Fft>,
+    /// The spectra of the partitions of each channel of the impulse response
+    partitions: Vec<Vec<Vec<Complex>>>,
+    paths: Vec<Path>,
+    /// The last two partitions of input of each channel, in the time domain
+    inputs: Vec<Vec<f32>>,
+    /// The spectra of the last inputs of each channel, the newest first
+    history: Vec<VecDeque<Vec<Complex>>>,
+    /// The convolved partition being played for each channel
+    outputs: Vec<Vec<f32>>,
+    /// Position in the current partition
+    position: usize,
+    /// Buffer for the spectrum being computed
+    spectrum: Vec<Complex>,
+}
+
+#[derive(Debug, Clone)]
+/// Convolves the samples with an impulse response (a recording of a room, a speaker cabinet, etc.)
+/// to make them sound like they were played through it. The impulse response can have:
+/// * 1 channel, applied to every channel
+/// * 2 channels, the left one to the left channel and the right one to the right channel
+/// * 4 channels for true stereo: left to left, left to right, right to left and right to right
+///
+/// Uses a partitioned FFT convolution, which delays the audio by the partition size in real time.
+/// `modify` appends the tail of the impulse response to the samples
+pub struct Convolution {
+    impulse_response: Samples<IntermediateSampleType>,
+    partition_size: usize,
+    wet: f32,
+    dry: f32,
+    state: ConvolutionState,
+}
+
+impl Convolution {
+    /// Creates a convolution with the impulse response, it is resampled to the sample rate of the samples it modifies.
+    /// Fails if the impulse response does not have 1, 2 or 4 channels
+    pub fn new(impulse_response: Samples<IntermediateSampleType>) -> Error<Convolution> {
+        if ![1, 2, 4].contains(&impulse_response.metadata.channels) {
+            return Err(PlayError::Unsupported(format!("impulse responses with {} channels", impulse_response.metadata.channels)))
+        }
+
+        Ok(Convolution {
+            impulse_response,
+            partition_size: DEFAULT_PARTITION_SIZE,
+            wet: 1.0,
+            dry: 0.0,
+            state: ConvolutionState::default(),
+        })
+    }
+
+    /// Loads the impulse response from an audio file
+    pub fn from_audio_file(file: &impl AudioFileTrait) -> Error<Convolution> {
+        let samples = file.get_samples()?.generic_representation_samples();
+
+        Convolution::new(samples)
+    }
+
+    /// Loads the impulse response from a WAV file
+    pub fn from_wav_path(path: &str) -> Error<Convolution> {
+        Convolution::from_audio_file(&WavAudio::build_from_path(path)?)
+    }
+
+    /// Sets the frames per partition (rounded up to a power of two), 512 by default.
+    /// Smaller partitions lower the latency in real time but use more processing
+    pub fn with_partition_size(mut self, frames: usize) -> Convolution {
+        self.partition_size = frames.max(1).next_power_of_two();
+
+        // Prepared partitions have the old size
+        let (channels, sample_rate) = (self.state.channels, self.state.sample_rate);
+        self.state = ConvolutionState::default();
+        if channels != 0 {
+            self.prepare(&SamplesMetadata::new(channels as u16, sample_rate, SampleType::F32));
+        }
+        self
+    }
+
+    /// Prepares the convolution for samples with the channels and sample rate, see `Convolution::prepare`
+    pub fn with_sample_rate(mut self, channels: u16, sample_rate: u32) -> Convolution {
+        self.prepare(&SamplesMetadata::new(channels, sample_rate, SampleType::F32));
+        self
+    }
+
+    /// Sets the volume of the convolved sound and of the original sound, 1.0 and 0.0 by default
+    pub fn with_wet_dry(mut self, wet: f32, dry: f32) -> Convolution {
+        self.wet = wet;
+        self.dry = dry;
+        self
+    }
+
+    /// Returns the delay added to the audio in real time, in frames
+    pub fn latency_frames(&self) -> usize {
+        self.partition_size
+    }
+
+    /// Returns the length of the impulse response in frames at the sample rate
+    fn impulse_response_frames(&self, sample_rate: u32) -> usize {
+        let metadata = &self.impulse_response.metadata;
+        let frames = self.impulse_response.samples.len() / metadata.channels.max(1) as usize;
+
+        (frames as f64 * sample_rate as f64 / metadata.sample_rate.max(1) as f64).ceil() as usize
+    }
+
+    /// Sends each input channel through the impulse responses to the output channels
+    fn paths(ir_channels: usize, channels: usize) -> Vec<Path> {
+        let path = |input, output, impulse_response| Path { input, output, impulse_response };
+
+        if ir_channels == 4 && channels >= 2 {
+            let mut paths = vec![path(0, 0, 0), path(0, 1, 1), path(1, 0, 2), path(1, 1, 3)];
+            paths.extend((2..channels).map(|c| path(c, c, 0)));
+            return paths
+        }
+
+        // A mono source only gets the first (left to left) impulse response
+        let ir_channels = if ir_channels == 4 { 1 } else { ir_channels };
+        (0..channels).map(|c| path(c, c, c % ir_channels)).collect()
+    }
+
+    /// Resamples the impulse response and computes its spectra for samples with the metadata.
+    /// This is done on the first block otherwise, which is too slow for a real time stream
+    pub fn prepare(&mut self, metadata: &SamplesMetadata) {
+        let channels = metadata.channels.max(1) as usize;
+        if self.state.channels == channels && self.state.sample_rate == metadata.sample_rate {
+            return
+        }
+
+        let partition = self.partition_size;
+        let fft = Fft::new(partition * 2);
+
+        let impulse_response = resampler::resample(self.impulse_response.clone(), metadata.sample_rate, ResampleQuality::Sinc);
+        let ir_channels = impulse_response.metadata.channels.max(1) as usize;
+
+        // Each partition is zero padded to twice its size so that the circular convolution does not wrap around
+        let partitions = (0..ir_channels)
+            .map(|c| {
+                let channel = impulse_response.samples.iter().skip(c).step_by(ir_channels).copied().collect::<Vec<_>>();
+
+                channel.chunks(partition)
+                    .map(|chunk| {
+                        let mut spectrum = vec![Complex::default(); partition * 2];
+                        for (bin, sample) in spectrum.iter_mut().zip(chunk) {
+                            bin.re = *sample;
+                        }
+                        fft.forward(&mut spectrum);
+                        spectrum
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let partition_count = partitions.iter().map(|p| p.len()).max().unwrap_or(0);
+
+        self.state = ConvolutionState {
+            channels,
+            sample_rate: metadata.sample_rate,
+            fft: Some(fft),
+            partitions,
+            paths: Convolution::paths(ir_channels, channels),
+            inputs: vec![vec![0.0; partition * 2]; channels],
+            history: vec![VecDeque::from(vec![vec![Complex::default(); partition * 2]; partition_count]); channels],
+            outputs: vec![vec![0.0; partition]; channels],
+            position: 0,
+            spectrum: vec![Complex::default(); partition * 2],
+        };
+    }
+
+    /// Convolves the partition of input that was just filled
+    fn convolve_partition(&mut self) {
+        let partition = self.partition_size;
+        let state = &mut self.state;
+        let fft = match &state.fft {
+            Some(f) => f,
+            None => return,
+        };
+
+        // Overlap-save, the spectrum of the last two partitions of input goes in the history
+        for (input, history) in state.inputs.iter_mut().zip(state.history.iter_mut()) {
+            let mut spectrum = match history.pop_back() {
+                Some(s) => s,
+                None => continue,
+            };
+            for (bin, sample) in spectrum.iter_mut().zip(input.iter()) {
+                *bin = Complex::new(*sample, 0.0);
+            }
+            fft.forward(&mut spectrum);
+            history.push_front(spectrum);
+
+            input.copy_within(partition.., 0);
+        }
+
+        for (output_channel, output) in state.outputs.iter_mut().enumerate() {
+            state.spectrum.iter_mut().for_each(|b| *b = Complex::default());
+
+            for path in state.paths.iter().filter(|p| p.output == output_channel) {
+                let history = &state.history[path.input];
+                for (input, ir) in history.iter().zip(&state.partitions[path.impulse_response]) {
+                    for ((bin, i), h) in state.spectrum.iter_mut().zip(input).zip(ir) {
+                        *bin += *i * *h;
+                    }
+                }
+            }
+
+            fft.inverse(&mut state.spectrum);
+
+            // The first half wrapped around, the second half is the convolved partition
+            for (sample, bin) in output.iter_mut().zip(&state.spectrum[partition..]) {
+                *sample = bin.re;
+            }
+        }
+    }
+}
+
+impl ModifierTrait for Convolution {
+    fn modify(&self, mut samples: Samples<IntermediateSampleType>) -> Samples<IntermediateSampleType> {
+        let mut convolution = self.clone();
+        convolution.reset();
+
+        let metadata = samples.metadata.clone();
+        let channels = metadata.channels.max(1) as usize;
+        let frames = samples.samples.len() / channels;
+        let tail_frames = self.impulse_response_frames(metadata.sample_rate).saturating_sub(1);
+
+        // The latency is pushed out at the end then removed from the start
+        let latency = self.latency_frames();
+        samples.samples.resize((frames + tail_frames + latency) * channels, 0.0);
+        convolution.process_block(&mut samples.samples, &metadata);
+        samples.samples.drain(..latency * channels);
+
+        samples
+    }
+
+    fn process_block(&mut self, block: &mut [IntermediateSampleType], metadata: &SamplesMetadata) {
+        self.prepare(metadata);
+
+        let partition = self.partition_size;
+        for frame in block.chunks_mut(self.state.channels) {
+            let position = self.state.position;
+
+            for (c, sample) in frame.iter_mut().enumerate() {
+                self.state.inputs[c][partition + position] = *sample;
+                *sample = self.dry * *sample + self.wet * self.state.outputs[c][position];
+            }
+
+            self.state.position += 1;
+            if self.state.position == partition {
+                self.state.position = 0;
+                self.convolve_partition();
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        // The prepared impulse response is kept, only what was played is forgotten
+        let state = &mut self.state;
+        state.inputs.iter_mut().for_each(|i| i.fill(0.0));
+        state.history.iter_mut().flatten().for_each(|s| s.fill(Complex::default()));
+        state.outputs.iter_mut().for_each(|o| o.fill(0.0));
+        state.position = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn direct_convolution(input: &[f32], impulse_response: &[f32]) -> Vec<f32> {
+        let mut output = vec![0.0; input.len() + impulse_response.len() - 1];
+        for (i, x) in input.iter().enumerate() {
+            for (j, h) in impulse_response.iter().enumerate() {
+                output[i + j] += x * h;
+            }
+        }
+
+        output
+    }
+
+    #[test]
+    fn matches_direct_convolution_across_partitions() {
+        let impulse_response = (0..37).map(|i| ((i * 7 % 11) as f32 - 5.0) / 10.0).collect::<Vec<_>>();
+        let input = (0..100).map(|i| ((i * 13 % 17) as f32 - 8.0) / 8.0).collect::<Vec<_>>();
+
+        let convolution = Convolution::new(Samples::new(impulse_response.clone(), SamplesMetadata::new(1, 1000, SampleType::F32)))
+            .unwrap()
+            .with_partition_size(8);
+        let output = convolution.modify(Samples::new(input.clone(), SamplesMetadata::new(1, 1000, SampleType::F32)));
+
+        let expected = direct_convolution(&input, &impulse_response);
+        assert_eq!(output.samples.len(), expected.len());
+        for (s, e) in output.samples.iter().zip(&expected) {
+            assert!((s - e).abs() < 1e-4);
+        }
+
+        // Preparing ahead of time, then after a reset, gives the same result
+        let mut prepared = convolution.with_sample_rate(1, 1000);
+        prepared.reset();
+        let mut streamed = input.clone();
+        streamed.resize(expected.len() + prepared.latency_frames(), 0.0);
+        prepared.process_block(&mut streamed, &SamplesMetadata::new(1, 1000, SampleType::F32));
+        for (s, e) in streamed[prepared.latency_frames()..].iter().zip(&expected) {
+            assert!((s - e).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn true_stereo_routes_between_channels() {
+        // Only left to right has an impulse, delayed by 2 frames
+        let mut impulse_response = vec![0.0; 4 * 3];
+        impulse_response[2 * 4 + 1] = 1.0;
+        let convolution = Convolution::new(Samples::new(impulse_response, SamplesMetadata::new(4, 1000, SampleType::F32)))
+            .unwrap()
+            .with_partition_size(4);
+
+        let input = Samples::new(vec![1.0, 0.0, 0.0, 0.5, 0.0, 0.0], SamplesMetadata::new(2, 1000, SampleType::F32));
+        let output = convolution.modify(input);
+
+        let expected = [0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0];
+        for (s, e) in output.samples.iter().zip(&expected) {
+            assert!((s - e).abs() < 1e-5);
+        }
+        assert!(Convolution::new(Samples::new(vec![0.0; 3], SamplesMetadata::new(3, 1000, SampleType::F32))).is_err());
+    }
+}
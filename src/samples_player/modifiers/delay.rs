@@ -0,0 +1,302 @@
+use std::time::Duration;
+
+use crate::samples::{IntermediateSampleType, Samples, SamplesMetadata};
+
+use super::ModifierTrait;
+
+/// The tail added by `modify` is never longer than this, even with a lot of feedback
+const MAX_TAIL: Duration = Duration::from_secs(30);
+
+/// A delay is never longer than this, so that its delay line fits in memory
+const MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// The echoes are considered gone once they are this quiet (-60 dB)
+const TAIL_THRESHOLD: f32 = 0.001;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// How long a delay is, either fixed or synced to a tempo
+pub enum DelayTime {
+    /// A fixed time in milliseconds
+    Milliseconds(f32),
+    /// A number of beats (0.5 is an eighth note in 4/4) at the tempo in beats per minute
+    Beats {
+        /// The number of beats
+        beats: f32,
+        /// The tempo
+        bpm: f32,
+    },
+}
+
+impl DelayTime {
+    /// Returns the delay as a duration, at most 30 seconds
+    pub fn duration(&self) -> Duration {
+        let seconds = match *self {
+            DelayTime::Milliseconds(ms) => ms / 1000.0,
+            DelayTime::Beats { beats, bpm } if bpm > 0.0 => beats * 60.0 / bpm,
+            DelayTime::Beats { .. } => 0.0,
+        };
+
+        // Infinite or huge delays do not fit in a duration
+        Duration::try_from_secs_f32(seconds.max(0.0))
+            .unwrap_or(MAX_DELAY)
+            .min(MAX_DELAY)
+    }
+
+    /// Returns the delay in frames at the sample rate, at least one
+    pub fn frames(&self, sample_rate: u32) -> usize {
+        ((self.duration().as_secs_f64() * sample_rate as f64).round() as usize).max(1)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// An echo of a multi-tap `Delay`
+pub struct DelayTap {
+    /// When the echo is heard after the sound
+    pub time: DelayTime,
+    /// The volume of the echo
+    pub gain: f32,
+}
+
+impl DelayTap {
+    /// Creates a tap
+    pub fn new(time: DelayTime, gain: f32) -> DelayTap {
+        DelayTap { time, gain }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct DelayState {
+    channels: usize,
+    sample_rate: u32,
+    /// One delay line per channel
+    lines: Vec<Vec<IntermediateSampleType>>,
+    /// Where the next frame is written in the lines
+    write: usize,
+}
+
+impl DelayState {
+    /// Returns the sample written that many frames ago on the channel
+    fn read(&self, channel: usize, frames: usize) -> IntermediateSampleType {
+        let line = &self.lines[channel];
+        line[(self.write + line.len() - frames) % line.len()]
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Repeats the sound after a delay, the repeats are fed back into the delay to get more and more quiet echoes.
+/// With `tail` on, `modify` extends the samples until the echoes die out so that they are not cut off
+pub struct Delay {
+    taps: Vec<DelayTap>,
+    feedback: f32,
+    wet: f32,
+    dry: f32,
+    ping_pong: bool,
+    tail: bool,
+    state: DelayState,
+}
+
+impl Delay {
+    /// Creates a delay with a single echo, the feedback (between 0 and 0.99) is how much of an echo goes back into the delay
+    pub fn new(time: DelayTime, feedback: f32) -> Delay {
+        Delay::multi_tap(vec![DelayTap::new(time, 1.0)], feedback)
+    }
+
+    /// Creates a delay with an echo per tap, the longest tap is fed back into the delay
+    pub fn multi_tap(taps: Vec<DelayTap>, feedback: f32) -> Delay {
+        Delay {
+            taps,
+            feedback: feedback.clamp(0.0, 0.99),
+            wet: 0.5,
+            dry: 1.0,
+            ping_pong: false,
+            tail: true,
+            state: DelayState::default(),
+        }
+    }
+
+    /// Creates a stereo delay whose echoes bounce between the left and right channels.
+    /// On mono samples it is a normal delay, and channels after the first two get a normal delay
+    pub fn ping_pong(time: DelayTime, feedback: f32) -> Delay {
+        let mut delay = Delay::new(time, feedback);
+        delay.ping_pong = true;
+
+        delay
+    }
+
+    /// Sets the volume of the echoes and of the original sound, 0.5 and 1.0 by default
+    pub fn with_wet_dry(mut self, wet: f32, dry: f32) -> Delay {
+        self.wet = wet;
+        self.dry = dry;
+        self
+    }
+
+    /// Sets if `modify` extends the samples for the echoes to die out, on by default
+    pub fn with_tail(mut self, tail: bool) -> Delay {
+        self.tail = tail;
+        self
+    }
+
+    /// Returns the length of the longest tap in frames
+    fn longest_tap(&self, sample_rate: u32) -> usize {
+        self.taps.iter()
+            .map(|t| t.time.frames(sample_rate))
+            .max()
+            .unwrap_or(1)
+    }
+
+    /// Returns how long the echoes last after the sound stops
+    pub fn tail_duration(&self) -> Duration {
+        let longest = self.taps.iter()
+            .map(|t| t.time.duration())
+            .max()
+            .unwrap_or(Duration::ZERO);
+
+        // Number of times the longest echo goes around before being quiet enough
+        let repeats = if self.feedback > 0.0 {
+            (TAIL_THRESHOLD.ln() / self.feedback.ln()).ceil() as u32 + 1
+        } else {
+            1
+        };
+
+        longest.saturating_mul(repeats).min(MAX_TAIL)
+    }
+
+    fn prepare(&mut self, metadata: &SamplesMetadata) {
+        let channels = metadata.channels.max(1) as usize;
+        if self.state.channels == channels && self.state.sample_rate == metadata.sample_rate {
+            return
+        }
+
+        let len = self.longest_tap(metadata.sample_rate) + 1;
+        self.state = DelayState {
+            channels,
+            sample_rate: metadata.sample_rate,
+            lines: vec![vec![0.0; len]; channels],
+            write: 0,
+        };
+    }
+
+    fn process_frame(&mut self, frame: &mut [IntermediateSampleType], tap_frames: &[usize], longest: usize) {
+        let state = &mut self.state;
+
+        let plain_channels = if self.ping_pong && frame.len() >= 2 {
+            // The input goes in on the left, each pass of the delay moves the echo to the other side
+            let input = (frame[0] + frame[1]) / 2.0;
+            let left = state.read(0, longest);
+            let right = state.read(1, longest);
+
+            state.lines[0][state.write] = input + self.feedback * right;
+            state.lines[1][state.write] = left;
+
+            frame[0] = self.dry * frame[0] + self.wet * left;
+            frame[1] = self.dry * frame[1] + self.wet * right;
+
+            2
+        } else {
+            0
+        };
+
+        // The channels that do not ping-pong get a plain delay
+        for (c, sample) in frame.iter_mut().enumerate().skip(plain_channels) {
+            let echoes = self.taps.iter().zip(tap_frames)
+                .map(|(tap, frames)| tap.gain * state.read(c, *frames))
+                .sum::<IntermediateSampleType>();
+
+            state.lines[c][state.write] = *sample + self.feedback * state.read(c, longest);
+            *sample = self.dry * *sample + self.wet * echoes;
+        }
+
+        state.write = (state.write + 1) % state.lines[0].len();
+    }
+}
+
+impl ModifierTrait for Delay {
+    fn modify(&self, mut samples: Samples<IntermediateSampleType>) -> Samples<IntermediateSampleType> {
+        let mut delay = self.clone();
+        delay.reset();
+
+        let metadata = samples.metadata.clone();
+        if self.tail {
+            let tail_frames = (self.tail_duration().as_secs_f64() * metadata.sample_rate as f64).ceil() as usize;
+            samples.samples.extend(std::iter::repeat_n(0.0, tail_frames * metadata.channels.max(1) as usize));
+        }
+
+        delay.process_block(&mut samples.samples, &metadata);
+
+        samples
+    }
+
+    fn process_block(&mut self, block: &mut [IntermediateSampleType], metadata: &SamplesMetadata) {
+        if self.taps.is_empty() {
+            return
+        }
+
+        self.prepare(metadata);
+
+        let tap_frames = self.taps.iter()
+            .map(|t| t.time.frames(metadata.sample_rate))
+            .collect::<Vec<_>>();
+        let longest = self.longest_tap(metadata.sample_rate);
+
+        for frame in block.chunks_mut(self.state.channels) {
+            self.process_frame(frame, &tap_frames, longest);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.state = DelayState::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::samples::SampleType;
+
+    fn impulse(channels: u16, frames: usize) -> Samples<IntermediateSampleType> {
+        let mut samples = vec![0.0; frames * channels as usize];
+        samples[..channels as usize].iter_mut().for_each(|s| *s = 1.0);
+
+        Samples::new(samples, SamplesMetadata::new(channels, 1000, SampleType::F32))
+    }
+
+    #[test]
+    fn echoes_repeat_and_extend_the_tail() {
+        // 120 BPM, an eighth note is 250 ms
+        let delay = Delay::new(DelayTime::Beats { beats: 0.5, bpm: 120.0 }, 0.5).with_wet_dry(1.0, 0.0);
+        let output = delay.modify(impulse(1, 10));
+
+        assert_eq!(output.samples[250], 1.0);
+        assert_eq!(output.samples[500], 0.5);
+        assert_eq!(output.samples[750], 0.25);
+        assert!(output.samples.len() >= 250 * 11);
+        assert_eq!(delay.clone().with_tail(false).modify(impulse(1, 10)).samples.len(), 10);
+    }
+
+    #[test]
+    fn ping_pong_alternates_sides() {
+        let delay = Delay::ping_pong(DelayTime::Milliseconds(100.0), 0.5).with_wet_dry(1.0, 0.0);
+        let output = delay.modify(impulse(2, 10));
+
+        let frame = |i: usize| (output.samples[i * 2], output.samples[i * 2 + 1]);
+        assert_eq!(frame(100), (1.0, 0.0));
+        assert_eq!(frame(200), (0.0, 1.0));
+        assert_eq!(frame(300), (0.5, 0.0));
+
+        // A truncated stereo buffer ends on half a frame
+        let mut truncated = impulse(2, 10);
+        truncated.samples.pop();
+        assert_eq!(delay.clone().with_tail(false).modify(truncated).samples.len(), 19);
+
+        // The third channel echoes in place
+        let output = delay.with_tail(false).modify(impulse(3, 201));
+        assert_eq!(&output.samples[300..303], &[1.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn huge_delays_are_capped() {
+        assert_eq!(DelayTime::Milliseconds(f32::INFINITY).duration(), MAX_DELAY);
+        assert_eq!(DelayTime::Beats { beats: f32::MAX, bpm: 1.0 }.duration(), MAX_DELAY);
+        assert_eq!(DelayTime::Milliseconds(f32::NAN).duration(), Duration::ZERO);
+    }
+}
@@ -0,0 +1,534 @@
+use std::collections::VecDeque;
+
+use crate::samples::{IntermediateSampleType, Samples, SamplesMetadata};
+
+use super::{utils, ModifierTrait};
+
+/// Levels are floored to this so that silence does not give -inf dB
+const SILENCE_DB: f32 = -120.0;
+
+fn gain_to_db(gain: f32) -> f32 {
+    if gain <= 0.0 {
+        return SILENCE_DB
+    }
+
+    (20.0 * gain.log10()).max(SILENCE_DB)
+}
+
+fn db_to_gain(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+/// Returns the coefficient of a one pole smoother reaching ~63% of the way in that time
+fn time_coefficient(ms: f32, sample_rate: u32) -> f32 {
+    let frames = ms.max(0.0) * 0.001 * sample_rate.max(1) as f32;
+    if frames < 1.0 {
+        return 0.0
+    }
+
+    (-1.0 / frames).exp()
+}
+
+/// Returns the level of the loudest channel of the frame, so that all channels get the same gain
+/// and the stereo image does not move
+fn frame_peak(frame: &[IntermediateSampleType]) -> f32 {
+    frame.iter().fold(0.0, |peak, s| peak.max(s.abs()))
+}
+
+/// Moves the gain reduction towards the target, with the attack when reducing more and the release otherwise
+fn smooth(current: f32, target: f32, attack: f32, release: f32) -> f32 {
+    let coefficient = if target < current { attack } else { release };
+
+    coefficient * current + (1.0 - coefficient) * target
+}
+
+#[derive(Debug, Clone)]
+/// Lowers the volume of what goes above the threshold, by the ratio.
+/// The channels are detected together so that the stereo image does not move
+pub struct Compressor {
+    threshold_db: f32,
+    ratio: f32,
+    attack_ms: f32,
+    release_ms: f32,
+    knee_db: f32,
+    makeup_gain_db: f32,
+    /// The current gain reduction in dB, 0 or negative
+    reduction_db: f32,
+}
+
+impl Compressor {
+    /// Creates a compressor with a 10 ms attack, a 100 ms release, a hard knee and no makeup gain.
+    /// A ratio of 4 means that 4 dB above the threshold come out as 1 dB above it
+    pub fn new(threshold_db: f32, ratio: f32) -> Compressor {
+        Compressor {
+            threshold_db,
+            ratio: ratio.max(1.0),
+            attack_ms: 10.0,
+            release_ms: 100.0,
+            knee_db: 0.0,
+            makeup_gain_db: 0.0,
+            reduction_db: 0.0,
+        }
+    }
+
+    /// Sets how fast the compressor reacts to the audio going above the threshold
+    pub fn with_attack(mut self, attack_ms: f32) -> Compressor {
+        self.attack_ms = attack_ms;
+        self
+    }
+
+    /// Sets how fast the compressor lets go once the audio is back under the threshold
+    pub fn with_release(mut self, release_ms: f32) -> Compressor {
+        self.release_ms = release_ms;
+        self
+    }
+
+    /// Sets the width of the knee in dB, the compression starts progressively around the threshold
+    pub fn with_knee(mut self, knee_db: f32) -> Compressor {
+        self.knee_db = knee_db.max(0.0);
+        self
+    }
+
+    /// Sets the gain applied after the compression, to get back the volume that was lost
+    pub fn with_makeup_gain(mut self, makeup_gain_db: f32) -> Compressor {
+        self.makeup_gain_db = makeup_gain_db;
+        self
+    }
+
+    /// Returns the gain reduction applied at the end of the last block, in dB
+    pub fn gain_reduction_db(&self) -> f32 {
+        self.reduction_db
+    }
+
+    /// Returns the level the input level comes out at, without smoothing
+    fn compressed_level(&self, level_db: f32) -> f32 {
+        let over = level_db - self.threshold_db;
+
+        if 2.0 * over < -self.knee_db {
+            level_db
+        } else if self.knee_db > 0.0 && 2.0 * over.abs() <= self.knee_db {
+            let knee_position = over + self.knee_db / 2.0;
+            level_db + (1.0 / self.ratio - 1.0) * knee_position * knee_position / (2.0 * self.knee_db)
+        } else {
+            self.threshold_db + over / self.ratio
+        }
+    }
+}
+
+impl ModifierTrait for Compressor {
+    fn modify(&self, samples: Samples<IntermediateSampleType>) -> Samples<IntermediateSampleType> {
+        utils::modify_with_process_block(self, samples)
+    }
+
+    fn process_block(&mut self, block: &mut [IntermediateSampleType], metadata: &SamplesMetadata) {
+        let attack = time_coefficient(self.attack_ms, metadata.sample_rate);
+        let release = time_coefficient(self.release_ms, metadata.sample_rate);
+
+        for frame in block.chunks_mut(metadata.channels.max(1) as usize) {
+            let level_db = gain_to_db(frame_peak(frame));
+            let target = self.compressed_level(level_db) - level_db;
+            self.reduction_db = smooth(self.reduction_db, target, attack, release);
+
+            let gain = db_to_gain(self.reduction_db + self.makeup_gain_db);
+            frame.iter_mut().for_each(|s| *s *= gain);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.reduction_db = 0.0;
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Lowers the volume of what goes under the threshold, by the ratio. Makes quiet noises quieter.
+/// The channels are detected together so that the stereo image does not move
+pub struct Expander {
+    threshold_db: f32,
+    ratio: f32,
+    attack_ms: f32,
+    release_ms: f32,
+    range_db: f32,
+    /// The current gain reduction in dB, 0 or negative
+    reduction_db: f32,
+}
+
+impl Expander {
+    /// Creates an expander with a 1 ms attack, a 100 ms release and a range of 60 dB.
+    /// A ratio of 2 means that 1 dB under the threshold comes out as 2 dB under it
+    pub fn new(threshold_db: f32, ratio: f32) -> Expander {
+        Expander {
+            threshold_db,
+            ratio: ratio.max(1.0),
+            attack_ms: 1.0,
+            release_ms: 100.0,
+            range_db: 60.0,
+            reduction_db: 0.0,
+        }
+    }
+
+    /// Sets how fast the expander lets the audio through once it goes back above the threshold
+    pub fn with_attack(mut self, attack_ms: f32) -> Expander {
+        self.attack_ms = attack_ms;
+        self
+    }
+
+    /// Sets how fast the expander lowers the volume once the audio is under the threshold
+    pub fn with_release(mut self, release_ms: f32) -> Expander {
+        self.release_ms = release_ms;
+        self
+    }
+
+    /// Sets the most the volume can be lowered by, in dB
+    pub fn with_range(mut self, range_db: f32) -> Expander {
+        self.range_db = range_db.abs();
+        self
+    }
+}
+
+impl ModifierTrait for Expander {
+    fn modify(&self, samples: Samples<IntermediateSampleType>) -> Samples<IntermediateSampleType> {
+        utils::modify_with_process_block(self, samples)
+    }
+
+    fn process_block(&mut self, block: &mut [IntermediateSampleType], metadata: &SamplesMetadata) {
+        let attack = time_coefficient(self.attack_ms, metadata.sample_rate);
+        let release = time_coefficient(self.release_ms, metadata.sample_rate);
+
+        for frame in block.chunks_mut(metadata.channels.max(1) as usize) {
+            let under = (gain_to_db(frame_peak(frame)) - self.threshold_db).min(0.0);
+            let target = (under * (self.ratio - 1.0)).max(-self.range_db);
+
+            // Opening is the attack here, so the coefficients are swapped compared to a compressor
+            self.reduction_db = smooth(self.reduction_db, target, release, attack);
+
+            let gain = db_to_gain(self.reduction_db);
+            frame.iter_mut().for_each(|s| *s *= gain);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.reduction_db = 0.0;
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Mutes the audio while it is quiet. It opens when the audio goes above the open threshold and closes
+/// when it goes under the close threshold, the gap between both keeps it from chattering
+pub struct Gate {
+    open_threshold_db: f32,
+    close_threshold_db: f32,
+    attack_ms: f32,
+    hold_ms: f32,
+    release_ms: f32,
+    range_db: f32,
+    open: bool,
+    /// Frames left before the gate starts closing
+    hold_frames_left: usize,
+    /// The current gain, between the floor and 1
+    gain: f32,
+}
+
+impl Gate {
+    /// Creates a gate with a 1 ms attack, a 50 ms hold, a 100 ms release that mutes completely when closed.
+    /// The close threshold is brought down to the open one if it is above it
+    pub fn new(open_threshold_db: f32, close_threshold_db: f32) -> Gate {
+        Gate {
+            open_threshold_db,
+            close_threshold_db: close_threshold_db.min(open_threshold_db),
+            attack_ms: 1.0,
+            hold_ms: 50.0,
+            release_ms: 100.0,
+            range_db: -SILENCE_DB,
+            open: false,
+            hold_frames_left: 0,
+            gain: 0.0,
+        }
+    }
+
+    /// Sets how fast the gate opens
+    pub fn with_attack(mut self, attack_ms: f32) -> Gate {
+        self.attack_ms = attack_ms;
+        self
+    }
+
+    /// Sets how long the gate stays open once the audio is under the close threshold
+    pub fn with_hold(mut self, hold_ms: f32) -> Gate {
+        self.hold_ms = hold_ms.max(0.0);
+        self
+    }
+
+    /// Sets how fast the gate closes
+    pub fn with_release(mut self, release_ms: f32) -> Gate {
+        self.release_ms = release_ms;
+        self
+    }
+
+    /// Sets how much the volume is lowered when the gate is closed, in dB
+    pub fn with_range(mut self, range_db: f32) -> Gate {
+        self.range_db = range_db.abs();
+        self
+    }
+
+    /// Returns true if the gate was open at the end of the last block
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+}
+
+impl ModifierTrait for Gate {
+    fn modify(&self, samples: Samples<IntermediateSampleType>) -> Samples<IntermediateSampleType> {
+        utils::modify_with_process_block(self, samples)
+    }
+
+    fn process_block(&mut self, block: &mut [IntermediateSampleType], metadata: &SamplesMetadata) {
+        let attack = time_coefficient(self.attack_ms, metadata.sample_rate);
+        let release = time_coefficient(self.release_ms, metadata.sample_rate);
+        let hold_frames = (self.hold_ms * 0.001 * metadata.sample_rate as f32) as usize;
+        let floor = if self.range_db >= -SILENCE_DB { 0.0 } else { db_to_gain(-self.range_db) };
+
+        for frame in block.chunks_mut(metadata.channels.max(1) as usize) {
+            let level_db = gain_to_db(frame_peak(frame));
+
+            if level_db >= self.open_threshold_db {
+                self.open = true;
+                self.hold_frames_left = hold_frames;
+            } else if level_db < self.close_threshold_db && self.open {
+                if self.hold_frames_left == 0 {
+                    self.open = false;
+                } else {
+                    self.hold_frames_left -= 1;
+                }
+            }
+
+            self.gain = if self.open {
+                attack * self.gain + (1.0 - attack)
+            } else {
+                release * self.gain + (1.0 - release) * floor
+            };
+
+            frame.iter_mut().for_each(|s| *s *= self.gain);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.open = false;
+        self.hold_frames_left = 0;
+        self.gain = 0.0;
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct LimiterState {
+    channels: usize,
+    sample_rate: u32,
+    /// The delayed samples, interleaved
+    delay: VecDeque<IntermediateSampleType>,
+    /// The gains needed in the look-ahead window as (frame, gain), increasing so the front is the minimum
+    needed_gains: VecDeque<(u64, f32)>,
+    /// The minimum gain after the release
+    released_gain: f32,
+    /// The last released gains, averaged to ramp the gain down before the peaks
+    ramp: VecDeque<f32>,
+    ramp_sum: f64,
+    frame: u64,
+}
+
+#[derive(Debug, Clone)]
+/// Keeps the audio under the ceiling without ever going over it. It looks ahead to lower the volume
+/// smoothly before the peaks, which delays the audio by the look-ahead when used in real time
+pub struct Limiter {
+    ceiling_db: f32,
+    lookahead_ms: f32,
+    release_ms: f32,
+    state: LimiterState,
+}
+
+impl Limiter {
+    /// Creates a limiter with a 5 ms look-ahead and a 50 ms release
+    pub fn new(ceiling_db: f32) -> Limiter {
+        Limiter {
+            ceiling_db,
+            lookahead_ms: 5.0,
+            release_ms: 50.0,
+            state: LimiterState::default(),
+        }
+    }
+
+    /// Sets how far the limiter looks ahead, longer is smoother but adds latency
+    pub fn with_lookahead(mut self, lookahead_ms: f32) -> Limiter {
+        self.lookahead_ms = lookahead_ms.max(0.0);
+        self
+    }
+
+    /// Sets how fast the volume comes back after a peak
+    pub fn with_release(mut self, release_ms: f32) -> Limiter {
+        self.release_ms = release_ms;
+        self
+    }
+
+    /// Returns the delay added to the audio in real time, in frames
+    pub fn latency_frames(&self, sample_rate: u32) -> usize {
+        (self.lookahead_ms * 0.001 * sample_rate as f32).round() as usize
+    }
+
+    /// Limits the frame and writes the frame that comes out of the delay in its place
+    fn process_frame(&mut self, frame: &mut [IntermediateSampleType], window: usize, release: f32, ceiling: f32) {
+        let state = &mut self.state;
+
+        let peak = frame_peak(frame);
+        let needed_gain = if peak > ceiling { ceiling / peak } else { 1.0 };
+
+        // Sliding minimum over the look-ahead window
+        while state.needed_gains.back().is_some_and(|(_, g)| *g >= needed_gain) {
+            state.needed_gains.pop_back();
+        }
+        state.needed_gains.push_back((state.frame, needed_gain));
+        while state.needed_gains.front().is_some_and(|(f, _)| *f + window as u64 <= state.frame) {
+            state.needed_gains.pop_front();
+        }
+        let window_gain = state.needed_gains.front().map(|(_, g)| *g).unwrap_or(1.0);
+
+        state.released_gain = if window_gain < state.released_gain {
+            window_gain
+        } else {
+            release * state.released_gain + (1.0 - release) * window_gain
+        };
+
+        // Averaging over the window ramps the gain down to the minimum by the time the peak comes out of the delay
+        state.ramp.push_back(state.released_gain);
+        state.ramp_sum += state.released_gain as f64;
+        if state.ramp.len() > window {
+            state.ramp_sum -= state.ramp.pop_front().unwrap_or(1.0) as f64;
+        }
+        let gain = (state.ramp_sum / window as f64) as f32;
+
+        state.delay.extend(frame.iter());
+        for sample in frame.iter_mut() {
+            *sample = state.delay.pop_front().unwrap_or(0.0) * gain;
+        }
+
+        state.frame += 1;
+    }
+
+    fn prepare(&mut self, metadata: &SamplesMetadata) {
+        let channels = metadata.channels.max(1) as usize;
+        if self.state.channels == channels && self.state.sample_rate == metadata.sample_rate {
+            return
+        }
+
+        // Fills the delay with silence, which does not need any gain reduction
+        let delay = self.latency_frames(metadata.sample_rate);
+        self.state = LimiterState {
+            channels,
+            sample_rate: metadata.sample_rate,
+            delay: std::iter::repeat_n(0.0, delay * channels).collect(),
+            needed_gains: VecDeque::new(),
+            released_gain: 1.0,
+            ramp: std::iter::repeat_n(1.0, delay).collect(),
+            ramp_sum: delay as f64,
+            frame: 0,
+        };
+    }
+}
+
+impl ModifierTrait for Limiter {
+    fn modify(&self, mut samples: Samples<IntermediateSampleType>) -> Samples<IntermediateSampleType> {
+        let mut limiter = self.clone();
+        limiter.reset();
+
+        // Pushes the end out of the delay, then removes the delay from the start
+        let metadata = samples.metadata.clone();
+        let channels = metadata.channels.max(1) as usize;
+        let delay = self.latency_frames(metadata.sample_rate) * channels;
+        let len = samples.samples.len();
+
+        samples.samples.extend(std::iter::repeat_n(0.0, delay));
+        limiter.process_block(&mut samples.samples, &metadata);
+        samples.samples.drain(..delay);
+        samples.samples.truncate(len);
+
+        samples
+    }
+
+    fn process_block(&mut self, block: &mut [IntermediateSampleType], metadata: &SamplesMetadata) {
+        self.prepare(metadata);
+
+        let window = self.latency_frames(metadata.sample_rate) + 1;
+        let release = time_coefficient(self.release_ms, metadata.sample_rate);
+        let ceiling = db_to_gain(self.ceiling_db);
+
+        for frame in block.chunks_mut(self.state.channels) {
+            self.process_frame(frame, window, release, ceiling);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.state = LimiterState::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::samples::SampleType;
+
+    fn stereo(samples: Vec<IntermediateSampleType>) -> Samples<IntermediateSampleType> {
+        Samples::new(samples, SamplesMetadata::new(2, 1000, SampleType::F32))
+    }
+
+    #[test]
+    fn compressor_reduces_by_the_ratio() {
+        let compressor = Compressor::new(-20.0, 4.0).with_attack(0.0).with_makeup_gain(3.0);
+
+        // Only the left channel is loud, both get the same gain
+        let output = compressor.modify(stereo(vec![1.0, 0.5].repeat(100)));
+        let left_db = gain_to_db(output.samples[198]);
+
+        assert!((left_db - (-15.0 + 3.0)).abs() < 0.01);
+        assert!((output.samples[199] / output.samples[198] - 0.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn compressor_at_the_threshold_stays_finite() {
+        // Full scale is 0 dB, exactly on the threshold with a hard knee
+        let output = Compressor::new(0.0, 4.0).with_attack(0.0).modify(stereo(vec![1.0; 20]));
+
+        assert!(output.samples.iter().all(|s| (s - 1.0).abs() < 1e-5));
+    }
+
+    #[test]
+    fn limiter_never_goes_over_the_ceiling() {
+        let mut samples = vec![0.1; 400];
+        samples[200] = 2.0;
+        samples[201] = -1.5;
+
+        let limiter = Limiter::new(-6.0).with_lookahead(10.0);
+        let output = limiter.modify(stereo(samples));
+
+        assert_eq!(output.samples.len(), 400);
+        assert!(output.samples.iter().all(|s| s.abs() <= db_to_gain(-6.0) + 1e-6));
+        // The audio is not shifted
+        assert!(output.samples[200] > 0.4);
+    }
+
+    #[test]
+    fn gate_hysteresis_keeps_it_open_between_thresholds() {
+        let metadata = SamplesMetadata::new(1, 1000, SampleType::F32);
+        let mut gate = Gate::new(-20.0, -40.0).with_hold(0.0).with_attack(0.0).with_release(0.0);
+
+        let mut block = vec![0.05; 10];
+        gate.process_block(&mut block, &metadata);
+        assert!(!gate.is_open());
+        assert_eq!(block, vec![0.0; 10]);
+
+        let mut block = vec![0.5; 10];
+        gate.process_block(&mut block, &metadata);
+        assert!(gate.is_open());
+
+        // -26 dB is under the open threshold but above the close one
+        let mut block = vec![0.05; 10];
+        gate.process_block(&mut block, &metadata);
+        assert!(gate.is_open());
+        assert_eq!(block, vec![0.05; 10]);
+    }
+}
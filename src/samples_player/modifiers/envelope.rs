@@ -0,0 +1,161 @@
+use crate::samples::{IntermediateSampleType, Samples, SamplesMetadata};
+use crate::samples_player::FadeCurve;
+
+use super::ModifierTrait;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// A point of an `Envelope`, the gain moves from one point to the next along the curve of the first
+pub struct Breakpoint {
+    /// When the gain is reached, in seconds from the start of the samples
+    pub time: f32,
+    /// The gain the volume is multiplied by
+    pub gain: f32,
+    /// The shape of the change towards the next point
+    pub curve: FadeCurve,
+}
+
+impl Breakpoint {
+    /// Creates a breakpoint, the change towards the next point is linear
+    pub fn new(time: f32, gain: f32) -> Breakpoint {
+        Breakpoint {
+            time,
+            gain,
+            curve: FadeCurve::Linear,
+        }
+    }
+
+    /// Creates a breakpoint with the gain in decibels
+    pub fn from_db(time: f32, gain_db: f32) -> Breakpoint {
+        Breakpoint::new(time, 10f32.powf(gain_db / 20.0))
+    }
+
+    /// Sets the shape of the change towards the next point
+    pub fn with_curve(mut self, curve: FadeCurve) -> Breakpoint {
+        self.curve = curve;
+        self
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// Automates the volume with breakpoints, the gain is held before the first and after the last one.
+/// Without breakpoints the samples are left as is
+pub struct Envelope {
+    breakpoints: Vec<Breakpoint>,
+    /// Frames played since the stream started, for `process_block`
+    elapsed: usize,
+}
+
+impl Envelope {
+    /// Creates an envelope from the breakpoints, they do not need to be in order
+    pub fn new(breakpoints: Vec<Breakpoint>) -> Envelope {
+        let mut envelope = Envelope { breakpoints, elapsed: 0 };
+        envelope.sort();
+
+        envelope
+    }
+
+    /// Creates an envelope with linear changes between the (time in seconds, gain) points
+    pub fn from_points(points: &[(f32, f32)]) -> Envelope {
+        Envelope::new(points.iter().map(|(time, gain)| Breakpoint::new(*time, *gain)).collect())
+    }
+
+    fn sort(&mut self) {
+        self.breakpoints.sort_by(|a, b| a.time.total_cmp(&b.time));
+    }
+
+    /// Adds a breakpoint, moving the gain to the gain at the time
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) {
+        self.breakpoints.push(breakpoint);
+        self.sort();
+    }
+
+    /// Removes every breakpoint
+    pub fn clear(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    /// Returns the breakpoints, in order of time
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    /// Returns the time of the last breakpoint, in seconds
+    pub fn duration(&self) -> f32 {
+        self.breakpoints.last().map(|b| b.time).unwrap_or(0.0)
+    }
+
+    /// Returns the gain at the time, in seconds
+    pub fn gain_at(&self, time: f32) -> f32 {
+        let next = self.breakpoints.partition_point(|b| b.time <= time);
+
+        match (next.checked_sub(1).map(|i| &self.breakpoints[i]), self.breakpoints.get(next)) {
+            (Some(from), Some(to)) => {
+                let progress = (time - from.time) / (to.time - from.time);
+
+                // The curves go from 0 to 1, they are stretched between the two gains in the direction of the change
+                if to.gain >= from.gain {
+                    from.gain + (to.gain - from.gain) * from.curve.fade_in_gain(progress)
+                } else {
+                    to.gain + (from.gain - to.gain) * from.curve.fade_out_gain(progress)
+                }
+            },
+            (Some(last), None) => last.gain,
+            (None, Some(first)) => first.gain,
+            (None, None) => 1.0,
+        }
+    }
+
+    fn apply(&self, block: &mut [IntermediateSampleType], metadata: &SamplesMetadata, start_frame: usize) {
+        let sample_rate = metadata.sample_rate.max(1) as f64;
+        for (i, frame) in block.chunks_mut(metadata.channels.max(1) as usize).enumerate() {
+            let gain = self.gain_at(((start_frame + i) as f64 / sample_rate) as f32);
+            frame.iter_mut().for_each(|s| *s *= gain);
+        }
+    }
+}
+
+impl ModifierTrait for Envelope {
+    fn modify(&self, mut samples: Samples<IntermediateSampleType>) -> Samples<IntermediateSampleType> {
+        let metadata = samples.metadata.clone();
+        self.apply(&mut samples.samples, &metadata, 0);
+
+        samples
+    }
+
+    fn process_block(&mut self, block: &mut [IntermediateSampleType], metadata: &SamplesMetadata) {
+        self.apply(block, metadata, self.elapsed);
+        self.elapsed += block.len() / metadata.channels.max(1) as usize;
+    }
+
+    fn reset(&mut self) {
+        self.elapsed = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::samples::SampleType;
+
+    #[test]
+    fn follows_the_breakpoints_and_holds_the_ends() {
+        let envelope = Envelope::from_points(&[(2.0, 0.0), (1.0, 1.0), (3.0, 0.5)]);
+        assert_eq!(envelope.gain_at(0.0), 1.0);
+        assert_eq!(envelope.gain_at(1.5), 0.5);
+        assert_eq!(envelope.gain_at(2.5), 0.25);
+        assert_eq!(envelope.gain_at(10.0), 0.5);
+        assert_eq!(Envelope::default().gain_at(1.0), 1.0);
+
+        // The same gains whether the samples are modified at once or in blocks
+        let samples = Samples::new(vec![1.0; 80], SamplesMetadata::new(2, 10, SampleType::F32));
+        let modified = envelope.modify(samples.clone());
+
+        let mut blocks = envelope.clone();
+        let mut streamed = samples.samples.clone();
+        for block in streamed.chunks_mut(6) {
+            blocks.process_block(block, &samples.metadata);
+        }
+        assert_eq!(modified.samples, streamed);
+        assert_eq!(modified.samples[40], 0.0);
+    }
+}
@@ -0,0 +1,415 @@
+use std::fmt::Display;
+use std::str::FromStr;
+
+use crate::errors::{Error, PlayError};
+use crate::samples::{IntermediateSampleType, Samples, SamplesMetadata};
+
+use super::{Biquad, FilterType, ModifierTrait};
+
+/// Center frequencies of the octave bands of ISO 266
+const ISO_OCTAVE_FREQUENCIES: [f32; 10] = [31.5, 63.0, 125.0, 250.0, 500.0, 1000.0, 2000.0, 4000.0, 8000.0, 16000.0];
+
+/// Center frequencies of the third of octave bands of ISO 266
+const ISO_THIRD_OCTAVE_FREQUENCIES: [f32; 31] = [
+    20.0, 25.0, 31.5, 40.0, 50.0, 63.0, 80.0, 100.0, 125.0, 160.0, 200.0, 250.0, 315.0, 400.0, 500.0, 630.0,
+    800.0, 1000.0, 1250.0, 1600.0, 2000.0, 2500.0, 3150.0, 4000.0, 5000.0, 6300.0, 8000.0, 10000.0, 12500.0, 16000.0, 20000.0,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The layouts of a graphic equalizer, one peaking band per ISO center frequency
+pub enum GraphicEq {
+    /// 10 bands, one per octave from 31.5 Hz to 16 kHz
+    TenBand,
+    /// 31 bands, one per third of octave from 20 Hz to 20 kHz
+    ThirtyOneBand,
+}
+
+impl GraphicEq {
+    /// Returns the center frequencies of the bands
+    pub fn frequencies(&self) -> &'static [f32] {
+        match self {
+            GraphicEq::TenBand => &ISO_OCTAVE_FREQUENCIES,
+            GraphicEq::ThirtyOneBand => &ISO_THIRD_OCTAVE_FREQUENCIES,
+        }
+    }
+
+    /// Returns the Q that makes each band as wide as the space between two centers
+    pub fn q(&self) -> f32 {
+        let octaves: f32 = match self {
+            GraphicEq::TenBand => 1.0,
+            GraphicEq::ThirtyOneBand => 1.0 / 3.0,
+        };
+
+        2f32.powf(octaves).sqrt() / (2f32.powf(octaves) - 1.0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// A band of an `Equalizer`, see `Biquad` for what the settings do
+pub struct EqBand {
+    /// The shape of the band
+    pub filter_type: FilterType,
+    /// In Hz
+    pub frequency: f32,
+    /// How wide the band is, or how resonant for the passes
+    pub q: f32,
+    /// In dB, only used by the shelves and peaking filters
+    pub gain_db: f32,
+    /// A disabled band does nothing, but keeps its settings
+    pub enabled: bool,
+}
+
+impl EqBand {
+    /// Creates an enabled band
+    pub fn new(filter_type: FilterType, frequency: f32, q: f32, gain_db: f32) -> EqBand {
+        EqBand {
+            filter_type,
+            frequency,
+            q,
+            gain_db,
+            enabled: true,
+        }
+    }
+
+    /// Creates a peaking band, the usual band of a parametric equalizer
+    pub fn peaking(frequency: f32, q: f32, gain_db: f32) -> EqBand {
+        EqBand::new(FilterType::Peaking, frequency, q, gain_db)
+    }
+
+    fn filter(&self) -> Biquad {
+        Biquad::new(self.filter_type, self.frequency, self.q, self.gain_db)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// The settings of an `Equalizer` under a name, can be saved as text and parsed back:
+/// ```text
+/// name: Bass boost
+/// output_gain: -3
+/// band: low_shelf 120 0.707 6
+/// band: peaking 3000 1.41 -2 off
+/// ```
+pub struct EqPreset {
+    /// The name of the preset, on a single line
+    pub name: String,
+    /// The bands in the order they are applied
+    pub bands: Vec<EqBand>,
+    /// In dB
+    pub output_gain_db: f32,
+}
+
+impl Display for EqPreset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "name: {}", self.name)?;
+        writeln!(f, "output_gain: {}", self.output_gain_db)?;
+
+        for band in &self.bands {
+            write!(f, "band: {} {} {} {}", filter_type_name(band.filter_type), band.frequency, band.q, band.gain_db)?;
+            if !band.enabled {
+                f.write_str(" off")?;
+            }
+            writeln!(f)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for EqPreset {
+    type Err = PlayError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut preset = EqPreset {
+            name: String::new(),
+            bands: Vec::new(),
+            output_gain_db: 0.0,
+        };
+
+        for line in s.lines().map(|l| l.trim()).filter(|l| !l.is_empty()) {
+            let invalid = || PlayError::ParseError(format!("the preset line '{line}'"));
+
+            let (key, value) = line.split_once(':').ok_or_else(invalid)?;
+            let value = value.trim();
+
+            match key.trim() {
+                "name" => preset.name = value.to_string(),
+                "output_gain" => preset.output_gain_db = value.parse().map_err(|_| invalid())?,
+                "band" => preset.bands.push(parse_band(value).ok_or_else(invalid)?),
+                _ => return Err(invalid()),
+            }
+        }
+
+        Ok(preset)
+    }
+}
+
+fn filter_type_name(filter_type: FilterType) -> &'static str {
+    match filter_type {
+        FilterType::LowPass => "low_pass",
+        FilterType::HighPass => "high_pass",
+        FilterType::BandPass => "band_pass",
+        FilterType::Notch => "notch",
+        FilterType::AllPass => "all_pass",
+        FilterType::LowShelf => "low_shelf",
+        FilterType::HighShelf => "high_shelf",
+        FilterType::Peaking => "peaking",
+    }
+}
+
+fn parse_band(s: &str) -> Option<EqBand> {
+    let mut parts = s.split_whitespace();
+
+    let filter_type = match parts.next()? {
+        "low_pass" => FilterType::LowPass,
+        "high_pass" => FilterType::HighPass,
+        "band_pass" => FilterType::BandPass,
+        "notch" => FilterType::Notch,
+        "all_pass" => FilterType::AllPass,
+        "low_shelf" => FilterType::LowShelf,
+        "high_shelf" => FilterType::HighShelf,
+        "peaking" => FilterType::Peaking,
+        _ => return None,
+    };
+    let frequency = parts.next()?.parse().ok()?;
+    let q = parts.next()?.parse().ok()?;
+    let gain_db = parts.next()?.parse().ok()?;
+    let enabled = match parts.next() {
+        None | Some("on") => true,
+        Some("off") => false,
+        Some(_) => return None,
+    };
+
+    Some(EqBand { filter_type, frequency, q, gain_db, enabled })
+}
+
+#[derive(Debug, Clone, Default)]
+/// Runs the samples through a chain of filter bands, then applies the output gain.
+/// Changing a band while playing in real time keeps the memory of its filter so that there is no click
+pub struct Equalizer {
+    bands: Vec<EqBand>,
+    /// One filter per band, in the same order
+    filters: Vec<Biquad>,
+    output_gain_db: f32,
+}
+
+impl Equalizer {
+    /// Creates an equalizer without bands, it does nothing until bands are added
+    pub fn new() -> Equalizer {
+        Equalizer::default()
+    }
+
+    /// Creates a graphic equalizer, all the bands are flat
+    pub fn graphic(layout: GraphicEq) -> Equalizer {
+        let mut equalizer = Equalizer::new();
+        for frequency in layout.frequencies() {
+            equalizer.add_band(EqBand::peaking(*frequency, layout.q(), 0.0));
+        }
+
+        equalizer
+    }
+
+    /// Creates an equalizer from the preset
+    pub fn from_preset(preset: &EqPreset) -> Equalizer {
+        let mut equalizer = Equalizer::new();
+        equalizer.apply_preset(preset);
+
+        equalizer
+    }
+
+    /// Replaces the bands and output gain by the ones of the preset
+    pub fn apply_preset(&mut self, preset: &EqPreset) {
+        self.bands.clear();
+        self.filters.clear();
+        for band in &preset.bands {
+            self.add_band(band.clone());
+        }
+
+        self.output_gain_db = preset.output_gain_db;
+    }
+
+    /// Returns the bands and output gain as a preset with that name
+    pub fn preset(&self, name: &str) -> EqPreset {
+        EqPreset {
+            name: name.to_string(),
+            bands: self.bands.clone(),
+            output_gain_db: self.output_gain_db,
+        }
+    }
+
+    /// Adds a band at the end of the chain and returns its index
+    pub fn add_band(&mut self, band: EqBand) -> usize {
+        self.filters.push(band.filter());
+        self.bands.push(band);
+
+        self.bands.len() - 1
+    }
+
+    /// Removes the band at the index
+    pub fn remove_band(&mut self, index: usize) -> Error<EqBand> {
+        self.check_index(index)?;
+        self.filters.remove(index);
+
+        Ok(self.bands.remove(index))
+    }
+
+    /// Returns all the bands
+    pub fn bands(&self) -> &[EqBand] {
+        &self.bands
+    }
+
+    /// Returns the band at the index
+    pub fn band(&self, index: usize) -> Option<&EqBand> {
+        self.bands.get(index)
+    }
+
+    fn check_index(&self, index: usize) -> Error<()> {
+        if index >= self.bands.len() {
+            return Err(PlayError::BandDoesNotExist(index))
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the settings of the band at the index
+    pub fn set_band(&mut self, index: usize, band: EqBand) -> Error<()> {
+        self.check_index(index)?;
+
+        let filter = &mut self.filters[index];
+        filter.set_filter_type(band.filter_type);
+        filter.set_frequency(band.frequency);
+        filter.set_q(band.q);
+        filter.set_gain_db(band.gain_db);
+
+        self.bands[index] = band;
+
+        Ok(())
+    }
+
+    /// Changes the gain of the band at the index, in dB
+    pub fn set_gain(&mut self, index: usize, gain_db: f32) -> Error<()> {
+        self.check_index(index)?;
+
+        self.bands[index].gain_db = gain_db;
+        self.filters[index].set_gain_db(gain_db);
+
+        Ok(())
+    }
+
+    /// Enables or disables the band at the index
+    pub fn set_enabled(&mut self, index: usize, enabled: bool) -> Error<()> {
+        self.check_index(index)?;
+
+        self.bands[index].enabled = enabled;
+
+        Ok(())
+    }
+
+    /// Sets the gains of the bands in order, for graphic equalizers. Extra gains are ignored
+    pub fn set_gains(&mut self, gains_db: &[f32]) {
+        for (index, gain_db) in gains_db.iter().enumerate().take(self.bands.len()) {
+            self.bands[index].gain_db = *gain_db;
+            self.filters[index].set_gain_db(*gain_db);
+        }
+    }
+
+    /// Returns the gain applied after the bands, in dB
+    pub fn output_gain_db(&self) -> f32 {
+        self.output_gain_db
+    }
+
+    /// Sets the gain applied after the bands, in dB. Useful to avoid clipping when boosting
+    pub fn set_output_gain_db(&mut self, gain_db: f32) {
+        self.output_gain_db = gain_db;
+    }
+
+    /// Returns how much the equalizer changes the volume at the frequency, in dB
+    pub fn response_db(&self, frequency: f32, sample_rate: u32) -> f32 {
+        self.bands.iter().zip(&self.filters)
+            .filter(|(b, _)| b.enabled)
+            .map(|(_, f)| f.response_db(frequency, sample_rate))
+            .sum::<f32>() + self.output_gain_db
+    }
+
+    /// Returns the response at that many frequencies spaced logarithmically from 20 Hz to 20 kHz
+    /// (or the nyquist frequency if it is lower), as (frequency, dB) pairs. Made to draw the curve of the equalizer
+    pub fn response_curve(&self, points: usize, sample_rate: u32) -> Vec<(f32, f32)> {
+        let min = 20f32;
+        let max = (sample_rate as f32 / 2.0).min(20000.0).max(min);
+
+        (0..points)
+            .map(|i| {
+                let position = i as f32 / (points.max(2) - 1) as f32;
+                let frequency = min * (max / min).powf(position);
+
+                (frequency, self.response_db(frequency, sample_rate))
+            })
+            .collect()
+    }
+
+    fn output_gain(&self) -> IntermediateSampleType {
+        10f32.powf(self.output_gain_db / 20.0)
+    }
+}
+
+impl ModifierTrait for Equalizer {
+    fn modify(&self, samples: Samples<IntermediateSampleType>) -> Samples<IntermediateSampleType> {
+        let mut samples = self.bands.iter().zip(&self.filters)
+            .filter(|(b, _)| b.enabled)
+            .fold(samples, |samples, (_, f)| f.modify(samples));
+
+        let gain = self.output_gain();
+        samples.samples.iter_mut().for_each(|s| *s *= gain);
+
+        samples
+    }
+
+    fn process_block(&mut self, block: &mut [IntermediateSampleType], metadata: &SamplesMetadata) {
+        for (_, filter) in self.bands.iter().zip(self.filters.iter_mut()).filter(|(b, _)| b.enabled) {
+            filter.process_block(block, metadata);
+        }
+
+        let gain = self.output_gain();
+        block.iter_mut().for_each(|s| *s *= gain);
+    }
+
+    fn reset(&mut self) {
+        self.filters.iter_mut().for_each(|f| f.reset());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn graphic_bands_shape_the_response() {
+        let mut equalizer = Equalizer::graphic(GraphicEq::TenBand);
+        assert_eq!(equalizer.bands().len(), 10);
+        assert!(equalizer.response_db(1000.0, 48000).abs() < 0.01);
+
+        // Boosts the 1 kHz band only
+        equalizer.set_gain(5, 6.0).unwrap();
+        assert!((equalizer.response_db(1000.0, 48000) - 6.0).abs() < 0.01);
+        assert!(equalizer.response_db(63.0, 48000).abs() < 0.1);
+
+        equalizer.set_enabled(5, false).unwrap();
+        assert!(equalizer.response_db(1000.0, 48000).abs() < 0.01);
+        assert!(matches!(equalizer.set_gain(10, 1.0), Err(PlayError::BandDoesNotExist(10))));
+    }
+
+    #[test]
+    fn presets_round_trip_through_text() {
+        let mut equalizer = Equalizer::new();
+        equalizer.add_band(EqBand::new(FilterType::LowShelf, 120.0, 0.707, 6.0));
+        equalizer.add_band(EqBand::peaking(3000.0, 1.41, -2.5));
+        equalizer.set_enabled(1, false).unwrap();
+        equalizer.set_output_gain_db(-3.0);
+
+        let preset = equalizer.preset("Bass boost");
+        let parsed: EqPreset = preset.to_string().parse().unwrap();
+        assert_eq!(parsed, preset);
+        assert_eq!(Equalizer::from_preset(&parsed).bands(), equalizer.bands());
+
+        assert!(matches!("band: wobble 100 1 0".parse::<EqPreset>(), Err(PlayError::ParseError(_))));
+    }
+}
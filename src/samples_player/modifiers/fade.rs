@@ -0,0 +1,136 @@
+use std::time::Duration;
+
+use crate::samples::{IntermediateSampleType, Samples, SamplesMetadata};
+use crate::samples_player::FadeCurve;
+
+use super::ModifierTrait;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// If a `Fade` brings the sound in or out
+pub enum FadeDirection {
+    /// From silence to the full volume
+    In,
+    /// From the full volume to silence
+    Out,
+}
+
+#[derive(Debug, Clone)]
+/// Fades the sound in or out.
+/// A fade in starts at the start of the samples and a fade out ends at their end, unless they are given an offset.
+/// In real time the end is not known, so a fade out without an offset starts with the stream
+pub struct Fade {
+    direction: FadeDirection,
+    duration: Duration,
+    curve: FadeCurve,
+    offset: Option<Duration>,
+    /// Frames played since the stream started, for `process_block`
+    elapsed: usize,
+}
+
+impl Fade {
+    /// Creates a fade from silence to the full volume at the start of the samples
+    pub fn fade_in(duration: Duration, curve: FadeCurve) -> Fade {
+        Fade::new(FadeDirection::In, duration, curve)
+    }
+
+    /// Creates a fade from the full volume to silence at the end of the samples
+    pub fn fade_out(duration: Duration, curve: FadeCurve) -> Fade {
+        Fade::new(FadeDirection::Out, duration, curve)
+    }
+
+    /// Creates a fade in the direction
+    pub fn new(direction: FadeDirection, duration: Duration, curve: FadeCurve) -> Fade {
+        Fade {
+            direction,
+            duration,
+            curve,
+            offset: None,
+            elapsed: 0,
+        }
+    }
+
+    /// Starts the fade at the offset from the start of the samples.
+    /// It is silent before a fade in and after a fade out
+    pub fn with_offset(mut self, offset: Duration) -> Fade {
+        self.offset = Some(offset);
+        self
+    }
+
+    /// Returns the gain of the frame, the total frames are only known outside of real time
+    fn gain(&self, frame: usize, total_frames: Option<usize>, sample_rate: u32) -> IntermediateSampleType {
+        let length = (self.duration.as_secs_f64() * sample_rate as f64).round() as usize;
+        let start = match (self.offset, self.direction, total_frames) {
+            (Some(offset), _, _) => (offset.as_secs_f64() * sample_rate as f64).round() as usize,
+            (None, FadeDirection::Out, Some(total)) => total.saturating_sub(length),
+            (None, _, _) => 0,
+        };
+
+        let progress = if frame < start {
+            0.0
+        } else if frame - start >= length {
+            1.0
+        } else {
+            (frame - start) as IntermediateSampleType / length as IntermediateSampleType
+        };
+
+        match self.direction {
+            FadeDirection::In => self.curve.fade_in_gain(progress),
+            FadeDirection::Out => self.curve.fade_out_gain(progress),
+        }
+    }
+}
+
+impl ModifierTrait for Fade {
+    fn modify(&self, mut samples: Samples<IntermediateSampleType>) -> Samples<IntermediateSampleType> {
+        let channels = samples.metadata.channels.max(1) as usize;
+        let total_frames = samples.samples.len() / channels;
+        let sample_rate = samples.metadata.sample_rate;
+
+        for (i, frame) in samples.samples.chunks_mut(channels).enumerate() {
+            let gain = self.gain(i, Some(total_frames), sample_rate);
+            frame.iter_mut().for_each(|s| *s *= gain);
+        }
+
+        samples
+    }
+
+    fn process_block(&mut self, block: &mut [IntermediateSampleType], metadata: &SamplesMetadata) {
+        for frame in block.chunks_mut(metadata.channels.max(1) as usize) {
+            let gain = self.gain(self.elapsed, None, metadata.sample_rate);
+            frame.iter_mut().for_each(|s| *s *= gain);
+
+            self.elapsed += 1;
+        }
+    }
+
+    fn reset(&mut self) {
+        self.elapsed = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::samples::SampleType;
+
+    fn ones(frames: usize) -> Samples<IntermediateSampleType> {
+        Samples::new(vec![1.0; frames * 2], SamplesMetadata::new(2, 10, SampleType::F32))
+    }
+
+    #[test]
+    fn fades_in_at_the_start_and_out_at_the_end() {
+        let faded_in = Fade::fade_in(Duration::from_secs(1), FadeCurve::Linear).modify(ones(20));
+        assert_eq!(&faded_in.samples[..4], &[0.0, 0.0, 0.1, 0.1]);
+        assert_eq!(faded_in.samples[20..], [1.0; 20]);
+
+        let faded_out = Fade::fade_out(Duration::from_secs(1), FadeCurve::Linear).modify(ones(20));
+        assert_eq!(faded_out.samples[..20], [1.0; 20]);
+        assert!(faded_out.samples[38..].iter().all(|s| (s - 0.1).abs() < 1e-6));
+
+        // Every curve goes from silence to the full volume
+        for curve in [FadeCurve::Linear, FadeCurve::EqualPower, FadeCurve::SCurve, FadeCurve::Exponential, FadeCurve::Logarithmic] {
+            assert!(curve.fade_in_gain(0.0).abs() < 1e-6 && (curve.fade_in_gain(1.0) - 1.0).abs() < 1e-6);
+        }
+        assert!(FadeCurve::Exponential.fade_in_gain(0.5) < 0.5 && FadeCurve::Logarithmic.fade_in_gain(0.5) > 0.5);
+    }
+}
@@ -1,6 +1,6 @@
 //! Contains premade modifiers and a trait to make your own modifiers
 
-use crate::cpal_abstraction::{Samples, IntermediateSampleType};
+use crate::cpal_abstraction::{Samples, SamplesMetadata, IntermediateSampleType};
 
 mod r#loop;
 pub use r#loop::Loop;
@@ -10,6 +10,26 @@ mod flatten;
 pub use flatten::Flatten;
 mod shittify;
 pub use shittify::Shittify;
+mod biquad;
+pub use biquad::{Biquad, FilterType};
+mod equalizer;
+pub use equalizer::{Equalizer, EqBand, EqPreset, GraphicEq};
+mod dynamics;
+pub use dynamics::{Compressor, Expander, Gate, Limiter};
+mod delay;
+pub use delay::{Delay, DelayTap, DelayTime};
+mod reverb;
+pub use reverb::Reverb;
+mod convolution;
+pub use convolution::Convolution;
+mod modulation;
+pub use modulation::{Chorus, Flanger, LfoWaveform, Phaser, Tremolo, Vibrato};
+mod pitch;
+pub use pitch::{PitchShift, TimeStretch};
+mod fade;
+pub use fade::{Fade, FadeDirection};
+mod envelope;
+pub use envelope::{Breakpoint, Envelope};
 
 pub mod utils;
 
@@ -26,4 +46,19 @@ pub trait ModifierTrait: std::fmt::Debug {
     /// the sample rate or channel number will do nothing on how the samples are played
     /// * The order of modifiers is important for SamplesPlayer, the modifiers are applied to the result of the previous one
     fn modify(&self, samples: Samples<IntermediateSampleType>) -> Samples<IntermediateSampleType>;
+
+    /// Modifies a block of a longer stream in place, used when the samples are processed in real time (see `Monitor`).
+    /// Modifiers with memory (filters, delays, etc.) should keep it between blocks so that there is no discontinuity.
+    /// By default the block goes through `modify`, what does not fit back in the block is discarded
+    fn process_block(&mut self, block: &mut [IntermediateSampleType], metadata: &SamplesMetadata) {
+        let modified = self.modify(Samples::new(block.to_vec(), metadata.clone()));
+
+        block.iter_mut().for_each(|s| *s = 0.0);
+        for (sample, modified_sample) in block.iter_mut().zip(modified.samples) {
+            *sample = modified_sample;
+        }
+    }
+
+    /// Forgets what was kept between blocks, as if the stream was starting anew
+    fn reset(&mut self) {}
 }
\ No newline at end of file
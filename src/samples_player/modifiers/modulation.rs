@@ -0,0 +1,559 @@
+use std::f32::consts::PI;
+
+use crate::samples::{IntermediateSampleType, Samples, SamplesMetadata};
+
+use super::{utils, ModifierTrait};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// The shape of the low frequency oscillator (LFO) that moves the modulation effects
+pub enum LfoWaveform {
+    /// Smooth back and forth
+    #[default]
+    Sine,
+    /// Straight lines back and forth
+    Triangle,
+    /// Jumps between both ends
+    Square,
+    /// Goes up then jumps back down
+    SawUp,
+    /// Goes down then jumps back up
+    SawDown,
+}
+
+impl LfoWaveform {
+    /// Returns the value of the waveform between -1 and 1, the phase is between 0 and 1
+    pub fn value(&self, phase: f32) -> f32 {
+        match self {
+            LfoWaveform::Sine => (2.0 * PI * phase).sin(),
+            LfoWaveform::Triangle => 1.0 - 4.0 * (phase - 0.25 - (phase - 0.25).floor() - 0.5).abs(),
+            LfoWaveform::Square => if phase < 0.5 { 1.0 } else { -1.0 },
+            LfoWaveform::SawUp => 2.0 * phase - 1.0,
+            LfoWaveform::SawDown => 1.0 - 2.0 * phase,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// A low frequency oscillator, each channel is offset by the stereo phase
+struct Lfo {
+    waveform: LfoWaveform,
+    rate_hz: f32,
+    /// Offset between two channels, in cycles
+    stereo_phase: f32,
+    /// Between 0 and 1
+    phase: f64,
+}
+
+impl Lfo {
+    fn new(rate_hz: f32, stereo_phase_degrees: f32) -> Lfo {
+        Lfo {
+            waveform: LfoWaveform::Sine,
+            rate_hz,
+            stereo_phase: stereo_phase_degrees / 360.0,
+            phase: 0.0,
+        }
+    }
+
+    /// Returns the value of the LFO for the channel, between -1 and 1
+    fn value(&self, channel: usize) -> f32 {
+        let phase = self.phase as f32 + channel as f32 * self.stereo_phase;
+
+        self.waveform.value(phase - phase.floor())
+    }
+
+    /// Returns the value of the LFO for the channel, between 0 and 1
+    fn unipolar_value(&self, channel: usize) -> f32 {
+        (self.value(channel) + 1.0) / 2.0
+    }
+
+    fn advance(&mut self, sample_rate: u32) {
+        self.phase = (self.phase + self.rate_hz.max(0.0) as f64 / sample_rate.max(1) as f64).fract();
+    }
+}
+
+#[derive(Debug, Clone)]
+/// A delay line whose length is moved by an LFO, the core of the chorus, flanger and vibrato
+struct ModulatedDelay {
+    lfo: Lfo,
+    delay_ms: f32,
+    depth_ms: f32,
+    feedback: f32,
+    mix: f32,
+    /// One delay line per channel
+    lines: Vec<Vec<IntermediateSampleType>>,
+    write: usize,
+    sample_rate: u32,
+}
+
+impl ModulatedDelay {
+    fn new(rate_hz: f32, delay_ms: f32, depth_ms: f32, feedback: f32, mix: f32, stereo_phase_degrees: f32) -> ModulatedDelay {
+        ModulatedDelay {
+            lfo: Lfo::new(rate_hz, stereo_phase_degrees),
+            delay_ms: delay_ms.max(0.0),
+            depth_ms: depth_ms.max(0.0),
+            feedback: feedback.clamp(-0.95, 0.95),
+            mix: mix.clamp(0.0, 1.0),
+            lines: Vec::new(),
+            write: 0,
+            sample_rate: 0,
+        }
+    }
+
+    fn prepare(&mut self, metadata: &SamplesMetadata) {
+        let channels = metadata.channels.max(1) as usize;
+        if self.lines.len() == channels && self.sample_rate == metadata.sample_rate {
+            return
+        }
+
+        // Room for the longest delay and the interpolation
+        let len = ((self.delay_ms + self.depth_ms) * 0.001 * metadata.sample_rate as f32).ceil() as usize + 2;
+        self.lines = vec![vec![0.0; len]; channels];
+        self.write = 0;
+        self.sample_rate = metadata.sample_rate;
+    }
+
+    /// Reads the line that many frames ago, between two samples if it falls between them
+    fn read(&self, channel: usize, frames: f32) -> IntermediateSampleType {
+        let line = &self.lines[channel];
+        let len = line.len();
+
+        let whole = frames.floor() as usize;
+        let fraction = frames - whole as f32;
+        let newer = line[(self.write + len - whole) % len];
+        let older = line[(self.write + 2 * len - whole - 1) % len];
+
+        newer + (older - newer) * fraction
+    }
+
+    fn process_block(&mut self, block: &mut [IntermediateSampleType], metadata: &SamplesMetadata) {
+        self.prepare(metadata);
+
+        let frames_per_ms = metadata.sample_rate as f32 * 0.001;
+        let channels = self.lines.len();
+        for frame in block.chunks_mut(channels) {
+            for (c, sample) in frame.iter_mut().enumerate() {
+                let delay = (self.delay_ms + self.depth_ms * self.lfo.unipolar_value(c)) * frames_per_ms;
+                // At least a frame so that the line is never read where it is being written
+                let delayed = self.read(c, delay.max(1.0));
+
+                self.lines[c][self.write] = *sample + self.feedback * delayed;
+                *sample = (1.0 - self.mix) * *sample + self.mix * delayed;
+            }
+
+            self.write = (self.write + 1) % self.lines[0].len();
+            self.lfo.advance(metadata.sample_rate);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.lines.clear();
+        self.lfo.phase = 0.0;
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Thickens the sound by mixing it with copies of itself whose delay slowly moves, like several voices singing together
+pub struct Chorus {
+    delay: ModulatedDelay,
+}
+
+impl Default for Chorus {
+    fn default() -> Self {
+        Chorus::new()
+    }
+}
+
+impl Chorus {
+    /// Creates a chorus at 0.8 Hz with a 20 ms delay moved by 5 ms, no feedback, half mixed
+    /// and the channels offset by 90 degrees
+    pub fn new() -> Chorus {
+        Chorus {
+            delay: ModulatedDelay::new(0.8, 20.0, 5.0, 0.0, 0.5, 90.0),
+        }
+    }
+
+    /// Sets how many times per second the delay moves back and forth
+    pub fn with_rate(mut self, rate_hz: f32) -> Chorus {
+        self.delay.lfo.rate_hz = rate_hz;
+        self
+    }
+
+    /// Sets how much the delay moves, in milliseconds
+    pub fn with_depth(mut self, depth_ms: f32) -> Chorus {
+        self.delay.depth_ms = depth_ms.max(0.0);
+        self
+    }
+
+    /// Sets the shortest delay, in milliseconds
+    pub fn with_delay(mut self, delay_ms: f32) -> Chorus {
+        self.delay.delay_ms = delay_ms.max(0.0);
+        self
+    }
+
+    /// Sets how much of the delayed sound goes back into the delay, between -0.95 and 0.95
+    pub fn with_feedback(mut self, feedback: f32) -> Chorus {
+        self.delay.feedback = feedback.clamp(-0.95, 0.95);
+        self
+    }
+
+    /// Sets how much of the delayed sound is heard, 0 is only the original and 1 only the delayed sound
+    pub fn with_mix(mut self, mix: f32) -> Chorus {
+        self.delay.mix = mix.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Sets the shape of the LFO
+    pub fn with_waveform(mut self, waveform: LfoWaveform) -> Chorus {
+        self.delay.lfo.waveform = waveform;
+        self
+    }
+
+    /// Sets how much the LFO of each channel is ahead of the previous channel, in degrees
+    pub fn with_stereo_phase(mut self, degrees: f32) -> Chorus {
+        self.delay.lfo.stereo_phase = degrees / 360.0;
+        self
+    }
+}
+
+impl ModifierTrait for Chorus {
+    fn modify(&self, samples: Samples<IntermediateSampleType>) -> Samples<IntermediateSampleType> {
+        utils::modify_with_process_block(self, samples)
+    }
+
+    fn process_block(&mut self, block: &mut [IntermediateSampleType], metadata: &SamplesMetadata) {
+        self.delay.process_block(block, metadata);
+    }
+
+    fn reset(&mut self) {
+        self.delay.reset();
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Mixes the sound with a copy of itself whose very short delay sweeps back and forth, the "jet plane" effect
+pub struct Flanger {
+    delay: ModulatedDelay,
+}
+
+impl Default for Flanger {
+    fn default() -> Self {
+        Flanger::new()
+    }
+}
+
+impl Flanger {
+    /// Creates a flanger at 0.25 Hz with a 1 ms delay moved by 3 ms, a 0.5 feedback, half mixed
+    /// and the channels in phase
+    pub fn new() -> Flanger {
+        Flanger {
+            delay: ModulatedDelay::new(0.25, 1.0, 3.0, 0.5, 0.5, 0.0),
+        }
+    }
+
+    /// Sets how many times per second the delay sweeps back and forth
+    pub fn with_rate(mut self, rate_hz: f32) -> Flanger {
+        self.delay.lfo.rate_hz = rate_hz;
+        self
+    }
+
+    /// Sets how much the delay sweeps, in milliseconds
+    pub fn with_depth(mut self, depth_ms: f32) -> Flanger {
+        self.delay.depth_ms = depth_ms.max(0.0);
+        self
+    }
+
+    /// Sets the shortest delay, in milliseconds
+    pub fn with_delay(mut self, delay_ms: f32) -> Flanger {
+        self.delay.delay_ms = delay_ms.max(0.0);
+        self
+    }
+
+    /// Sets how much of the delayed sound goes back into the delay, between -0.95 and 0.95.
+    /// More feedback makes the effect more pronounced, negative feedback makes it hollower
+    pub fn with_feedback(mut self, feedback: f32) -> Flanger {
+        self.delay.feedback = feedback.clamp(-0.95, 0.95);
+        self
+    }
+
+    /// Sets how much of the delayed sound is heard, 0.5 gives the deepest notches
+    pub fn with_mix(mut self, mix: f32) -> Flanger {
+        self.delay.mix = mix.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Sets the shape of the LFO
+    pub fn with_waveform(mut self, waveform: LfoWaveform) -> Flanger {
+        self.delay.lfo.waveform = waveform;
+        self
+    }
+
+    /// Sets how much the LFO of each channel is ahead of the previous channel, in degrees
+    pub fn with_stereo_phase(mut self, degrees: f32) -> Flanger {
+        self.delay.lfo.stereo_phase = degrees / 360.0;
+        self
+    }
+}
+
+impl ModifierTrait for Flanger {
+    fn modify(&self, samples: Samples<IntermediateSampleType>) -> Samples<IntermediateSampleType> {
+        utils::modify_with_process_block(self, samples)
+    }
+
+    fn process_block(&mut self, block: &mut [IntermediateSampleType], metadata: &SamplesMetadata) {
+        self.delay.process_block(block, metadata);
+    }
+
+    fn reset(&mut self) {
+        self.delay.reset();
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Makes the pitch waver by only playing a copy of the sound whose delay moves back and forth
+pub struct Vibrato {
+    delay: ModulatedDelay,
+}
+
+impl Vibrato {
+    /// Creates a vibrato, the depth is how much the delay moves in milliseconds (2 ms is about a semitone at 5 Hz)
+    pub fn new(rate_hz: f32, depth_ms: f32) -> Vibrato {
+        Vibrato {
+            delay: ModulatedDelay::new(rate_hz, 0.0, depth_ms, 0.0, 1.0, 0.0),
+        }
+    }
+
+    /// Sets the shape of the LFO
+    pub fn with_waveform(mut self, waveform: LfoWaveform) -> Vibrato {
+        self.delay.lfo.waveform = waveform;
+        self
+    }
+
+    /// Sets how much the LFO of each channel is ahead of the previous channel, in degrees
+    pub fn with_stereo_phase(mut self, degrees: f32) -> Vibrato {
+        self.delay.lfo.stereo_phase = degrees / 360.0;
+        self
+    }
+}
+
+impl ModifierTrait for Vibrato {
+    fn modify(&self, samples: Samples<IntermediateSampleType>) -> Samples<IntermediateSampleType> {
+        utils::modify_with_process_block(self, samples)
+    }
+
+    fn process_block(&mut self, block: &mut [IntermediateSampleType], metadata: &SamplesMetadata) {
+        self.delay.process_block(block, metadata);
+    }
+
+    fn reset(&mut self) {
+        self.delay.reset();
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Makes the volume go up and down
+pub struct Tremolo {
+    lfo: Lfo,
+    depth: f32,
+}
+
+impl Tremolo {
+    /// Creates a tremolo, the depth is between 0 (no effect) and 1 (the volume goes down to silence)
+    pub fn new(rate_hz: f32, depth: f32) -> Tremolo {
+        Tremolo {
+            lfo: Lfo::new(rate_hz, 0.0),
+            depth: depth.clamp(0.0, 1.0),
+        }
+    }
+
+    /// Sets the shape of the LFO
+    pub fn with_waveform(mut self, waveform: LfoWaveform) -> Tremolo {
+        self.lfo.waveform = waveform;
+        self
+    }
+
+    /// Sets how much the LFO of each channel is ahead of the previous channel, in degrees.
+    /// 180 degrees makes the sound go from one side to the other (auto-pan)
+    pub fn with_stereo_phase(mut self, degrees: f32) -> Tremolo {
+        self.lfo.stereo_phase = degrees / 360.0;
+        self
+    }
+}
+
+impl ModifierTrait for Tremolo {
+    fn modify(&self, samples: Samples<IntermediateSampleType>) -> Samples<IntermediateSampleType> {
+        utils::modify_with_process_block(self, samples)
+    }
+
+    fn process_block(&mut self, block: &mut [IntermediateSampleType], metadata: &SamplesMetadata) {
+        for frame in block.chunks_mut(metadata.channels.max(1) as usize) {
+            for (c, sample) in frame.iter_mut().enumerate() {
+                *sample *= 1.0 - self.depth * (1.0 - self.lfo.unipolar_value(c));
+            }
+
+            self.lfo.advance(metadata.sample_rate);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.lfo.phase = 0.0;
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Sweeps notches through the sound with a chain of all-pass filters whose frequency is moved by an LFO
+pub struct Phaser {
+    lfo: Lfo,
+    stages: usize,
+    min_frequency: f32,
+    max_frequency: f32,
+    feedback: f32,
+    mix: f32,
+    /// The last input and output of each stage for each channel
+    states: Vec<Vec<[f32; 2]>>,
+    /// The last output of the chain for each channel, fed back into it
+    last_outputs: Vec<f32>,
+}
+
+impl Default for Phaser {
+    fn default() -> Self {
+        Phaser::new()
+    }
+}
+
+impl Phaser {
+    /// Creates a 4 stage phaser at 0.5 Hz sweeping from 200 Hz to 2 kHz, with a 0.5 feedback, half mixed
+    /// and the channels in phase
+    pub fn new() -> Phaser {
+        Phaser {
+            lfo: Lfo::new(0.5, 0.0),
+            stages: 4,
+            min_frequency: 200.0,
+            max_frequency: 2000.0,
+            feedback: 0.5,
+            mix: 0.5,
+            states: Vec::new(),
+            last_outputs: Vec::new(),
+        }
+    }
+
+    /// Sets how many times per second the notches sweep back and forth
+    pub fn with_rate(mut self, rate_hz: f32) -> Phaser {
+        self.lfo.rate_hz = rate_hz;
+        self
+    }
+
+    /// Sets the frequencies the sweep goes between, in Hz
+    pub fn with_range(mut self, min_frequency: f32, max_frequency: f32) -> Phaser {
+        self.min_frequency = min_frequency.max(1.0);
+        self.max_frequency = max_frequency.max(self.min_frequency);
+        self
+    }
+
+    /// Sets the number of all-pass filters, each pair adds a notch
+    pub fn with_stages(mut self, stages: usize) -> Phaser {
+        self.stages = stages.max(1);
+        self.states.clear();
+        self
+    }
+
+    /// Sets how much of the output goes back into the filters, between -0.95 and 0.95
+    pub fn with_feedback(mut self, feedback: f32) -> Phaser {
+        self.feedback = feedback.clamp(-0.95, 0.95);
+        self
+    }
+
+    /// Sets how much of the filtered sound is heard, 0.5 gives the deepest notches
+    pub fn with_mix(mut self, mix: f32) -> Phaser {
+        self.mix = mix.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Sets the shape of the LFO
+    pub fn with_waveform(mut self, waveform: LfoWaveform) -> Phaser {
+        self.lfo.waveform = waveform;
+        self
+    }
+
+    /// Sets how much the LFO of each channel is ahead of the previous channel, in degrees
+    pub fn with_stereo_phase(mut self, degrees: f32) -> Phaser {
+        self.lfo.stereo_phase = degrees / 360.0;
+        self
+    }
+}
+
+impl ModifierTrait for Phaser {
+    fn modify(&self, samples: Samples<IntermediateSampleType>) -> Samples<IntermediateSampleType> {
+        utils::modify_with_process_block(self, samples)
+    }
+
+    fn process_block(&mut self, block: &mut [IntermediateSampleType], metadata: &SamplesMetadata) {
+        let channels = metadata.channels.max(1) as usize;
+        if self.states.len() != channels {
+            self.states = vec![vec![[0.0; 2]; self.stages]; channels];
+            self.last_outputs = vec![0.0; channels];
+        }
+
+        let sample_rate = metadata.sample_rate.max(1) as f32;
+        let ratio = self.max_frequency / self.min_frequency;
+        for frame in block.chunks_mut(channels) {
+            for (c, sample) in frame.iter_mut().enumerate() {
+                // The sweep is exponential so that it sounds even
+                let frequency = (self.min_frequency * ratio.powf(self.lfo.unipolar_value(c))).min(sample_rate * 0.49);
+                let tan = (PI * frequency / sample_rate).tan();
+                let coefficient = (tan - 1.0) / (tan + 1.0);
+
+                let mut filtered = *sample + self.feedback * self.last_outputs[c];
+                for state in self.states[c].iter_mut() {
+                    let output = coefficient * filtered + state[0] - coefficient * state[1];
+                    state[0] = filtered;
+                    state[1] = output;
+                    filtered = output;
+                }
+                self.last_outputs[c] = filtered;
+
+                *sample = (1.0 - self.mix) * *sample + self.mix * filtered;
+            }
+
+            self.lfo.advance(metadata.sample_rate);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.states.clear();
+        self.last_outputs.clear();
+        self.lfo.phase = 0.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::samples::SampleType;
+
+    #[test]
+    fn tremolo_follows_the_lfo_with_a_stereo_offset() {
+        // 1 Hz at 4 Hz sample rate, the square LFO is up for 2 frames then down for 2
+        let tremolo = Tremolo::new(1.0, 0.5).with_waveform(LfoWaveform::Square).with_stereo_phase(180.0);
+        let output = tremolo.modify(Samples::new(vec![1.0; 8], SamplesMetadata::new(2, 4, SampleType::F32)));
+
+        assert_eq!(output.samples, vec![1.0, 0.5, 1.0, 0.5, 0.5, 1.0, 0.5, 1.0]);
+    }
+
+    #[test]
+    fn modulated_delays_keep_the_length_and_delay_the_sound() {
+        let mut impulse = vec![0.0; 100];
+        impulse[0] = 1.0;
+        let samples = Samples::new(impulse, SamplesMetadata::new(1, 1000, SampleType::F32));
+
+        // Without depth the chorus is a plain 20 ms delay
+        let output = Chorus::new().with_depth(0.0).with_mix(1.0).modify(samples.clone());
+        assert_eq!(output.samples.len(), 100);
+        assert!((output.samples[20] - 1.0).abs() < 1e-6);
+        assert_eq!(output.samples.iter().filter(|s| s.abs() > 1e-6).count(), 1);
+
+        // A phaser only moves the phase, all of the sound is still there
+        let output = Phaser::new().with_feedback(0.0).with_mix(1.0).modify(samples);
+        let energy: f32 = output.samples.iter().map(|s| s * s).sum();
+        assert!(energy > 0.9 && energy < 1.01);
+    }
+}
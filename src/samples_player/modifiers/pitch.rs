@@ -0,0 +1,429 @@
+use std::f32::consts::PI;
+
+use crate::samples::{IntermediateSampleType, Samples, SamplesMetadata};
+use crate::samples_player::fft::{Complex, Fft};
+
+use super::{utils, ModifierTrait};
+
+/// Frames analysed at once by default, long enough to resolve the pitch of low voices
+const DEFAULT_FFT_SIZE: usize = 2048;
+/// How many analysed frames overlap each sample
+const OVERLAP: usize = 4;
+/// The sum of the squared Hann windows at 4 times overlap, the output is divided by it
+const WINDOW_GAIN: f32 = 1.5;
+/// The cepstrum is cut above this quefrency (in seconds) to get the formants without the pitch
+const FORMANT_QUEFRENCY: f32 = 0.001;
+
+fn hann(size: usize) -> Vec<f32> {
+    (0..size).map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / size as f32).cos()).collect()
+}
+
+/// Brings the phase back between -π and π
+fn wrap_phase(phase: f32) -> f32 {
+    phase - 2.0 * PI * (phase / (2.0 * PI)).round()
+}
+
+/// Smooths the magnitudes into the spectral envelope (the formants) by only keeping the low quefrencies of the cepstrum
+fn spectral_envelope(fft: &Fft, magnitudes: &[f32], lifter: usize, buffer: &mut [Complex], envelope: &mut [f32]) {
+    let size = buffer.len();
+    for (k, bin) in buffer.iter_mut().enumerate() {
+        let magnitude = magnitudes[k.min(size - k)];
+        *bin = Complex::new((magnitude + 1e-9).ln(), 0.0);
+    }
+
+    fft.inverse(buffer);
+    for (quefrency, bin) in buffer.iter_mut().enumerate() {
+        if quefrency > lifter && quefrency < size - lifter {
+            *bin = Complex::default();
+        }
+    }
+    fft.forward(buffer);
+
+    for (e, bin) in envelope.iter_mut().zip(buffer.iter()) {
+        *e = bin.re.exp();
+    }
+}
+
+#[derive(Debug, Clone)]
+/// The analysis and synthesis of one channel of a phase vocoder
+struct Vocoder {
+    size: usize,
+    last_phases: Vec<f32>,
+    phase_sums: Vec<f32>,
+    spectrum: Vec<Complex>,
+}
+
+impl Vocoder {
+    fn new(size: usize) -> Vocoder {
+        Vocoder {
+            size,
+            last_phases: vec![0.0; size / 2 + 1],
+            phase_sums: vec![0.0; size / 2 + 1],
+            spectrum: vec![Complex::default(); size],
+        }
+    }
+
+    /// Finds the magnitude and the true frequency (in bins) of each bin of the frame, analysed `hop` frames after the previous one
+    fn analyze(&mut self, fft: &Fft, frame: &[f32], window: &[f32], hop: usize, magnitudes: &mut [f32], frequencies: &mut [f32]) {
+        for ((bin, sample), w) in self.spectrum.iter_mut().zip(frame).zip(window) {
+            *bin = Complex::new(sample * w, 0.0);
+        }
+        fft.forward(&mut self.spectrum);
+
+        // How much the phase of a bin turns between two frames
+        let expected = 2.0 * PI * hop as f32 / self.size as f32;
+        for (k, last_phase) in self.last_phases.iter_mut().enumerate() {
+            let bin = self.spectrum[k];
+            let phase = bin.phase();
+
+            let deviation = wrap_phase(phase - *last_phase - k as f32 * expected);
+            *last_phase = phase;
+
+            magnitudes[k] = bin.magnitude();
+            frequencies[k] = k as f32 + deviation / expected;
+        }
+    }
+
+    /// Builds the windowed frame out of the magnitudes and frequencies, synthesized `hop` frames after the previous one
+    fn synthesize(&mut self, fft: &Fft, magnitudes: &[f32], frequencies: &[f32], window: &[f32], hop: usize, output: &mut [f32]) {
+        let size = self.size;
+        for (k, phase_sum) in self.phase_sums.iter_mut().enumerate() {
+            *phase_sum = wrap_phase(*phase_sum + 2.0 * PI * frequencies[k] * hop as f32 / size as f32);
+            self.spectrum[k] = Complex::from_polar(magnitudes[k], *phase_sum);
+        }
+        // The negative frequencies mirror the positive ones for the signal to be real
+        for k in size / 2 + 1..size {
+            self.spectrum[k] = self.spectrum[size - k].conj();
+        }
+        fft.inverse(&mut self.spectrum);
+
+        for ((sample, bin), w) in output.iter_mut().zip(&self.spectrum).zip(window) {
+            *sample = bin.re * w / WINDOW_GAIN;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct PitchShiftState {
+    channels: usize,
+    sample_rate: u32,
+    fft: Option<Fft>,
+    window: Vec<f32>,
+    vocoders: Vec<Vocoder>,
+    /// The last fft size frames of input of each channel
+    inputs: Vec<Vec<f32>>,
+    /// The overlapped synthesized frames of each channel
+    accumulators: Vec<Vec<f32>>,
+    /// The hop of output being played for each channel
+    outputs: Vec<Vec<f32>>,
+    /// Position in the current hop
+    position: usize,
+    magnitudes: Vec<f32>,
+    frequencies: Vec<f32>,
+    shifted_magnitudes: Vec<f32>,
+    shifted_frequencies: Vec<f32>,
+    envelope: Vec<f32>,
+    cepstrum: Vec<Complex>,
+    frame: Vec<f32>,
+}
+
+#[derive(Debug, Clone)]
+/// Changes the pitch without changing the speed with a phase vocoder, unlike `Shittify` or resampling.
+/// With formant preservation on, voices keep their timbre instead of sounding like chipmunks or giants.
+/// Delays the audio by `latency_frames` in real time, `modify` compensates for it
+pub struct PitchShift {
+    semitones: f32,
+    preserve_formants: bool,
+    fft_size: usize,
+    state: PitchShiftState,
+}
+
+impl PitchShift {
+    /// Creates a pitch shift by the semitones, 12 is an octave up and -12 an octave down. Fractions of semitones are fine
+    pub fn new(semitones: f32) -> PitchShift {
+        PitchShift {
+            semitones,
+            preserve_formants: false,
+            fft_size: DEFAULT_FFT_SIZE,
+            state: PitchShiftState::default(),
+        }
+    }
+
+    /// Sets if the formants (the timbre of voices) stay where they are, off by default
+    pub fn with_formant_preservation(mut self, preserve_formants: bool) -> PitchShift {
+        self.preserve_formants = preserve_formants;
+        self
+    }
+
+    /// Sets the frames analysed at once (rounded up to a power of two), 2048 by default.
+    /// Bigger sizes are better for low and steady sounds, smaller ones for drums and have less latency
+    pub fn with_fft_size(mut self, frames: usize) -> PitchShift {
+        self.fft_size = frames.max(OVERLAP * 4).next_power_of_two();
+        self.state = PitchShiftState::default();
+        self
+    }
+
+    /// Returns how much the frequencies are multiplied by
+    pub fn ratio(&self) -> f32 {
+        2f32.powf(self.semitones / 12.0)
+    }
+
+    /// Returns the delay added to the audio in real time, in frames
+    pub fn latency_frames(&self) -> usize {
+        self.fft_size - self.fft_size / OVERLAP
+    }
+
+    fn prepare(&mut self, metadata: &SamplesMetadata) {
+        let channels = metadata.channels.max(1) as usize;
+        if self.state.channels == channels && self.state.sample_rate == metadata.sample_rate {
+            return
+        }
+
+        let size = self.fft_size;
+        let bins = size / 2 + 1;
+        self.state = PitchShiftState {
+            channels,
+            sample_rate: metadata.sample_rate,
+            fft: Some(Fft::new(size)),
+            window: hann(size),
+            vocoders: vec![Vocoder::new(size); channels],
+            inputs: vec![vec![0.0; size]; channels],
+            accumulators: vec![vec![0.0; size]; channels],
+            outputs: vec![vec![0.0; size / OVERLAP]; channels],
+            position: 0,
+            magnitudes: vec![0.0; bins],
+            frequencies: vec![0.0; bins],
+            shifted_magnitudes: vec![0.0; bins],
+            shifted_frequencies: vec![0.0; bins],
+            envelope: vec![1.0; bins],
+            cepstrum: vec![Complex::default(); size],
+            frame: vec![0.0; size],
+        };
+    }
+
+    /// Shifts the last fft size frames of input and overlaps them with the previous ones
+    fn shift_frame(&mut self) {
+        let ratio = self.ratio();
+        let size = self.fft_size;
+        let hop = size / OVERLAP;
+        let lifter = ((FORMANT_QUEFRENCY * self.state.sample_rate as f32) as usize).clamp(1, size / 2 - 1);
+
+        let PitchShiftState {
+            fft, window, vocoders, inputs, accumulators, outputs,
+            magnitudes, frequencies, shifted_magnitudes, shifted_frequencies, envelope, cepstrum, frame, ..
+        } = &mut self.state;
+        let fft = match fft {
+            Some(f) => &*f,
+            None => return,
+        };
+
+        for (((vocoder, input), accumulator), output) in vocoders.iter_mut().zip(inputs.iter_mut()).zip(accumulators.iter_mut()).zip(outputs.iter_mut()) {
+            vocoder.analyze(fft, input, window, hop, magnitudes, frequencies);
+
+            // The envelope is taken out before moving the bins and put back after, so that it does not move
+            if self.preserve_formants {
+                spectral_envelope(fft, magnitudes, lifter, cepstrum, envelope);
+                magnitudes.iter_mut().zip(envelope.iter()).for_each(|(m, e)| *m /= e);
+            }
+
+            shifted_magnitudes.iter_mut().for_each(|m| *m = 0.0);
+            shifted_frequencies.iter_mut().for_each(|f| *f = 0.0);
+            for (k, (magnitude, frequency)) in magnitudes.iter().zip(frequencies.iter()).enumerate() {
+                let shifted = (k as f32 * ratio).round() as usize;
+                if shifted < shifted_magnitudes.len() {
+                    shifted_magnitudes[shifted] += magnitude;
+                    shifted_frequencies[shifted] = frequency * ratio;
+                }
+            }
+
+            if self.preserve_formants {
+                shifted_magnitudes.iter_mut().zip(envelope.iter()).for_each(|(m, e)| *m *= e);
+            }
+
+            vocoder.synthesize(fft, shifted_magnitudes, shifted_frequencies, window, hop, frame);
+
+            for (a, s) in accumulator.iter_mut().zip(frame.iter()) {
+                *a += s;
+            }
+            output.copy_from_slice(&accumulator[..hop]);
+            accumulator.copy_within(hop.., 0);
+            accumulator[size - hop..].iter_mut().for_each(|a| *a = 0.0);
+
+            input.copy_within(hop.., 0);
+        }
+    }
+}
+
+impl ModifierTrait for PitchShift {
+    fn modify(&self, mut samples: Samples<IntermediateSampleType>) -> Samples<IntermediateSampleType> {
+        let channels = samples.metadata.channels.max(1) as usize;
+        let frames = samples.samples.len() / channels;
+
+        // The latency is pushed out at the end then removed from the start
+        let latency = self.latency_frames();
+        samples.samples.resize((frames + latency) * channels, 0.0);
+        let mut samples = utils::modify_with_process_block(self, samples);
+        samples.samples.drain(..latency * channels);
+
+        samples
+    }
+
+    fn process_block(&mut self, block: &mut [IntermediateSampleType], metadata: &SamplesMetadata) {
+        self.prepare(metadata);
+
+        let hop = self.fft_size / OVERLAP;
+        let latency = self.latency_frames();
+        for frame in block.chunks_mut(self.state.channels) {
+            let position = self.state.position;
+
+            for (c, sample) in frame.iter_mut().enumerate() {
+                self.state.inputs[c][latency + position] = *sample;
+                *sample = self.state.outputs[c][position];
+            }
+
+            self.state.position += 1;
+            if self.state.position == hop {
+                self.state.position = 0;
+                self.shift_frame();
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.state = PitchShiftState::default();
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Changes the tempo without changing the pitch with a phase vocoder, the samples get shorter or longer.
+/// The length can not change in real time so `process_block` leaves the audio as is,
+/// use `SpeedMode::TimeStretch` on a player to change the speed of a stream
+pub struct TimeStretch {
+    tempo: f32,
+    fft_size: usize,
+}
+
+impl TimeStretch {
+    /// Creates a time stretch, the tempo is the speed factor (1.5 is 50% faster, 0.5 twice as slow), between 0.1 and 10
+    pub fn new(tempo: f32) -> TimeStretch {
+        TimeStretch {
+            tempo: tempo.clamp(0.1, 10.0),
+            fft_size: DEFAULT_FFT_SIZE,
+        }
+    }
+
+    /// Sets the frames analysed at once (rounded up to a power of two), 2048 by default.
+    /// Bigger sizes are better for low and steady sounds, smaller ones for drums
+    pub fn with_fft_size(mut self, frames: usize) -> TimeStretch {
+        self.fft_size = frames.max(OVERLAP * 4).next_power_of_two();
+        self
+    }
+
+    /// Returns the speed factor
+    pub fn tempo(&self) -> f32 {
+        self.tempo
+    }
+
+    /// Stretches a single channel into `output_frames` frames
+    fn stretch_channel(&self, fft: &Fft, window: &[f32], channel: &[f32], output_frames: usize) -> Vec<f32> {
+        let size = self.fft_size;
+        let synthesis_hop = size / OVERLAP;
+        let analysis_hop = ((synthesis_hop as f32 * self.tempo).round() as usize).max(1);
+        let bins = size / 2 + 1;
+
+        let mut vocoder = Vocoder::new(size);
+        let (mut magnitudes, mut frequencies) = (vec![0.0; bins], vec![0.0; bins]);
+        let mut input = vec![0.0; size];
+        let mut frame = vec![0.0; size];
+
+        // The frames are centered on their position, the output starts half a frame late to fit the first one
+        let mut output = vec![0.0; output_frames + 2 * size];
+        let mut m = 0;
+        while m * synthesis_hop < output_frames + size / 2 {
+            let start = (m * analysis_hop) as isize - (size / 2) as isize;
+            for (i, sample) in input.iter_mut().enumerate() {
+                let index = start + i as isize;
+                *sample = if index >= 0 { channel.get(index as usize).copied().unwrap_or(0.0) } else { 0.0 };
+            }
+
+            vocoder.analyze(fft, &input, window, analysis_hop, &mut magnitudes, &mut frequencies);
+            vocoder.synthesize(fft, &magnitudes, &frequencies, window, synthesis_hop, &mut frame);
+
+            for (o, s) in output[m * synthesis_hop..].iter_mut().zip(&frame) {
+                *o += s;
+            }
+            m += 1;
+        }
+
+        output.drain(..size / 2);
+        output.truncate(output_frames);
+
+        output
+    }
+}
+
+impl ModifierTrait for TimeStretch {
+    fn modify(&self, samples: Samples<IntermediateSampleType>) -> Samples<IntermediateSampleType> {
+        let metadata = samples.metadata.clone();
+        if self.tempo == 1.0 || samples.samples.is_empty() {
+            return samples
+        }
+
+        let frames = samples.samples.len() / metadata.channels.max(1) as usize;
+        let output_frames = (frames as f64 / self.tempo as f64).round() as usize;
+
+        let fft = Fft::new(self.fft_size);
+        let window = hann(self.fft_size);
+        let channels = utils::seperate_channels(samples).iter()
+            .map(|c| self.stretch_channel(&fft, &window, c, output_frames))
+            .collect::<Vec<_>>();
+
+        Samples::new(utils::join_channels(channels), metadata)
+    }
+
+    fn process_block(&mut self, _block: &mut [IntermediateSampleType], _metadata: &SamplesMetadata) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::samples::SampleType;
+
+    fn sine(frequency: f32, frames: usize) -> Samples<IntermediateSampleType> {
+        let samples = (0..frames).map(|i| (2.0 * PI * frequency * i as f32 / 44100.0).sin() * 0.5).collect();
+        Samples::new(samples, SamplesMetadata::new(1, 44100, SampleType::F32))
+    }
+
+    /// Estimates the frequency of the middle of the samples by counting the zero crossings
+    fn frequency(samples: &[f32]) -> f32 {
+        let middle = &samples[samples.len() / 4..samples.len() * 3 / 4];
+        let crossings = middle.windows(2).filter(|w| (w[0] < 0.0) != (w[1] < 0.0)).count();
+
+        crossings as f32 / 2.0 / (middle.len() as f32 / 44100.0)
+    }
+
+    #[test]
+    fn pitch_shift_moves_the_pitch_but_keeps_the_length() {
+        let input = sine(440.0, 44100);
+
+        let output = PitchShift::new(12.0).modify(input.clone());
+        assert_eq!(output.samples.len(), input.samples.len());
+        assert!((frequency(&output.samples) - 880.0).abs() < 15.0);
+
+        let output = PitchShift::new(-12.0).with_formant_preservation(true).modify(input);
+        assert!((frequency(&output.samples) - 220.0).abs() < 10.0);
+    }
+
+    #[test]
+    fn time_stretch_changes_the_length_but_keeps_the_pitch() {
+        let input = sine(440.0, 44100);
+
+        let faster = TimeStretch::new(2.0).modify(input.clone());
+        assert_eq!(faster.samples.len(), 22050);
+        assert!((frequency(&faster.samples) - 440.0).abs() < 10.0);
+
+        let slower = TimeStretch::new(0.5).modify(input);
+        assert_eq!(slower.samples.len(), 88200);
+        assert!((frequency(&slower.samples) - 440.0).abs() < 10.0);
+    }
+}
@@ -0,0 +1,306 @@
+use std::time::Duration;
+
+use crate::samples::{IntermediateSampleType, Samples, SamplesMetadata};
+
+use super::ModifierTrait;
+
+/// Lengths of the comb filters at 44.1 kHz, from Freeverb
+const COMB_TUNINGS: [usize; 8] = [1116, 1188, 1277, 1356, 1422, 1491, 1557, 1617];
+/// Lengths of the all-pass filters at 44.1 kHz, from Freeverb
+const ALLPASS_TUNINGS: [usize; 4] = [556, 441, 341, 225];
+/// Added to the lengths of the right channel so that both sides are decorrelated
+const STEREO_SPREAD: usize = 23;
+/// The sample rate the tunings are given at
+const TUNING_SAMPLE_RATE: f32 = 44100.0;
+
+// Gains and scales of the parameters, from Freeverb
+const FIXED_GAIN: f32 = 0.015;
+const SCALE_WET: f32 = 3.0;
+const SCALE_ROOM: f32 = 0.28;
+const OFFSET_ROOM: f32 = 0.7;
+const SCALE_DAMP: f32 = 0.4;
+const ALLPASS_FEEDBACK: f32 = 0.5;
+
+/// The tail added by `modify` is never longer than this
+const MAX_TAIL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone)]
+/// A feedback comb filter with a low-pass in the loop, the low-pass is what makes the room sound damped
+struct Comb {
+    buffer: Vec<f32>,
+    index: usize,
+    filter_store: f32,
+}
+
+impl Comb {
+    fn new(len: usize) -> Comb {
+        Comb {
+            buffer: vec![0.0; len.max(1)],
+            index: 0,
+            filter_store: 0.0,
+        }
+    }
+
+    fn process(&mut self, input: f32, feedback: f32, damping: f32) -> f32 {
+        let output = self.buffer[self.index];
+        self.filter_store = output * (1.0 - damping) + self.filter_store * damping;
+        self.buffer[self.index] = input + self.filter_store * feedback;
+        self.index = (self.index + 1) % self.buffer.len();
+
+        output
+    }
+}
+
+#[derive(Debug, Clone)]
+/// A Schroeder all-pass filter, it diffuses the echoes of the combs
+struct AllPass {
+    buffer: Vec<f32>,
+    index: usize,
+}
+
+impl AllPass {
+    fn new(len: usize) -> AllPass {
+        AllPass {
+            buffer: vec![0.0; len.max(1)],
+            index: 0,
+        }
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        let buffered = self.buffer[self.index];
+        self.buffer[self.index] = input + buffered * ALLPASS_FEEDBACK;
+        self.index = (self.index + 1) % self.buffer.len();
+
+        buffered - input
+    }
+}
+
+#[derive(Debug, Clone)]
+/// The filters of one side of the reverb
+struct ReverbChannel {
+    combs: Vec<Comb>,
+    allpasses: Vec<AllPass>,
+}
+
+impl ReverbChannel {
+    fn new(sample_rate: u32, spread: usize) -> ReverbChannel {
+        let scale = |len: usize| ((len + spread) as f32 * sample_rate as f32 / TUNING_SAMPLE_RATE).round() as usize;
+
+        ReverbChannel {
+            combs: COMB_TUNINGS.iter().map(|l| Comb::new(scale(*l))).collect(),
+            allpasses: ALLPASS_TUNINGS.iter().map(|l| AllPass::new(scale(*l))).collect(),
+        }
+    }
+
+    fn process(&mut self, input: f32, feedback: f32, damping: f32) -> f32 {
+        let combed = self.combs.iter_mut()
+            .map(|c| c.process(input, feedback, damping))
+            .sum();
+
+        self.allpasses.iter_mut().fold(combed, |sample, a| a.process(sample))
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct ReverbState {
+    channels: usize,
+    sample_rate: u32,
+    left: Option<ReverbChannel>,
+    right: Option<ReverbChannel>,
+    /// Delays the input of the reverb by the pre-delay
+    pre_delay: Vec<f32>,
+    pre_delay_index: usize,
+}
+
+#[derive(Debug, Clone)]
+/// A Freeverb style reverb, parallel comb filters followed by all-pass filters.
+/// Works on mono and stereo samples, only the first two channels get the reverb on samples with more channels.
+/// `modify` appends the tail of the reverb to the samples
+pub struct Reverb {
+    room_size: f32,
+    damping: f32,
+    width: f32,
+    pre_delay_ms: f32,
+    wet: f32,
+    dry: f32,
+    state: ReverbState,
+}
+
+impl Default for Reverb {
+    fn default() -> Self {
+        Reverb::new(0.5, 0.5)
+    }
+}
+
+impl Reverb {
+    /// Creates a reverb, the room size and damping are between 0 and 1.
+    /// A bigger room rings longer and more damping makes it darker
+    pub fn new(room_size: f32, damping: f32) -> Reverb {
+        Reverb {
+            room_size: room_size.clamp(0.0, 1.0),
+            damping: damping.clamp(0.0, 1.0),
+            width: 1.0,
+            pre_delay_ms: 0.0,
+            wet: 0.33,
+            dry: 1.0,
+            state: ReverbState::default(),
+        }
+    }
+
+    /// Sets how wide the reverb is in stereo, from 0 (mono) to 1
+    pub fn with_width(mut self, width: f32) -> Reverb {
+        self.width = width.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Sets the time before the reverb starts, which makes the room sound bigger. At most 30 seconds
+    pub fn with_pre_delay(mut self, pre_delay_ms: f32) -> Reverb {
+        self.pre_delay_ms = pre_delay_ms.max(0.0).min(MAX_TAIL.as_secs_f32() * 1000.0);
+        self
+    }
+
+    /// Sets the volume of the reverb and of the original sound, 0.33 and 1.0 by default
+    pub fn with_wet_dry(mut self, wet: f32, dry: f32) -> Reverb {
+        self.wet = wet;
+        self.dry = dry;
+        self
+    }
+
+    fn feedback(&self) -> f32 {
+        self.room_size * SCALE_ROOM + OFFSET_ROOM
+    }
+
+    /// Returns how long the reverb rings after the sound stops, until it is 60 dB quieter
+    pub fn tail_duration(&self) -> Duration {
+        let longest_comb = (COMB_TUNINGS[COMB_TUNINGS.len() - 1] + STEREO_SPREAD) as f32 / TUNING_SAMPLE_RATE;
+        let repeats = (0.001f32.ln() / self.feedback().ln()).ceil();
+
+        let seconds = self.pre_delay_ms / 1000.0 + longest_comb * repeats;
+
+        Duration::from_secs_f32(seconds.max(0.0).min(MAX_TAIL.as_secs_f32()))
+    }
+
+    fn prepare(&mut self, metadata: &SamplesMetadata) {
+        let channels = metadata.channels.max(1) as usize;
+        if self.state.channels == channels && self.state.sample_rate == metadata.sample_rate {
+            return
+        }
+
+        let pre_delay_frames = (self.pre_delay_ms * 0.001 * metadata.sample_rate as f32).round() as usize;
+        self.state = ReverbState {
+            channels,
+            sample_rate: metadata.sample_rate,
+            left: Some(ReverbChannel::new(metadata.sample_rate, 0)),
+            right: if channels >= 2 { Some(ReverbChannel::new(metadata.sample_rate, STEREO_SPREAD)) } else { None },
+            pre_delay: vec![0.0; pre_delay_frames + 1],
+            pre_delay_index: 0,
+        };
+    }
+}
+
+impl ModifierTrait for Reverb {
+    fn modify(&self, mut samples: Samples<IntermediateSampleType>) -> Samples<IntermediateSampleType> {
+        let mut reverb = self.clone();
+        reverb.reset();
+
+        let metadata = samples.metadata.clone();
+        let tail_frames = (self.tail_duration().as_secs_f64() * metadata.sample_rate as f64).ceil() as usize;
+        samples.samples.extend(std::iter::repeat_n(0.0, tail_frames * metadata.channels.max(1) as usize));
+
+        reverb.process_block(&mut samples.samples, &metadata);
+
+        samples
+    }
+
+    fn process_block(&mut self, block: &mut [IntermediateSampleType], metadata: &SamplesMetadata) {
+        self.prepare(metadata);
+
+        let feedback = self.feedback();
+        let damping = self.damping * SCALE_DAMP;
+        let wet = self.wet * SCALE_WET;
+        let wet_main = wet * (self.width / 2.0 + 0.5);
+        let wet_cross = wet * ((1.0 - self.width) / 2.0);
+
+        let state = &mut self.state;
+        for frame in block.chunks_mut(state.channels) {
+            let stereo = frame.len() >= 2 && state.right.is_some();
+
+            let input = (if stereo { frame[0] + frame[1] } else { frame[0] }) * FIXED_GAIN;
+            let len = state.pre_delay.len();
+            state.pre_delay[state.pre_delay_index] = input;
+            state.pre_delay_index = (state.pre_delay_index + 1) % len;
+            // The oldest sample, written pre-delay frames ago
+            let input = state.pre_delay[state.pre_delay_index];
+
+            let left = match &mut state.left {
+                Some(l) => l.process(input, feedback, damping),
+                None => 0.0,
+            };
+
+            match (&mut state.right, stereo) {
+                (Some(r), true) => {
+                    let right = r.process(input, feedback, damping);
+
+                    frame[0] = left * wet_main + right * wet_cross + frame[0] * self.dry;
+                    frame[1] = right * wet_main + left * wet_cross + frame[1] * self.dry;
+                },
+                _ => frame[0] = left * wet + frame[0] * self.dry,
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.state = ReverbState::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::samples::SampleType;
+
+    fn impulse(channels: u16) -> Samples<IntermediateSampleType> {
+        let mut samples = vec![0.0; 100 * channels as usize];
+        samples[..channels as usize].iter_mut().for_each(|s| *s = 1.0);
+
+        Samples::new(samples, SamplesMetadata::new(channels, 44100, SampleType::F32))
+    }
+
+    fn energy(samples: &[IntermediateSampleType]) -> f32 {
+        samples.iter().map(|s| s * s).sum()
+    }
+
+    #[test]
+    fn appends_a_decaying_tail() {
+        let reverb = Reverb::new(0.8, 0.2).with_wet_dry(1.0, 0.0);
+        let output = reverb.modify(impulse(1));
+
+        assert!(output.samples.len() > 44100);
+        let early = energy(&output.samples[..22050]);
+        let late = energy(&output.samples[output.samples.len() - 22050..]);
+        assert!(early > 0.0);
+        assert!(late < early / 1000.0);
+    }
+
+    #[test]
+    fn huge_pre_delays_are_capped() {
+        assert_eq!(Reverb::default().with_pre_delay(f32::MAX).tail_duration(), MAX_TAIL);
+        assert_eq!(Reverb::default().with_pre_delay(f32::INFINITY).tail_duration(), MAX_TAIL);
+        assert!(Reverb::default().with_pre_delay(f32::NAN).tail_duration() < MAX_TAIL);
+    }
+
+    #[test]
+    fn pre_delay_and_width_on_stereo() {
+        let reverb = Reverb::new(0.5, 0.5).with_width(0.0).with_pre_delay(10.0).with_wet_dry(1.0, 0.0);
+        let output = reverb.modify(impulse(2));
+
+        // Nothing before the pre-delay and the shortest comb
+        let first_sound = output.samples.iter().position(|s| *s != 0.0).unwrap() / 2;
+        assert!(first_sound >= 441 + 1116);
+
+        // Without width both sides are the same
+        for frame in output.samples.chunks(2) {
+            assert!((frame[0] - frame[1]).abs() < 1e-6);
+        }
+    }
+}
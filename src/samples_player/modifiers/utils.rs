@@ -1,6 +1,8 @@
 //! Utils to make your own implementations of `ModifierTrait`
 
 use crate::samples::{Samples, Sample, IntermediateSampleType};
+use super::ModifierTrait;
+use crate::resampler::{self, ResampleQuality};
 
 // TODO: God have mercy for I have sinned, ono I have to debug it didn't work on the first try :((((
 // It works now, still an afront to god though
@@ -114,45 +116,32 @@ pub fn join_channels<T: Sample>(seperated_channels: Vec<Vec<T>>) -> Vec<T> {
     coalescence
 }
 
-// TODO: Add interpolation
 /// Transforms the samples into the desired sample rate, this changes the metadata and samples.
-/// **This does not interpolate the samples.**
+/// Uses the best quality of the resampler, see `resampler::resample` to choose the quality
 pub fn into_sample_rate<T: Sample>(samples: Samples<T>, desired_sample_rate: u32) -> Samples<T> {
-    let old_samples_per_second = 1.0 / samples.metadata.sample_rate as f64;
-    let new_samples_per_second = 1.0 / desired_sample_rate as f64;
+    let sample_type = samples.metadata.sample_type.clone();
 
-    let mut index = 0;
-    let mut time: f64 = old_samples_per_second;
+    // Goes through the float type of the sample since `Sample` only converts from the intermediate type
+    let generic_samples = Samples::new(
+        samples.samples.into_iter().map(|s| cpal::Sample::to_sample::<IntermediateSampleType>(cpal::Sample::to_float_sample(s))).collect(),
+        samples.metadata,
+    );
+    let resampled = resampler::resample(generic_samples, desired_sample_rate, ResampleQuality::Sinc);
 
-    let metadata = samples.metadata.clone();
-    let nb_channels = metadata.channels.clone() as usize;
-
-    let old_samples = samples.samples;
-    let mut new_samples = Vec::new();
-
-    while index < old_samples.len() {
-        if time == 0.0 || time.is_sign_negative() {
-            time += old_samples_per_second;
+    let mut new_samples = resampled.into_t_samples::<T>();
+    new_samples.metadata.sample_type = sample_type;
 
-            index += nb_channels;
-        }
+    new_samples
+}
 
-        if time.is_sign_positive() {
-            time -= new_samples_per_second;
-            
-            for i in index..(index + nb_channels) {
-                let sample = match old_samples.get(i) {
-                    Some(s) => *s,
-                    None => <T as cpal::Sample>::EQUILIBRIUM,
-                };
-
-                new_samples.push(sample);
-            }
-        }
-    }
+/// Implements `ModifierTrait::modify` with `ModifierTrait::process_block` for modifiers that keep memory between blocks.
+/// The samples go through a reset copy of the modifier as a single block, so the result does not depend on what it played before
+pub fn modify_with_process_block<M: ModifierTrait + Clone>(modifier: &M, mut samples: Samples<IntermediateSampleType>) -> Samples<IntermediateSampleType> {
+    let mut modifier = modifier.clone();
+    modifier.reset();
 
-    let mut new_metadata = metadata;
-    new_metadata.sample_rate = desired_sample_rate;
+    let metadata = samples.metadata.clone();
+    modifier.process_block(&mut samples.samples, &metadata);
 
-    Samples::new(new_samples, new_metadata)
-}
\ No newline at end of file
+    samples
+}
@@ -0,0 +1,515 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use crate::{Device, Error, PlayError, traits::{AudioFileTrait, AudioMetadataTrait}, cpal_abstraction};
+
+use cpal_abstraction::{Samples, SamplesMetadata, SampleType, IntermediateSampleType, StreamSource};
+
+use super::{PlayerEvent, handle_stream_errors};
+use super::mixer::into_output_layout;
+use super::crossfade::{Crossfade, CrossfadeState};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// What the `QueuePlayer` does once it reaches the end of a track
+pub enum RepeatMode {
+    /// Stops at the end of the queue
+    Off,
+    /// Plays the current track over and over
+    One,
+    /// Goes back to the start of the queue once it ends
+    All,
+}
+
+/// A decoded track ready to be played
+struct QueuedTrack {
+    /// Position of the track in the play order of the queue
+    order_position: usize,
+    samples: Samples<IntermediateSampleType>,
+}
+
+/// The state shared between the `QueuePlayer` and its stream
+struct QueueState {
+    channels: usize,
+    current: Option<QueuedTrack>,
+    /// Index of the next sample to be played in the current track
+    index: usize,
+    /// The track that is played as soon as the current one is over
+    next: Option<QueuedTrack>,
+    /// Position in the play order of the last track that ended, used to know where to continue
+    last_played: Option<usize>,
+    crossfade: Option<Crossfade>,
+    /// The previous track fading out, if there is a crossfade in progress
+    fade: Option<CrossfadeState>,
+}
+
+impl QueueState {
+    /// Starts fading out the current track if it is close enough to its end
+    fn start_crossfade_if_needed(&mut self) {
+        let crossfade = match &self.crossfade {
+            Some(c) => *c,
+            None => return,
+        };
+
+        if self.fade.is_some() || self.next.is_none() {
+            return
+        }
+
+        let remaining_frames = match &self.current {
+            Some(c) => c.samples.samples.len().saturating_sub(self.index) / self.channels,
+            None => return,
+        };
+
+        if remaining_frames > crossfade.frames(self.sample_rate()) {
+            return
+        }
+
+        if let Some(current) = self.current.take() {
+            self.last_played = Some(current.order_position);
+            self.fade = Some(CrossfadeState::new(current.samples, self.index, &crossfade));
+        }
+        self.current = self.next.take();
+        self.index = 0;
+    }
+
+    fn sample_rate(&self) -> u32 {
+        match &self.current {
+            Some(c) => c.samples.metadata.sample_rate,
+            None => 0,
+        }
+    }
+
+    /// Immediately switches to the track, fading out the current one if there is a crossfade
+    fn switch_to(&mut self, track: QueuedTrack) {
+        if let (Some(crossfade), Some(current)) = (&self.crossfade, self.current.take()) {
+            self.fade = Some(CrossfadeState::new(current.samples, self.index, crossfade));
+        }
+
+        self.current = Some(track);
+        self.index = 0;
+    }
+}
+
+impl StreamSource for QueueState {
+    fn fill_buffer(&mut self, buffer: &mut [IntermediateSampleType]) {
+        let channels = self.channels;
+
+        for frame in buffer.chunks_mut(channels) {
+            self.start_crossfade_if_needed();
+
+            if let Some(current) = &self.current {
+                for (i, sample) in frame.iter_mut().enumerate() {
+                    *sample = current.samples.samples.get(self.index + i).copied().unwrap_or(0.0);
+                }
+                self.index += channels;
+            }
+
+            if let Some(fade) = &mut self.fade {
+                if !fade.mix_frame(frame) {
+                    self.fade = None;
+                }
+            }
+
+            // Switches to the next track on the next frame so that there is no gap
+            let is_current_over = match &self.current {
+                Some(c) => self.index >= c.samples.samples.len(),
+                None => false,
+            };
+            if is_current_over {
+                self.last_played = self.current.as_ref().map(|c| c.order_position);
+                self.current = self.next.take();
+                self.index = 0;
+            }
+        }
+    }
+}
+
+/// Plays a queue of audio files one after the other without gaps, on a single output stream.
+/// The next track is decoded ahead of time so that the transition happens on the exact sample
+/// the previous track ends.
+///
+/// A `Crossfade` can be set to overlap the end of a track with the start of the next one.
+///
+/// Decoding happens on the thread owning the player, call `update` regularly (every frame of a game
+/// loop for example) so that the next track is always ready when the current one ends
+pub struct QueuePlayer {
+    metadata: SamplesMetadata,
+    tracks: Vec<Box<dyn AudioFileTrait>>,
+    /// The order in which the tracks are played, contains indexes to `tracks`
+    order: Vec<usize>,
+    shuffle: bool,
+    repeat: RepeatMode,
+    /// Used to shuffle the queue, there is no need for a good random generator
+    random_state: u64,
+    state: Arc<Mutex<QueueState>>,
+    stream: Option<cpal_abstraction::Stream>,
+}
+
+impl QueuePlayer {
+    /// Creates a new empty `QueuePlayer` outputing with the channel count and sample rate specified,
+    /// tracks are converted to them when decoded
+    pub fn new(channels: u16, sample_rate: u32) -> QueuePlayer {
+        // Without channels there would be no frames to play
+        let channels = channels.max(1);
+        let state = QueueState {
+            channels: channels as usize,
+            current: None,
+            index: 0,
+            next: None,
+            last_played: None,
+            crossfade: None,
+            fade: None,
+        };
+
+        let random_state = RandomState::new().build_hasher().finish() | 1;
+
+        QueuePlayer {
+            metadata: SamplesMetadata::new(channels, sample_rate, SampleType::F32),
+            tracks: Vec::new(),
+            order: Vec::new(),
+            shuffle: false,
+            repeat: RepeatMode::Off,
+            random_state,
+            state: Arc::new(Mutex::new(state)),
+            stream: None,
+        }
+    }
+
+    /// Returns the metadata of the output of the player
+    pub fn metadata(&self) -> Box<dyn AudioMetadataTrait> {
+        Box::new(self.metadata.clone())
+    }
+
+    fn lock_state(&self) -> Error<MutexGuard<'_, QueueState>> {
+        self.state.lock()
+            .map_err(|e| PlayError::PoisonedMutex("queue state".to_string(), e.to_string().into()))
+    }
+
+    /// Decodes the track at the position in the play order
+    fn decode(&self, order_position: usize) -> Error<QueuedTrack> {
+        let track = &self.tracks[self.order[order_position]];
+        let samples = track.get_samples()?.generic_representation_samples();
+
+        Ok(QueuedTrack {
+            order_position,
+            samples: into_output_layout(samples, &self.metadata),
+        })
+    }
+
+    /// The position in the play order that should be played after the one given when the track ends
+    fn order_position_after(&self, order_position: usize) -> Option<usize> {
+        match self.repeat {
+            RepeatMode::One => Some(order_position),
+            _ if order_position + 1 < self.order.len() => Some(order_position + 1),
+            RepeatMode::All if !self.order.is_empty() => Some(0),
+            _ => None,
+        }
+    }
+
+    /// Adds a track at the end of the queue. If nothing is playing, the track becomes the current track
+    pub fn push(&mut self, track: Box<dyn AudioFileTrait>) -> Error<()> {
+        self.tracks.push(track);
+        self.order.push(self.tracks.len() - 1);
+
+        self.update()
+    }
+
+    /// Returns the number of tracks in the queue
+    pub fn len(&self) -> usize {
+        self.tracks.len()
+    }
+
+    /// Returns true if there are no tracks in the queue
+    pub fn is_empty(&self) -> bool {
+        self.tracks.is_empty()
+    }
+
+    /// Removes all the tracks from the queue, stopping the current one
+    pub fn clear(&mut self) -> Error<()> {
+        self.tracks.clear();
+        self.order.clear();
+
+        let mut state = self.lock_state()?;
+        state.current = None;
+        state.next = None;
+        state.index = 0;
+        state.last_played = None;
+        state.fade = None;
+
+        Ok(())
+    }
+
+    /// Returns the index (in the order they were pushed) of the track currently playing
+    pub fn current_track(&self) -> Error<Option<usize>> {
+        let state = self.lock_state()?;
+
+        Ok(state.current.as_ref().map(|c| self.order[c.order_position]))
+    }
+
+    /// Makes sure that the track after the current one is decoded and ready to play.
+    /// Should be called regularly, see the documentation of `QueuePlayer`
+    pub fn update(&mut self) -> Error<()> {
+        let (current_position, has_next, last_played) = {
+            let state = self.lock_state()?;
+            (state.current.as_ref().map(|c| c.order_position), state.next.is_some(), state.last_played)
+        };
+
+        match (current_position, last_played) {
+            (Some(_), _) if has_next => (),
+            (Some(position), _) => {
+                let next = match self.order_position_after(position) {
+                    Some(p) => Some(self.decode(p)?),
+                    None => None,
+                };
+                self.lock_state()?.next = next;
+            },
+            // The queue ended, but tracks may have been pushed since
+            (None, Some(last)) => {
+                if let Some(p) = self.order_position_after(last) {
+                    self.jump_to_order_position(p)?;
+                }
+            },
+            // Nothing was ever played, starts from the top of the queue
+            (None, None) if !self.order.is_empty() => self.jump_to_order_position(0)?,
+            (None, None) => (),
+        }
+
+        Ok(())
+    }
+
+    /// Immediately plays the track at that position in the play order
+    fn jump_to_order_position(&mut self, order_position: usize) -> Error<()> {
+        let current = self.decode(order_position)?;
+        let next = match self.order_position_after(order_position) {
+            Some(p) => Some(self.decode(p)?),
+            None => None,
+        };
+
+        let mut state = self.lock_state()?;
+        state.switch_to(current);
+        state.next = next;
+
+        Ok(())
+    }
+
+    /// Immediately plays the track at that index (in the order they were pushed)
+    pub fn play_track(&mut self, index: usize) -> Error<()> {
+        let order_position = match self.order.iter().position(|i| *i == index) {
+            Some(p) => p,
+            None => return Err(PlayError::TrackDoesNotExist(index)),
+        };
+
+        self.jump_to_order_position(order_position)
+    }
+
+    /// Skips to the next track, wraps to the start of the queue if repeating.
+    /// Stops the playing if there is no next track
+    pub fn next_track(&mut self) -> Error<()> {
+        let current_position = self.lock_state()?.current.as_ref().map(|c| c.order_position);
+
+        let next_position = match current_position {
+            Some(p) if p + 1 < self.order.len() => Some(p + 1),
+            Some(_) if self.repeat != RepeatMode::Off => Some(0),
+            _ => None,
+        };
+
+        match next_position {
+            Some(p) => self.jump_to_order_position(p),
+            None => {
+                let mut state = self.lock_state()?;
+                state.last_played = current_position;
+                state.current = None;
+                state.next = None;
+                Ok(())
+            }
+        }
+    }
+
+    /// Goes back to the previous track, wraps to the end of the queue if repeating.
+    /// Restarts the current track if it is the first one
+    pub fn previous_track(&mut self) -> Error<()> {
+        let current_position = self.lock_state()?.current.as_ref().map(|c| c.order_position);
+
+        let previous_position = match current_position {
+            Some(0) if self.repeat != RepeatMode::Off => self.order.len() - 1,
+            Some(p) => p.saturating_sub(1),
+            None if !self.order.is_empty() => self.order.len() - 1,
+            None => return Ok(()),
+        };
+
+        self.jump_to_order_position(previous_position)
+    }
+
+    /// Sets what happens at the end of a track
+    pub fn set_repeat(&mut self, repeat: RepeatMode) -> Error<()> {
+        self.repeat = repeat;
+
+        // The next track may not be the same anymore
+        self.lock_state()?.next = None;
+        self.update()
+    }
+
+    /// Returns what happens at the end of a track
+    pub fn repeat(&self) -> RepeatMode {
+        self.repeat
+    }
+
+    /// Sets the crossfade used between tracks, both when a track ends and when skipping to another one.
+    /// None makes the tracks follow each other directly
+    pub fn set_crossfade(&mut self, crossfade: Option<Crossfade>) -> Error<()> {
+        self.lock_state()?.crossfade = crossfade;
+
+        Ok(())
+    }
+
+    /// Shuffles the play order, the current track continues playing and the others are played in a random order after it.
+    /// Turning shuffle off goes back to the order the tracks were pushed in
+    pub fn set_shuffle(&mut self, shuffle: bool) -> Error<()> {
+        self.shuffle = shuffle;
+
+        let current_track = self.current_track()?;
+
+        self.order = (0..self.tracks.len()).collect();
+        if shuffle {
+            // Fisher-Yates with a xorshift generator
+            for i in (1..self.order.len()).rev() {
+                self.random_state ^= self.random_state << 13;
+                self.random_state ^= self.random_state >> 7;
+                self.random_state ^= self.random_state << 17;
+
+                let j = (self.random_state % (i as u64 + 1)) as usize;
+                self.order.swap(i, j);
+            }
+
+            if let Some(track) = current_track {
+                let position = self.order.iter().position(|i| *i == track).unwrap_or(0);
+                self.order.remove(position);
+                self.order.insert(0, track);
+            }
+        }
+
+        // The current track keeps playing, but its position in the order changed
+        let mut state = self.lock_state()?;
+        if let (Some(current), Some(track)) = (state.current.as_mut(), current_track) {
+            current.order_position = self.order.iter().position(|i| *i == track).unwrap_or(0);
+        }
+        state.next = None;
+        drop(state);
+
+        self.update()
+    }
+
+    /// Returns true if the play order is shuffled
+    pub fn shuffle(&self) -> bool {
+        self.shuffle
+    }
+
+    /// Starts/Continues the playing
+    pub fn start(&self) -> Error<()> {
+        let stream = match &self.stream {
+            Some(s) => s,
+            None => return Ok(()), // No stream to start
+        };
+
+        stream.start()
+    }
+
+    /// Stops the playing
+    pub fn stop(&self) -> Error<()> {
+        let stream = match &self.stream {
+            Some(s) => s,
+            None => return Ok(()), // No stream to stop
+        };
+
+        stream.stop()
+    }
+
+    /// Returns the errors the stream ran into since the last call, see `Stream::take_errors`
+    pub fn take_stream_errors(&self) -> Vec<PlayError> {
+        match &self.stream {
+            Some(s) => s.take_errors(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Returns the latency of the stream, see `Stream::latency`. None if not playing or if it is not known yet
+    pub fn latency(&self) -> Option<Duration> {
+        self.stream.as_ref().and_then(|s| s.latency())
+    }
+
+    /// Checks what happened to the stream since the last call, call it regularly.
+    /// If the device was lost and failover is on, the queue is moved to the new default output
+    pub fn poll_events(&mut self, failover: bool) -> Vec<PlayerEvent> {
+        let errors = self.take_stream_errors();
+
+        handle_stream_errors(errors, failover, |device| self.play_on_device(device))
+    }
+
+    /// Starts playing the queue on a device
+    pub fn play_on_device(&mut self, device: Device) -> Error<()> {
+        self.update()?;
+
+        let stream = device.create_source_stream(&self.metadata, Arc::clone(&self.state))?;
+
+        // Makes sure that the stream is started
+        stream.start()?;
+
+        self.stream = Some(stream);
+
+        Ok(())
+    }
+
+    /// Starts playing the queue on the default device of the default host
+    pub fn play_on_default(&mut self) -> Error<()> {
+        let default_output = match Device::default_output() {
+            Some(o) => o,
+            None => return Err(PlayError::DeviceDoesNotExist { name : "default".to_string() }),
+        };
+
+        self.play_on_device(default_output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wav::WavAudio;
+
+    fn wav_track() -> Box<dyn AudioFileTrait> {
+        Box::new(WavAudio::build_from_path("test_assets/u8-stereo-lpcm.wav").unwrap())
+    }
+
+    #[test]
+    fn transitions_without_gap() {
+        let track = wav_track();
+        let metadata = track.metadata();
+        let mut queue = QueuePlayer::new(metadata.channels() as u16, metadata.sample_rate());
+        queue.push(track).unwrap();
+        queue.push(wav_track()).unwrap();
+
+        let track_samples = wav_track().get_samples().unwrap().generic_representation_samples().samples;
+        let length = track_samples.len();
+
+        let mut buffer = vec![0.0; length + 64];
+        queue.state.lock().unwrap().fill_buffer(&mut buffer);
+
+        assert_eq!(&buffer[length..], &track_samples[..64]);
+        assert_eq!(queue.current_track().unwrap(), Some(1));
+    }
+
+    #[test]
+    fn repeat_all_wraps_around() {
+        let mut queue = QueuePlayer::new(2, 48000);
+        queue.push(wav_track()).unwrap();
+        queue.push(wav_track()).unwrap();
+        queue.set_repeat(RepeatMode::All).unwrap();
+
+        queue.next_track().unwrap();
+        assert_eq!(queue.current_track().unwrap(), Some(1));
+        queue.next_track().unwrap();
+        assert_eq!(queue.current_track().unwrap(), Some(0));
+        queue.previous_track().unwrap();
+        assert_eq!(queue.current_track().unwrap(), Some(1));
+    }
+}
@@ -0,0 +1,352 @@
+//! Changes the sample rate of samples, either all at once with `resample` or
+//! block by block with a `Resampler` when the samples arrive over time (in a stream for example)
+
+use std::f64::consts::PI;
+
+use crate::cpal_abstraction::{Samples, IntermediateSampleType};
+
+/// Zero crossings of the sinc on each side of the interpolated frame, more is sharper but slower
+const SINC_ZERO_CROSSINGS: usize = 16;
+/// Number of fractional positions the sinc kernel is precomputed at, positions in between are interpolated
+const SINC_PHASES: usize = 256;
+/// Keeps the kernel from getting huge when downsampling by a large factor
+const MAX_SINC_HALF_TAPS: usize = 256;
+/// Where the anti-aliasing filter starts, as a fraction of the lowest of the two nyquist frequencies
+const SINC_CUTOFF: f64 = 0.95;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// How the samples in between the original ones are computed, the better the quality the slower it is
+pub enum ResampleQuality {
+    /// Draws a line between two frames, cheap but dulls the highs and lets aliasing through
+    Linear,
+    /// Catmull-Rom spline through four frames, smoother than linear for nearly no cost
+    Cubic,
+    /// Windowed sinc with a polyphase table, filters out what cannot be represented at the new sample rate
+    #[default]
+    Sinc,
+}
+
+#[derive(Debug, Clone)]
+/// The function used to compute a frame from the frames around it
+enum Kernel {
+    Linear,
+    Cubic,
+    Sinc {
+        half_taps: usize,
+        /// `SINC_PHASES + 1` rows of `2 * half_taps` coefficients
+        table: Vec<f32>,
+    },
+}
+
+impl Kernel {
+    fn new(quality: ResampleQuality, input_rate: u32, output_rate: u32) -> Kernel {
+        match quality {
+            ResampleQuality::Linear => Kernel::Linear,
+            ResampleQuality::Cubic => Kernel::Cubic,
+            ResampleQuality::Sinc => Kernel::new_sinc(input_rate, output_rate),
+        }
+    }
+
+    fn new_sinc(input_rate: u32, output_rate: u32) -> Kernel {
+        // When downsampling the cutoff moves down to the new nyquist frequency so it does not alias
+        let cutoff = SINC_CUTOFF * (output_rate as f64 / input_rate.max(1) as f64).min(1.0);
+        let half_taps = ((SINC_ZERO_CROSSINGS as f64 / cutoff).ceil() as usize).clamp(1, MAX_SINC_HALF_TAPS);
+        let taps = 2 * half_taps;
+
+        let mut table = Vec::with_capacity((SINC_PHASES + 1) * taps);
+        for phase in 0..=SINC_PHASES {
+            let fraction = phase as f64 / SINC_PHASES as f64;
+
+            let row_start = table.len();
+            for tap in 0..taps {
+                // Distance in frames between the tap and the interpolated position
+                let x = tap as f64 - (half_taps - 1) as f64 - fraction;
+                table.push((cutoff * sinc(cutoff * x) * blackman(x / half_taps as f64)) as f32);
+            }
+
+            // Normalizes so that a constant signal keeps its level
+            let sum: f32 = table[row_start..].iter().sum();
+            if sum != 0.0 {
+                table[row_start..].iter_mut().for_each(|c| *c /= sum);
+            }
+        }
+
+        Kernel::Sinc { half_taps, table }
+    }
+
+    /// Number of frames needed on each side of the interpolated position
+    fn half_taps(&self) -> usize {
+        match self {
+            Kernel::Linear => 1,
+            Kernel::Cubic => 2,
+            Kernel::Sinc { half_taps, .. } => *half_taps,
+        }
+    }
+
+    /// Computes the frame at `frame + fraction` in the interleaved frames, frames outside of them are silent
+    fn render_frame(&self, frames: &[IntermediateSampleType], channels: usize, frame: i64, fraction: f64, output: &mut [IntermediateSampleType]) {
+        let half_taps = self.half_taps() as i64;
+        let frame_count = (frames.len() / channels) as i64;
+        let sample_at = |f: i64, c: usize| -> IntermediateSampleType {
+            if f < 0 || f >= frame_count {
+                0.0
+            } else {
+                frames[f as usize * channels + c]
+            }
+        };
+
+        match self {
+            Kernel::Linear => {
+                let fraction = fraction as IntermediateSampleType;
+                for (c, sample) in output.iter_mut().enumerate() {
+                    let current = sample_at(frame, c);
+                    *sample = current + (sample_at(frame + 1, c) - current) * fraction;
+                }
+            },
+            Kernel::Cubic => {
+                let t = fraction as IntermediateSampleType;
+                for (c, sample) in output.iter_mut().enumerate() {
+                    let (p0, p1, p2, p3) = (sample_at(frame - 1, c), sample_at(frame, c), sample_at(frame + 1, c), sample_at(frame + 2, c));
+
+                    *sample = p1 + 0.5 * t * (p2 - p0
+                        + t * (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3
+                        + t * (3.0 * (p1 - p2) + p3 - p0)));
+                }
+            },
+            Kernel::Sinc { table, .. } => {
+                let taps = 2 * half_taps as usize;
+
+                // Interpolates between the two closest precomputed phases
+                let phase_position = fraction * SINC_PHASES as f64;
+                let phase = (phase_position as usize).min(SINC_PHASES - 1);
+                let phase_fraction = (phase_position - phase as f64) as f32;
+                let row = &table[(phase * taps)..((phase + 1) * taps)];
+                let next_row = &table[((phase + 1) * taps)..((phase + 2) * taps)];
+
+                let first_frame = frame - (half_taps - 1);
+                output.iter_mut().for_each(|s| *s = 0.0);
+
+                for tap in 0..taps {
+                    let coefficient = row[tap] + (next_row[tap] - row[tap]) * phase_fraction;
+                    let f = first_frame + tap as i64;
+
+                    if f >= 0 && f < frame_count {
+                        let frame_samples = &frames[(f as usize * channels)..((f as usize + 1) * channels)];
+                        for (sample, input) in output.iter_mut().zip(frame_samples) {
+                            *sample += input * coefficient;
+                        }
+                    }
+                }
+            },
+        }
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-9 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+/// Blackman window, x goes from -1.0 to 1.0
+fn blackman(x: f64) -> f64 {
+    if x.abs() >= 1.0 {
+        return 0.0
+    }
+
+    let phase = PI * (x + 1.0);
+    0.42 - 0.5 * phase.cos() + 0.08 * (2.0 * phase).cos()
+}
+
+/// Keeps the position of the next output frame as an exact fraction of input frames
+/// so that no error accumulates however long the resampling lasts
+#[derive(Debug, Clone)]
+struct Position {
+    frame: i64,
+    /// Numerator of the fractional part, the denominator is the output sample rate
+    remainder: u64,
+    input_rate: u64,
+    output_rate: u64,
+}
+
+impl Position {
+    fn new(input_rate: u32, output_rate: u32) -> Position {
+        Position {
+            frame: 0,
+            remainder: 0,
+            input_rate: input_rate.max(1) as u64,
+            output_rate: output_rate.max(1) as u64,
+        }
+    }
+
+    fn fraction(&self) -> f64 {
+        self.remainder as f64 / self.output_rate as f64
+    }
+
+    fn advance(&mut self) {
+        self.remainder += self.input_rate;
+        self.frame += (self.remainder / self.output_rate) as i64;
+        self.remainder %= self.output_rate;
+    }
+}
+
+/// Resamples all of the samples at once, changes the metadata to match
+pub fn resample(samples: Samples<IntermediateSampleType>, sample_rate: u32, quality: ResampleQuality) -> Samples<IntermediateSampleType> {
+    let mut metadata = samples.metadata.clone();
+    if metadata.sample_rate == sample_rate || sample_rate == 0 {
+        return samples
+    }
+
+    let channels = metadata.channels.max(1) as usize;
+    let input_frames = samples.samples.len() / channels;
+    let output_frames = (input_frames as u128 * sample_rate as u128 / metadata.sample_rate.max(1) as u128) as usize;
+
+    let kernel = Kernel::new(quality, metadata.sample_rate, sample_rate);
+    let mut position = Position::new(metadata.sample_rate, sample_rate);
+
+    let mut new_samples = vec![0.0; output_frames * channels];
+    for frame in new_samples.chunks_mut(channels) {
+        kernel.render_frame(&samples.samples, channels, position.frame, position.fraction(), frame);
+        position.advance();
+    }
+
+    metadata.sample_rate = sample_rate;
+    Samples::new(new_samples, metadata)
+}
+
+#[derive(Debug, Clone)]
+/// Resamples interleaved samples that arrive block by block, keeps what it needs from the previous blocks
+/// so that there is no discontinuity between them
+pub struct Resampler {
+    channels: usize,
+    kernel: Kernel,
+    position: Position,
+    /// Input frames that are still needed, interleaved. Starts with silence so that the first frame
+    /// has frames before it to interpolate with
+    history: Vec<IntermediateSampleType>,
+    /// The frame being rendered, kept so that `process` does not allocate in the audio thread
+    frame: Vec<IntermediateSampleType>,
+}
+
+impl Resampler {
+    /// Creates a new Resampler going from the input sample rate to the output sample rate
+    pub fn new(channels: u16, input_sample_rate: u32, output_sample_rate: u32, quality: ResampleQuality) -> Resampler {
+        let kernel = Kernel::new(quality, input_sample_rate, output_sample_rate);
+        let channels = channels.max(1) as usize;
+
+        let mut resampler = Resampler {
+            channels,
+            kernel,
+            position: Position::new(input_sample_rate, output_sample_rate),
+            history: Vec::new(),
+            frame: vec![0.0; channels],
+        };
+        resampler.reset();
+
+        resampler
+    }
+
+    /// Forgets the previous blocks, as if the resampler was just created
+    pub fn reset(&mut self) {
+        let half_taps = self.kernel.half_taps();
+
+        self.history.clear();
+        self.history.resize((half_taps - 1) * self.channels, 0.0);
+        self.position.frame = (half_taps - 1) as i64;
+        self.position.remainder = 0;
+    }
+
+    /// Number of input frames that need to come after a frame before it can be output
+    pub fn latency(&self) -> usize {
+        self.kernel.half_taps()
+    }
+
+    /// Resamples the block and appends the result to the output.
+    /// Frames that need future input to be computed are kept until the next call
+    pub fn process(&mut self, input: &[IntermediateSampleType], output: &mut Vec<IntermediateSampleType>) {
+        self.history.extend_from_slice(input);
+
+        let half_taps = self.kernel.half_taps() as i64;
+        let available_frames = (self.history.len() / self.channels) as i64;
+
+        while self.position.frame + half_taps < available_frames {
+            self.kernel.render_frame(&self.history, self.channels, self.position.frame, self.position.fraction(), &mut self.frame);
+            output.extend_from_slice(&self.frame);
+            self.position.advance();
+        }
+
+        // Forgets the frames that are too far behind to be used again
+        let first_needed = (self.position.frame - (half_taps - 1)).clamp(0, available_frames);
+        self.history.drain(..(first_needed as usize * self.channels));
+        self.position.frame -= first_needed;
+    }
+
+    /// Outputs the frames that were waiting on future input as if the input ended with silence, then resets
+    pub fn flush(&mut self, output: &mut Vec<IntermediateSampleType>) {
+        let silence = vec![0.0; self.latency() * self.channels];
+        self.process(&silence, output);
+        self.reset();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpal_abstraction::{SamplesMetadata, SampleType};
+
+    fn sine(frequency: f64, sample_rate: u32, frames: usize) -> Vec<f32> {
+        (0..frames)
+            .map(|i| (2.0 * PI * frequency * i as f64 / sample_rate as f64).sin() as f32)
+            .collect()
+    }
+
+    fn peak(samples: &[f32]) -> f32 {
+        samples.iter().fold(0.0, |p, s| s.abs().max(p))
+    }
+
+    #[test]
+    fn every_quality_follows_a_low_sine() {
+        let input = Samples::new(sine(100.0, 8000, 800), SamplesMetadata::new(1, 8000, SampleType::F32));
+        let expected = sine(100.0, 12000, 1200);
+
+        for quality in [ResampleQuality::Linear, ResampleQuality::Cubic, ResampleQuality::Sinc] {
+            let output = resample(input.clone(), 12000, quality);
+            assert_eq!(output.metadata.sample_rate, 12000);
+            assert_eq!(output.samples.len(), 1200);
+
+            // The edges are tapered by the silence around the samples
+            for (s, e) in output.samples.iter().zip(&expected).skip(100).take(1000) {
+                assert!((s - e).abs() < 0.01, "{quality:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn sinc_filters_out_what_would_alias() {
+        // 7kHz cannot be represented at 8kHz and would fold back to 1kHz
+        let input = Samples::new(sine(7000.0, 48000, 4800), SamplesMetadata::new(1, 48000, SampleType::F32));
+        let output = resample(input, 8000, ResampleQuality::Sinc);
+
+        assert!(peak(&output.samples[100..700]) < 0.01);
+    }
+
+    #[test]
+    fn streaming_matches_offline() {
+        let samples = sine(440.0, 44100, 2000);
+        let offline = resample(Samples::new(samples.clone(), SamplesMetadata::new(1, 44100, SampleType::F32)), 48000, ResampleQuality::Sinc);
+
+        let mut resampler = Resampler::new(1, 44100, 48000, ResampleQuality::Sinc);
+        let mut streamed = Vec::new();
+        for block in samples.chunks(123) {
+            resampler.process(block, &mut streamed);
+        }
+        resampler.flush(&mut streamed);
+
+        for (s, o) in streamed.iter().zip(&offline.samples) {
+            assert!((s - o).abs() < 0.0001);
+        }
+        assert!(streamed.len() >= offline.samples.len());
+    }
+}
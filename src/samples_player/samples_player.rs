@@ -1,17 +1,83 @@
 use std::sync::{Mutex, MutexGuard, Arc};
+use std::time::Duration;
 
-use crate::{Device, traits::AudioMetadataTrait, cpal_abstraction, Error, modifiers::ModifierTrait};
+use crate::{Device, traits::AudioMetadataTrait, cpal_abstraction, Error, PlayError, modifiers::ModifierTrait};
 
-use cpal_abstraction::{Sample, Samples, SamplesTrait, IntermediateSampleType};
+use cpal_abstraction::{Sample, Samples, SamplesTrait, IntermediateSampleType, StreamSource};
 
 use super::SamplesPlayerTrait;
+use super::crossfade::{Crossfade, CrossfadeState};
+use super::mixer::into_output_layout;
+use super::speed::{SpeedMode, TimeStretcher, interpolated_sample};
+
+/// The state shared between the `SamplesPlayer` and its stream
+struct PlaybackState {
+    samples: Samples<IntermediateSampleType>,
+    /// Position of the playhead in frames (one sample per channel), fractional because of the speed
+    position: f64,
+    speed: f64,
+    speed_mode: SpeedMode,
+    stretcher: TimeStretcher,
+    /// The previous samples fading out, if there is a crossfade in progress
+    fade: Option<CrossfadeState>,
+}
+
+impl PlaybackState {
+    fn new(samples: Samples<IntermediateSampleType>) -> PlaybackState {
+        let stretcher = TimeStretcher::new(samples.metadata.sample_rate, 0.0);
+
+        PlaybackState {
+            samples,
+            position: 0.0,
+            speed: 1.0,
+            speed_mode: SpeedMode::Varispeed,
+            stretcher,
+            fade: None,
+        }
+    }
+
+    fn channels(&self) -> usize {
+        self.samples.metadata.channels.max(1) as usize
+    }
+
+    /// Returns the index of the sample at the playhead
+    fn index(&self) -> usize {
+        self.position as usize * self.channels()
+    }
+}
+
+impl StreamSource for PlaybackState {
+    fn fill_buffer(&mut self, buffer: &mut [IntermediateSampleType]) {
+        let channels = self.channels();
+
+        for frame in buffer.chunks_mut(channels) {
+            match self.speed_mode {
+                SpeedMode::Varispeed => {
+                    for (c, sample) in frame.iter_mut().enumerate() {
+                        *sample = interpolated_sample(&self.samples, self.position, c);
+                    }
+                    self.position += self.speed;
+                },
+                SpeedMode::TimeStretch => {
+                    self.stretcher.render_frame(&self.samples, &mut self.position, self.speed, frame);
+                },
+            }
+
+            if let Some(fade) = &mut self.fade {
+                if !fade.mix_frame(frame) {
+                    self.fade = None;
+                }
+            }
+        }
+    }
+}
 
 /// Manages the applying of modifiers and the sending of samples to audio streams, **transforms the original sample into IntermediateSampleType which is much more efficient**.
 /// Go see ExactSamplesPlayer to send the exact sample type of the original sample to the audio streams.
 pub struct SamplesPlayer {
     original_samples: Samples<IntermediateSampleType>,
     modifiers: Vec<Box<dyn ModifierTrait>>,
-    samples_with_modifiers: Option<Arc<Mutex<Samples<IntermediateSampleType>>>>,
+    playback: Option<Arc<Mutex<PlaybackState>>>,
     stream: Option<cpal_abstraction::Stream>,
 }
 
@@ -22,18 +88,18 @@ impl SamplesPlayer {
         Self {
             original_samples: samples.into_generic_representation_samples(),
             modifiers: Vec::new(),
-            samples_with_modifiers: None,
+            playback: None,
             stream: None,
         }
     }
 
-    fn aquire_samples_with_modifiers_mutex_guard(&self) -> Option<MutexGuard<Samples<IntermediateSampleType>>> {
-        let modified_samples_mutex = match &self.samples_with_modifiers {
+    fn aquire_playback_mutex_guard(&self) -> Option<MutexGuard<'_, PlaybackState>> {
+        let playback_mutex = match &self.playback {
             Some(m) => m,
             None => return None,
         };
 
-        let mutex_lock = modified_samples_mutex.lock();
+        let mutex_lock = playback_mutex.lock();
         match mutex_lock {
             Ok(l) => Some(l),
             // TODO: Better error handling on that // Kinda? the Option enum signifies that
@@ -42,25 +108,31 @@ impl SamplesPlayer {
     }
 
     fn change_samples_with_modifiers(&mut self, samples: Samples<IntermediateSampleType>) {
-        let mutex_guard_option = self.aquire_samples_with_modifiers_mutex_guard();
+        let mutex_guard_option = self.aquire_playback_mutex_guard();
 
         if let Some(mut guard) = mutex_guard_option {
-            *guard = samples;
+            guard.samples = samples;
         } else {
             // Creates a Arc if there is none yet
             drop(mutex_guard_option);
-            self.samples_with_modifiers = Some(Arc::new(Mutex::new(samples)))
+            self.playback = Some(Arc::new(Mutex::new(PlaybackState::new(samples))))
         }
     }
 
-    /// Applies all the modifiers
-    fn apply_modifiers(&mut self) {
+    fn samples_with_modifiers(&self) -> Samples<IntermediateSampleType> {
         // Most of the time seems to be spent moving samples, cloning and transfering from one type to another
         let mut modified_samples = self.original_samples.clone();
         for modifier in &self.modifiers {
             modified_samples = modifier.modify(modified_samples);
         }
 
+        modified_samples
+    }
+
+    /// Applies all the modifiers
+    fn apply_modifiers(&mut self) {
+        let modified_samples = self.samples_with_modifiers();
+
         self.change_samples_with_modifiers(modified_samples);
     }
 
@@ -69,6 +141,47 @@ impl SamplesPlayer {
 
         self.stream = Some(stream);
     }
+
+    /// Plays the playback state on the device from its current position
+    fn play_playback(&mut self, device: Device) -> Error<()> {
+        let playback_arc = self.playback
+            .as_ref()
+            .expect("no playback state");
+        let playback_arc = Arc::clone(playback_arc);
+
+        let stream = device.create_source_stream(&self.original_samples.metadata,
+            playback_arc)?;
+
+        // Makes sure that the stream is started
+        stream.start()?;
+
+        self.set_stream(stream);
+
+        Ok(())
+    }
+
+    /// Replaces the samples being played, the modifiers are kept and applied to the new samples.
+    /// The new samples are played from their start, with a crossfade from the old ones if one is given.
+    /// The new samples are converted to the channel count and sample rate of the old ones
+    pub fn replace_samples<T: Sample>(&mut self, samples: Samples<T>, crossfade: Option<Crossfade>)
+    where IntermediateSampleType: cpal::FromSample<T> {
+        // The stream and the stretcher were made for the old layout
+        self.original_samples = into_output_layout(samples.into_generic_representation_samples(), &self.original_samples.metadata);
+        let modified_samples = self.samples_with_modifiers();
+
+        let mutex_guard_option = self.aquire_playback_mutex_guard();
+        if let Some(mut guard) = mutex_guard_option {
+            let old_index = guard.index();
+            let old_samples = std::mem::replace(&mut guard.samples, modified_samples);
+            guard.position = 0.0;
+            guard.stretcher.reset(0.0);
+
+            guard.fade = crossfade.map(|c| CrossfadeState::new(old_samples, old_index, &c));
+        } else {
+            drop(mutex_guard_option);
+            self.change_samples_with_modifiers(modified_samples);
+        }
+    }
 }
 
 impl SamplesPlayerTrait for SamplesPlayer {
@@ -106,23 +219,68 @@ impl SamplesPlayerTrait for SamplesPlayer {
         stream.stop()
     }
 
-    fn play_on_device(&mut self, device: Device) -> Error<()> {
-        // Makes sure that there is a Sample in self.samples_with_modifiers
-        self.apply_modifiers();
+    fn take_stream_errors(&self) -> Vec<PlayError> {
+        match &self.stream {
+            Some(s) => s.take_errors(),
+            None => Vec::new(),
+        }
+    }
 
-        let samples_arc = self.samples_with_modifiers
-            .as_ref()
-            .expect("no samples with modifiers");
-        let samples_arc = Arc::clone(samples_arc);
+    fn latency(&self) -> Option<Duration> {
+        self.stream.as_ref().and_then(|s| s.latency())
+    }
 
-        let stream = device.create_stream(&self.original_samples.metadata,
-            samples_arc)?;
+    fn set_speed(&mut self, speed: f32) -> Error<()> {
+        // Makes sure that there is a PlaybackState in self.playback
+        if self.playback.is_none() {
+            self.apply_modifiers();
+        }
 
-        // Makes sure that the stream is started
-        stream.start()?;
+        match self.aquire_playback_mutex_guard() {
+            Some(mut guard) => guard.speed = speed.max(0.0) as f64,
+            None => return Err(PlayError::PoisonedMutex("playback state".to_string(), "poisoned by the stream".into())),
+        }
 
-        self.set_stream(stream);
+        Ok(())
+    }
+
+    fn set_speed_mode(&mut self, mode: SpeedMode) -> Error<()> {
+        if self.playback.is_none() {
+            self.apply_modifiers();
+        }
+
+        match self.aquire_playback_mutex_guard() {
+            Some(mut guard) => {
+                let position = guard.position;
+                guard.stretcher.reset(position);
+                guard.speed_mode = mode;
+            },
+            None => return Err(PlayError::PoisonedMutex("playback state".to_string(), "poisoned by the stream".into())),
+        }
 
         Ok(())
     }
-}
\ No newline at end of file
+
+    fn play_on_device(&mut self, device: Device) -> Error<()> {
+        // Makes sure that there is a PlaybackState in self.playback
+        self.apply_modifiers();
+
+        match self.aquire_playback_mutex_guard() {
+            Some(mut guard) => {
+                guard.position = 0.0;
+                guard.stretcher.reset(0.0);
+                guard.fade = None;
+            },
+            None => return Err(PlayError::PoisonedMutex("playback state".to_string(), "poisoned by the stream".into())),
+        }
+
+        self.play_playback(device)
+    }
+
+    fn resume_on_device(&mut self, device: Device) -> Error<()> {
+        // Makes sure that there is a PlaybackState in self.playback, the position is kept
+        self.apply_modifiers();
+
+        self.play_playback(device)
+    }
+}
@@ -1,5 +1,9 @@
+use std::time::Duration;
+
 use crate::{Device, traits::AudioMetadataTrait, modifiers::ModifierTrait, errors::Error, PlayError};
 
+use super::{SpeedMode, PlayerEvent, handle_stream_errors};
+
 
 
 /// Trait that implements the functionality of the SamplesPlayer struct
@@ -19,9 +23,45 @@ pub trait SamplesPlayerTrait {
     /// Stops the playing
     fn stop(&self) -> Error<()>;
 
-    /// Starts playing on a device
+    /// Returns the errors the stream ran into since the last call, see `Stream::take_errors`
+    fn take_stream_errors(&self) -> Vec<PlayError> {
+        Vec::new()
+    }
+
+    /// Returns the latency of the stream, see `Stream::latency`. None if not playing or if it is not known yet
+    fn latency(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Checks what happened to the stream since the last call, call it regularly.
+    /// If the device was lost and failover is on, the player is moved to the new default output
+    /// and resumes where it was
+    fn poll_events(&mut self, failover: bool) -> Vec<PlayerEvent> {
+        let errors = self.take_stream_errors();
+
+        handle_stream_errors(errors, failover, |device| self.resume_on_device(device))
+    }
+
+    /// Sets the playback speed, 1.0 is the normal speed and 2.0 twice as fast.
+    /// The speed is applied while playing, see `set_speed_mode` for how it affects the pitch
+    fn set_speed(&mut self, _speed: f32) -> Error<()> {
+        Err(PlayError::Unsupported("changing the speed of this player".to_string()))
+    }
+
+    /// Sets how the speed affects the pitch, `SpeedMode::Varispeed` by default
+    fn set_speed_mode(&mut self, _mode: SpeedMode) -> Error<()> {
+        Err(PlayError::Unsupported("changing the speed mode of this player".to_string()))
+    }
+
+    /// Starts playing on a device, from the start of the samples
     fn play_on_device(&mut self, _device: Device) -> Error<()>;
 
+    /// Plays on a device from where the current stream is, used to fail over to a new device.
+    /// Players that can not resume start over with `play_on_device`
+    fn resume_on_device(&mut self, device: Device) -> Error<()> {
+        self.play_on_device(device)
+    }
+
     /// Starts playing on the default device of the default host
     fn play_on_default(&mut self) -> Error<()> {
         let default_output = match Device::default_output() {
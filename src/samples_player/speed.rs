@@ -0,0 +1,127 @@
+use std::f64::consts::PI;
+
+use crate::cpal_abstraction::{Samples, IntermediateSampleType};
+
+/// Duration of a grain of the time stretch, in seconds
+const GRAIN_DURATION: f64 = 0.04;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// How changing the playback speed affects the sound
+pub enum SpeedMode {
+    /// The pitch follows the speed, like a record played faster or slower
+    Varispeed,
+    /// The pitch stays the same, the audio is stretched by overlapping small grains of it
+    TimeStretch,
+}
+
+/// Linearly interpolates the sample of the channel at a fractional position in frames.
+/// Positions outside of the samples are silent
+pub(super) fn interpolated_sample(samples: &Samples<IntermediateSampleType>, position: f64, channel: usize) -> IntermediateSampleType {
+    if position < 0.0 {
+        return 0.0
+    }
+
+    let channels = samples.metadata.channels.max(1) as usize;
+    let frame = position as usize;
+    let fraction = (position - frame as f64) as IntermediateSampleType;
+
+    let samples = &samples.samples;
+    let current = samples.get(frame * channels + channel).copied().unwrap_or(0.0);
+    let next = samples.get((frame + 1) * channels + channel).copied().unwrap_or(0.0);
+
+    current + (next - current) * fraction
+}
+
+/// Changes the speed without changing the pitch in the audio thread by overlapping two grains read at the normal speed.
+/// A new grain is started from the playhead every half grain, so the playhead can move at any speed
+pub(super) struct TimeStretcher {
+    grain_frames: usize,
+    /// Position in the samples where the newest grain starts
+    newest_grain: f64,
+    /// Position in the samples where the grain before the newest one starts
+    older_grain: f64,
+    /// Frames played since the newest grain started
+    phase: usize,
+}
+
+impl TimeStretcher {
+    pub(super) fn new(sample_rate: u32, position: f64) -> TimeStretcher {
+        let grain_frames = ((sample_rate as f64 * GRAIN_DURATION) as usize).max(2) & !1;
+
+        TimeStretcher {
+            grain_frames,
+            newest_grain: position,
+            older_grain: position - (grain_frames / 2) as f64,
+            phase: 0,
+        }
+    }
+
+    /// Restarts the grains from the position, used when the playhead jumps
+    pub(super) fn reset(&mut self, position: f64) {
+        let hop = (self.grain_frames / 2) as f64;
+
+        self.newest_grain = position;
+        self.older_grain = position - hop;
+        self.phase = 0;
+    }
+
+    /// Writes the next frame and moves the playhead forward by the speed
+    pub(super) fn render_frame(&mut self, samples: &Samples<IntermediateSampleType>, position: &mut f64, speed: f64, frame: &mut [IntermediateSampleType]) {
+        let hop = self.grain_frames / 2;
+
+        // A Hann window at half overlap sums to one, so the older grain gets the complement of the newest
+        let window = (0.5 - 0.5 * (2.0 * PI * self.phase as f64 / self.grain_frames as f64).cos()) as IntermediateSampleType;
+
+        let newest_position = self.newest_grain + self.phase as f64;
+        let older_position = self.older_grain + (self.phase + hop) as f64;
+        for (c, sample) in frame.iter_mut().enumerate() {
+            *sample = interpolated_sample(samples, newest_position, c) * window
+                + interpolated_sample(samples, older_position, c) * (1.0 - window);
+        }
+
+        *position += speed;
+        self.phase += 1;
+
+        if self.phase >= hop {
+            self.older_grain = self.newest_grain;
+            self.newest_grain = *position;
+            self.phase = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpal_abstraction::{SamplesMetadata, SampleType};
+
+    fn ramp() -> Samples<IntermediateSampleType> {
+        let samples = (0..4800).map(|i| i as f32 / 4800.0).collect();
+        Samples::new(samples, SamplesMetadata::new(1, 4800, SampleType::F32))
+    }
+
+    #[test]
+    fn time_stretch_at_normal_speed_is_transparent() {
+        let samples = ramp();
+        let mut stretcher = TimeStretcher::new(4800, 0.0);
+        let mut position = 0.0;
+
+        for i in 0..1000 {
+            let mut frame = [0.0];
+            stretcher.render_frame(&samples, &mut position, 1.0, &mut frame);
+            assert!((frame[0] - samples.samples[i]).abs() < 0.0001);
+        }
+    }
+
+    #[test]
+    fn time_stretch_moves_playhead_with_speed() {
+        let samples = ramp();
+        let mut stretcher = TimeStretcher::new(4800, 0.0);
+        let mut position = 0.0;
+
+        for _ in 0..1000 {
+            stretcher.render_frame(&samples, &mut position, 2.0, &mut [0.0]);
+        }
+        assert_eq!(position, 2000.0);
+    }
+}
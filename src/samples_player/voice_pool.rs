@@ -0,0 +1,469 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use crate::{Device, Error, PlayError, traits::AudioMetadataTrait, cpal_abstraction, modifiers::utils};
+
+use cpal_abstraction::{Sample, Samples, SamplesTrait, SamplesMetadata, SampleType, IntermediateSampleType, StreamSource};
+
+use super::{PlayerEvent, handle_stream_errors};
+use super::mixer::{soft_limit, DEFAULT_LIMITER_THRESHOLD};
+use super::speed::interpolated_sample;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+/// Identifies a voice (one playing instance of a sound) inside of a `VoicePool`
+pub struct VoiceId(u64);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// What the `VoicePool` does when a sound is triggered while all the voices are in use
+pub enum VoiceStealing {
+    /// Stops the voice that was triggered first
+    Oldest,
+    /// Stops the voice with the lowest volume
+    Quietest,
+    /// Does not play the new sound
+    Refuse,
+}
+
+/// One playing instance of a sound
+struct Voice {
+    id: VoiceId,
+    sound: Arc<Samples<IntermediateSampleType>>,
+    /// Position in frames (one sample per channel) inside of the sound, fractional because of the pitch
+    position: f64,
+    /// How many frames of the sound are consumed for each frame of output
+    step: f64,
+    volume: IntermediateSampleType,
+    pitch: f64,
+}
+
+impl Voice {
+    fn frame_count(&self) -> usize {
+        self.sound.samples.len() / self.sound.metadata.channels.max(1) as usize
+    }
+
+    fn is_finished(&self) -> bool {
+        self.position as usize >= self.frame_count()
+    }
+
+    fn sample_at_position(&self, channel: usize) -> IntermediateSampleType {
+        interpolated_sample(&self.sound, self.position, channel)
+    }
+}
+
+/// The state shared between the `VoicePool` and its stream
+struct VoicePoolState {
+    channels: usize,
+    sample_rate: u32,
+    voices: Vec<Voice>,
+    max_voices: usize,
+    stealing: VoiceStealing,
+    master_volume: IntermediateSampleType,
+}
+
+impl VoicePoolState {
+    fn voice_mut(&mut self, id: VoiceId) -> Option<&mut Voice> {
+        self.voices.iter_mut().find(|v| v.id == id)
+    }
+
+    /// Stops one voice chosen by the policy, returns false if no voice was stopped
+    fn steal_voice(&mut self, stealing: VoiceStealing) -> bool {
+        let stolen = match stealing {
+            VoiceStealing::Oldest => self.voices.iter()
+                .enumerate()
+                .min_by_key(|(_, v)| v.id)
+                .map(|(i, _)| i),
+            VoiceStealing::Quietest => self.voices.iter()
+                .enumerate()
+                .min_by(|(_, a), (_, b)| a.volume.abs().total_cmp(&b.volume.abs()))
+                .map(|(i, _)| i),
+            VoiceStealing::Refuse => None,
+        };
+
+        match stolen {
+            Some(i) => {
+                self.voices.remove(i);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Makes room for a new voice, returns false if there is no room to be made
+    fn make_room(&mut self) -> bool {
+        if self.max_voices == 0 {
+            return false
+        }
+
+        while self.voices.len() >= self.max_voices {
+            if !self.steal_voice(self.stealing) {
+                return false
+            }
+        }
+
+        true
+    }
+
+    fn step_for(&self, sound: &Samples<IntermediateSampleType>, pitch: f64) -> f64 {
+        pitch * sound.metadata.sample_rate as f64 / self.sample_rate as f64
+    }
+}
+
+impl StreamSource for VoicePoolState {
+    fn fill_buffer(&mut self, buffer: &mut [IntermediateSampleType]) {
+        let channels = self.channels;
+
+        for voice in self.voices.iter_mut() {
+            for frame in buffer.chunks_mut(channels) {
+                if voice.is_finished() {
+                    break;
+                }
+
+                for (c, sample_out) in frame.iter_mut().enumerate() {
+                    *sample_out += voice.sample_at_position(c) * voice.volume;
+                }
+
+                voice.position += voice.step;
+            }
+        }
+
+        // The voices are freed once they are done playing
+        self.voices.retain(|v| !v.is_finished());
+
+        for sample in buffer.iter_mut() {
+            *sample = soft_limit(*sample * self.master_volume, DEFAULT_LIMITER_THRESHOLD);
+        }
+    }
+}
+
+/// A voice whose pitch is 0 or less never reaches the end of its sound, it would hold its slot forever
+fn check_pitch(pitch: f64) -> Error<()> {
+    if !pitch.is_finite() || pitch <= 0.0 {
+        return Err(PlayError::InvalidPitch(pitch))
+    }
+
+    Ok(())
+}
+
+/// A pool of voices to play preloaded sounds many times at once, made for sound effects.
+/// Each trigger of a sound creates a new voice with its own volume and pitch, when all the voices are used
+/// one is stolen according to the `VoiceStealing` of the pool.
+pub struct VoicePool {
+    metadata: SamplesMetadata,
+    sounds: HashMap<String, Arc<Samples<IntermediateSampleType>>>,
+    state: Arc<Mutex<VoicePoolState>>,
+    next_id: u64,
+    stream: Option<cpal_abstraction::Stream>,
+}
+
+impl VoicePool {
+    /// Creates a new `VoicePool` outputing with the channel count and sample rate specified,
+    /// that can play up to `max_voices` sounds at once
+    pub fn new(channels: u16, sample_rate: u32, max_voices: usize) -> VoicePool {
+        // Without channels there would be no frames to play
+        let channels = channels.max(1);
+        let state = VoicePoolState {
+            channels: channels as usize,
+            sample_rate,
+            voices: Vec::with_capacity(max_voices),
+            max_voices,
+            stealing: VoiceStealing::Oldest,
+            master_volume: 1.0,
+        };
+
+        VoicePool {
+            metadata: SamplesMetadata::new(channels, sample_rate, SampleType::F32),
+            sounds: HashMap::new(),
+            state: Arc::new(Mutex::new(state)),
+            next_id: 0,
+            stream: None,
+        }
+    }
+
+    /// Returns the metadata of the output of the pool
+    pub fn metadata(&self) -> Box<dyn AudioMetadataTrait> {
+        Box::new(self.metadata.clone())
+    }
+
+    fn lock_state(&self) -> Error<MutexGuard<'_, VoicePoolState>> {
+        self.state.lock()
+            .map_err(|e| PlayError::PoisonedMutex("voice pool state".to_string(), e.to_string().into()))
+    }
+
+    /// Loads a sound under a name so that it can be triggered, replaces the sound already under that name.
+    /// The sound is converted to the channel count of the pool, its sample rate is kept and
+    /// accounted for when playing
+    pub fn load_sound<T: Sample>(&mut self, name: &str, samples: Samples<T>)
+    where IntermediateSampleType: cpal::FromSample<T> {
+        let mut samples = samples.into_generic_representation_samples();
+        if samples.metadata.channels != self.metadata.channels {
+            samples = utils::into_n_channels(samples, self.metadata.channels);
+        }
+
+        self.sounds.insert(name.to_string(), Arc::new(samples));
+    }
+
+    /// Unloads a sound, voices already playing it continue until they are done.
+    /// Returns false if there was no sound under that name
+    pub fn unload_sound(&mut self, name: &str) -> bool {
+        self.sounds.remove(name).is_some()
+    }
+
+    /// Returns true if a sound is loaded under that name
+    pub fn has_sound(&self, name: &str) -> bool {
+        self.sounds.contains_key(name)
+    }
+
+    /// Plays a new instance of the sound with its own volume and pitch (1.0 is the original pitch, 2.0 an octave higher).
+    /// Returns None if the voice could not be played because of the `VoiceStealing::Refuse` policy.
+    /// Fails if the pitch is not above 0, the voice would never end
+    pub fn trigger(&mut self, name: &str, volume: IntermediateSampleType, pitch: f64) -> Error<Option<VoiceId>> {
+        check_pitch(pitch)?;
+        let sound = match self.sounds.get(name) {
+            Some(s) => Arc::clone(s),
+            None => return Err(PlayError::SoundDoesNotExist(name.to_string())),
+        };
+
+        let id = VoiceId(self.next_id);
+        let mut state = self.lock_state()?;
+
+        if !state.make_room() {
+            return Ok(None)
+        }
+
+        let voice = Voice {
+            id,
+            step: state.step_for(&sound, pitch),
+            sound,
+            position: 0.0,
+            volume,
+            pitch,
+        };
+        state.voices.push(voice);
+        drop(state);
+
+        self.next_id += 1;
+
+        Ok(Some(id))
+    }
+
+    /// Stops a voice before it is done playing
+    pub fn stop_voice(&mut self, id: VoiceId) -> Error<()> {
+        let mut state = self.lock_state()?;
+        let voice_count = state.voices.len();
+        state.voices.retain(|v| v.id != id);
+
+        if state.voices.len() == voice_count {
+            return Err(PlayError::VoiceDoesNotExist(id))
+        }
+
+        Ok(())
+    }
+
+    /// Stops all the voices
+    pub fn stop_all_voices(&mut self) -> Error<()> {
+        self.lock_state()?.voices.clear();
+
+        Ok(())
+    }
+
+    /// Returns true if the voice is still playing
+    pub fn is_voice_playing(&self, id: VoiceId) -> Error<bool> {
+        Ok(self.lock_state()?.voices.iter().any(|v| v.id == id))
+    }
+
+    /// Returns the number of voices currently playing
+    pub fn active_voices(&self) -> Error<usize> {
+        Ok(self.lock_state()?.voices.len())
+    }
+
+    /// Sets the volume of a voice that is playing
+    pub fn set_voice_volume(&mut self, id: VoiceId, volume: IntermediateSampleType) -> Error<()> {
+        let mut state = self.lock_state()?;
+        let voice = state.voice_mut(id).ok_or(PlayError::VoiceDoesNotExist(id))?;
+        voice.volume = volume;
+
+        Ok(())
+    }
+
+    /// Sets the pitch of a voice that is playing, fails if it is not above 0
+    pub fn set_voice_pitch(&mut self, id: VoiceId, pitch: f64) -> Error<()> {
+        check_pitch(pitch)?;
+        let mut state = self.lock_state()?;
+        let sound = match state.voices.iter().find(|v| v.id == id) {
+            Some(v) => Arc::clone(&v.sound),
+            None => return Err(PlayError::VoiceDoesNotExist(id)),
+        };
+        let step = state.step_for(&sound, pitch);
+
+        let voice = state.voice_mut(id).ok_or(PlayError::VoiceDoesNotExist(id))?;
+        voice.pitch = pitch;
+        voice.step = step;
+
+        Ok(())
+    }
+
+    /// Returns the pitch of a voice that is playing
+    pub fn voice_pitch(&self, id: VoiceId) -> Error<f64> {
+        let state = self.lock_state()?;
+        let voice = state.voices.iter().find(|v| v.id == id).ok_or(PlayError::VoiceDoesNotExist(id))?;
+
+        Ok(voice.pitch)
+    }
+
+    /// Sets the maximum number of voices that can play at once, voices over the limit are stolen right away
+    pub fn set_max_voices(&mut self, max_voices: usize) -> Error<()> {
+        let mut state = self.lock_state()?;
+        state.max_voices = max_voices;
+
+        // Voices over the limit are stolen even if the pool refuses to steal new voices
+        let stealing = match state.stealing {
+            VoiceStealing::Refuse => VoiceStealing::Oldest,
+            s => s,
+        };
+        while state.voices.len() > max_voices {
+            state.steal_voice(stealing);
+        }
+
+        Ok(())
+    }
+
+    /// Sets what happens when a sound is triggered while all the voices are used
+    pub fn set_stealing(&mut self, stealing: VoiceStealing) -> Error<()> {
+        self.lock_state()?.stealing = stealing;
+
+        Ok(())
+    }
+
+    /// Sets the volume applied to all voices
+    pub fn set_master_volume(&mut self, volume: IntermediateSampleType) -> Error<()> {
+        self.lock_state()?.master_volume = volume;
+
+        Ok(())
+    }
+
+    /// Starts/Continues the playing
+    pub fn start(&self) -> Error<()> {
+        let stream = match &self.stream {
+            Some(s) => s,
+            None => return Ok(()), // No stream to start
+        };
+
+        stream.start()
+    }
+
+    /// Stops the playing
+    pub fn stop(&self) -> Error<()> {
+        let stream = match &self.stream {
+            Some(s) => s,
+            None => return Ok(()), // No stream to stop
+        };
+
+        stream.stop()
+    }
+
+    /// Returns the errors the stream ran into since the last call, see `Stream::take_errors`
+    pub fn take_stream_errors(&self) -> Vec<PlayError> {
+        match &self.stream {
+            Some(s) => s.take_errors(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Returns the latency of the stream, see `Stream::latency`. None if not playing or if it is not known yet
+    pub fn latency(&self) -> Option<Duration> {
+        self.stream.as_ref().and_then(|s| s.latency())
+    }
+
+    /// Checks what happened to the stream since the last call, call it regularly.
+    /// If the device was lost and failover is on, the pool is moved to the new default output
+    pub fn poll_events(&mut self, failover: bool) -> Vec<PlayerEvent> {
+        let errors = self.take_stream_errors();
+
+        handle_stream_errors(errors, failover, |device| self.play_on_device(device))
+    }
+
+    /// Starts playing the voices on a device
+    pub fn play_on_device(&mut self, device: Device) -> Error<()> {
+        let stream = device.create_source_stream(&self.metadata, Arc::clone(&self.state))?;
+
+        // Makes sure that the stream is started
+        stream.start()?;
+
+        self.stream = Some(stream);
+
+        Ok(())
+    }
+
+    /// Starts playing the voices on the default device of the default host
+    pub fn play_on_default(&mut self) -> Error<()> {
+        let default_output = match Device::default_output() {
+            Some(o) => o,
+            None => return Err(PlayError::DeviceDoesNotExist { name : "default".to_string() }),
+        };
+
+        self.play_on_device(default_output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool_with_beep(max_voices: usize) -> VoicePool {
+        let mut pool = VoicePool::new(1, 48000, max_voices);
+        let beep = Samples::new(vec![0.5; 480], SamplesMetadata::new(1, 48000, SampleType::F32));
+        pool.load_sound("beep", beep);
+
+        pool
+    }
+
+    #[test]
+    fn oldest_voice_is_stolen() {
+        let mut pool = pool_with_beep(2);
+        let first = pool.trigger("beep", 1.0, 1.0).unwrap().unwrap();
+        let second = pool.trigger("beep", 1.0, 1.0).unwrap().unwrap();
+        let third = pool.trigger("beep", 1.0, 1.0).unwrap().unwrap();
+
+        assert!(!pool.is_voice_playing(first).unwrap());
+        assert!(pool.is_voice_playing(second).unwrap());
+        assert!(pool.is_voice_playing(third).unwrap());
+    }
+
+    #[test]
+    fn quietest_voice_is_stolen() {
+        let mut pool = pool_with_beep(2);
+        pool.set_stealing(VoiceStealing::Quietest).unwrap();
+        let loud = pool.trigger("beep", 1.0, 1.0).unwrap().unwrap();
+        let quiet = pool.trigger("beep", 0.1, 1.0).unwrap().unwrap();
+        pool.trigger("beep", 1.0, 1.0).unwrap().unwrap();
+
+        assert!(pool.is_voice_playing(loud).unwrap());
+        assert!(!pool.is_voice_playing(quiet).unwrap());
+    }
+
+    #[test]
+    fn pitch_changes_voice_length() {
+        let mut pool = pool_with_beep(4);
+        pool.trigger("beep", 1.0, 2.0).unwrap().unwrap();
+
+        let mut buffer = vec![0.0; 480];
+        pool.state.lock().unwrap().fill_buffer(&mut buffer);
+
+        assert!(buffer[200] > 0.0);
+        assert_eq!(buffer[300], 0.0);
+        assert_eq!(pool.active_voices().unwrap(), 0);
+    }
+
+    #[test]
+    fn pitch_must_be_above_zero() {
+        let mut pool = pool_with_beep(4);
+
+        assert!(matches!(pool.trigger("beep", 1.0, 0.0), Err(PlayError::InvalidPitch(_))));
+        assert!(pool.trigger("beep", 1.0, f64::NAN).is_err());
+
+        let id = pool.trigger("beep", 1.0, 1.0).unwrap().unwrap();
+        assert!(pool.set_voice_pitch(id, -1.0).is_err());
+        assert_eq!(pool.voice_pitch(id).unwrap(), 1.0);
+    }
+}
@@ -1,4 +1,6 @@
 mod wav_audio;
 pub use wav_audio::*;
+mod wav_writer;
+pub use wav_writer::*;
 pub mod utils;
 pub use utils::*;
\ No newline at end of file
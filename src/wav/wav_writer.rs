@@ -0,0 +1,213 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write, Seek, SeekFrom};
+
+use crate::cpal_abstraction::{SamplesMetadata, SampleType, Samples, IntermediateSampleType, StreamSink};
+use crate::cpal_abstraction::Sample as CrateSample;
+use crate::errors::{Error, PlayError};
+use cpal::Sample;
+
+/// Size of the header written by the WavWriter, the data starts right after
+const HEADER_SIZE: u32 = 44;
+/// Bytes buffered by `WavWriter::create` before they are written to the file, about 3 seconds of 48kHz stereo f32
+const FILE_BUFFER_SIZE: usize = 1 << 20;
+
+/// Writes samples into a WAVE file as they come, the sizes in the header are written when finished
+pub struct WavWriter<W: Write + Seek> {
+    writer: W,
+    metadata: SamplesMetadata,
+    /// Number of bytes of samples written so far
+    data_size: u32,
+    /// The converted samples, kept between writes so that recording does not allocate
+    bytes: Vec<u8>,
+    /// First error that happened while used as a `StreamSink`, where it can't be returned
+    sink_error: Option<io::Error>,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    /// Creates a new WavWriter and writes the header.
+    /// The samples are stored in the sample type of the metadata, which can be U8, I16, I32 (LPcm) or F32 (IEEE float)
+    pub fn new(mut writer: W, metadata: SamplesMetadata) -> Error<WavWriter<W>> {
+        bits_per_sample(&metadata.sample_type)?;
+
+        writer.seek(SeekFrom::Start(0))?;
+        write_header(&mut writer, &metadata, 0)?;
+
+        Ok(WavWriter {
+            writer,
+            metadata,
+            data_size: 0,
+            bytes: Vec::new(),
+            sink_error: None,
+        })
+    }
+
+    /// Returns the metadata of the written samples
+    pub fn metadata(&self) -> &SamplesMetadata {
+        &self.metadata
+    }
+
+    /// Appends interleaved samples to the file, they are converted to the sample type of the metadata
+    pub fn write_samples(&mut self, samples: &[IntermediateSampleType]) -> Error<()> {
+        let bytes = &mut self.bytes;
+        bytes.clear();
+        for sample in samples {
+            match self.metadata.sample_type {
+                SampleType::U8 => bytes.push(sample.to_sample::<u8>()),
+                SampleType::I16 => bytes.extend_from_slice(&sample.to_sample::<i16>().to_le_bytes()),
+                SampleType::I32 => bytes.extend_from_slice(&sample.to_sample::<i32>().to_le_bytes()),
+                _ => bytes.extend_from_slice(&sample.to_le_bytes()),
+            }
+        }
+
+        // The sizes in the header can't go past 4 GiB
+        let data_size = u32::try_from(bytes.len()).ok()
+            .and_then(|len| self.data_size.checked_add(len))
+            .filter(|size| riff_size(*size).is_some())
+            .ok_or_else(too_large)?;
+
+        self.writer.write_all(bytes)?;
+        self.data_size = data_size;
+
+        Ok(())
+    }
+
+    /// Appends the samples to the file, they need to have the same channel count
+    pub fn write<T: CrateSample>(&mut self, samples: &Samples<T>) -> Error<()>
+    where IntermediateSampleType: cpal::FromSample<T> {
+        if samples.metadata.channels != self.metadata.channels {
+            return Err(PlayError::Unsupported("writing samples with another channel count than the WavWriter".to_string()))
+        }
+
+        let generic_samples = samples.samples.iter()
+            .map(|s| s.to_sample::<IntermediateSampleType>())
+            .collect::<Vec<_>>();
+
+        self.write_samples(&generic_samples)
+    }
+
+    /// Writes the final sizes into the header and gives back the writer
+    pub fn finish(mut self) -> Error<W> {
+        if let Some(e) = self.sink_error.take() {
+            return Err(e.into())
+        }
+
+        // A pad byte keeps the chunk aligned on an even number of bytes
+        if self.data_size % 2 == 1 {
+            self.writer.write_all(&[0])?;
+        }
+
+        self.writer.seek(SeekFrom::Start(0))?;
+        write_header(&mut self.writer, &self.metadata, self.data_size)?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()?;
+
+        Ok(self.writer)
+    }
+}
+
+impl WavWriter<BufWriter<File>> {
+    /// Creates the file (or truncates it) and a WavWriter writing into it.
+    /// The file is written in large chunks so that recording into it rarely waits on the disk
+    pub fn create(path: &str, metadata: SamplesMetadata) -> Error<WavWriter<BufWriter<File>>> {
+        let file = File::create(path)?;
+
+        WavWriter::new(BufWriter::with_capacity(FILE_BUFFER_SIZE, file), metadata)
+    }
+}
+
+impl<W: Write + Seek + Send + 'static> StreamSink for WavWriter<W> {
+    /// Writes the captured samples, errors are returned by `finish`
+    fn consume_buffer(&mut self, buffer: &[IntermediateSampleType]) {
+        if self.sink_error.is_some() {
+            return
+        }
+
+        if let Err(PlayError::FileNotAccessible(e)) = self.write_samples(buffer) {
+            self.sink_error = Some(e);
+        }
+    }
+}
+
+fn bits_per_sample(sample_type: &SampleType) -> Error<u16> {
+    match sample_type {
+        SampleType::U8 => Ok(8),
+        SampleType::I16 => Ok(16),
+        SampleType::I32 | SampleType::F32 => Ok(32),
+        t => Err(PlayError::Unsupported(format!("writing {t:?} samples in a WAVE file"))),
+    }
+}
+
+/// Returns the size written in the RIFF chunk, if it fits
+fn riff_size(data_size: u32) -> Option<u32> {
+    data_size.checked_add(data_size % 2)?.checked_add(HEADER_SIZE - 8)
+}
+
+fn too_large() -> PlayError {
+    PlayError::FileNotAccessible(io::Error::new(io::ErrorKind::FileTooLarge, "WAVE files can't hold more than 4 GiB"))
+}
+
+fn write_header(writer: &mut impl Write, metadata: &SamplesMetadata, data_size: u32) -> Error<()> {
+    let bits_per_sample = bits_per_sample(&metadata.sample_type)?;
+    let block_align = metadata.channels * bits_per_sample / 8;
+    let byte_rate = metadata.sample_rate * block_align as u32;
+    // 1 is LPcm and 3 is IEEE float
+    let audio_format: u16 = if metadata.sample_type == SampleType::F32 { 3 } else { 1 };
+    let riff_size = riff_size(data_size).ok_or_else(too_large)?;
+
+    let mut header = Vec::with_capacity(HEADER_SIZE as usize);
+    header.extend_from_slice(b"RIFF");
+    header.extend_from_slice(&riff_size.to_le_bytes());
+    header.extend_from_slice(b"WAVE");
+
+    header.extend_from_slice(b"fmt ");
+    header.extend_from_slice(&16u32.to_le_bytes());
+    header.extend_from_slice(&audio_format.to_le_bytes());
+    header.extend_from_slice(&metadata.channels.to_le_bytes());
+    header.extend_from_slice(&metadata.sample_rate.to_le_bytes());
+    header.extend_from_slice(&byte_rate.to_le_bytes());
+    header.extend_from_slice(&block_align.to_le_bytes());
+    header.extend_from_slice(&bits_per_sample.to_le_bytes());
+
+    header.extend_from_slice(b"data");
+    header.extend_from_slice(&data_size.to_le_bytes());
+
+    writer.write_all(&header)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::wav::WavAudioMetadata;
+
+    #[test]
+    fn writes_a_readable_header() {
+        let metadata = SamplesMetadata::new(2, 8000, SampleType::I16);
+        let mut writer = WavWriter::new(Cursor::new(Vec::new()), metadata).unwrap();
+        writer.write_samples(&[0.0, 0.5, -0.5, 1.0]).unwrap();
+        let bytes = writer.finish().unwrap().into_inner();
+
+        assert_eq!(bytes.len(), 44 + 8);
+        assert_eq!(&bytes[40..44], &8u32.to_le_bytes());
+        assert_eq!(&bytes[46..48], &16384i16.to_le_bytes());
+
+        let read_metadata = WavAudioMetadata::build_from_reader(Cursor::new(bytes)).unwrap();
+        assert_eq!(read_metadata.channels(), 2);
+        assert_eq!(read_metadata.sample_rate(), 8000);
+        assert_eq!(read_metadata.sample_type(), SampleType::I16);
+    }
+
+    #[test]
+    fn stops_at_the_riff_size_limit() {
+        let metadata = SamplesMetadata::new(1, 8000, SampleType::I16);
+        let mut writer = WavWriter::new(Cursor::new(Vec::new()), metadata).unwrap();
+        writer.data_size = u32::MAX - 40;
+
+        assert!(matches!(writer.write_samples(&[0.0, 0.5]), Err(PlayError::FileNotAccessible(e)) if e.kind() == io::ErrorKind::FileTooLarge));
+        writer.write_samples(&[0.0]).unwrap();
+        assert_eq!(writer.data_size, u32::MAX - 38);
+    }
+}